@@ -0,0 +1,14 @@
+//! Convert between zfec share files and sharefiles (same as "ida convert")
+//!
+
+use guff_sharefiles::cli::*;
+
+use clap::App;
+
+fn main() {
+
+    let app = convert_args(App::new("ida-convert")
+			   .version(VERSION)
+			   .author(AUTHOR));
+    finish(run_convert(&get_matches(app)));
+}
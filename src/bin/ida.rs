@@ -0,0 +1,14 @@
+//! Split, combine, inspect, check and repair IDA share files
+//!
+
+use guff_sharefiles::cli::*;
+
+fn main() {
+
+    // With cargo run, remember to use --bin AND --. For example:
+    //
+    // cargo run --bin ida -- split -r foo
+
+    let matches = get_matches(ida_app());
+    finish(run_ida(&matches));
+}
@@ -0,0 +1,1125 @@
+// Command-line handling shared by the ida tool and the older
+// single-purpose ida-* binaries
+//
+// Each subcommand has a function that adds its arguments to an App
+// and another that runs it from the resulting matches. The ida binary
+// puts them all together as subcommands, while ida-split and friends
+// use one each.
+
+use clap::{Arg, App, AppSettings, ArgMatches, SubCommand};
+use std::fs::File;
+
+use crate::*;
+use crate::codec::*;
+use crate::split::*;
+use crate::combine::*;
+use crate::discover::*;
+use crate::error::*;
+use crate::inspect::*;
+use crate::json::*;
+use crate::manifest::*;
+use crate::output::*;
+use crate::naming::*;
+use crate::rewrite::*;
+use crate::selftest::*;
+use crate::store::*;
+use crate::tune::*;
+use crate::zfec::*;
+
+pub const VERSION : &str = "1.0";
+pub const AUTHOR  : &str = "Declan Malone <idablack@users.sourceforge.net>";
+
+// Options common to several subcommands
+
+fn kernel_args<'a, 'b>(app : App<'a, 'b>) -> App<'a, 'b> {
+    app.args_from_usage(
+	"-r                   'Use reference matrix mul'
+	 -s                   'Use SIMD matrix mul (default, if the CPU can)'
+	 -v, --verbose        'Say which matrix mul is used'
+	 --field=[poly]       'Field polynomial (default 0x11b)'
+	 -b, --bufsize=[cols] 'Columns to process at a time'")
+}
+
+// Picking the buffer size by timing a few (see tune.rs)
+fn tune_args<'a, 'b>(app : App<'a, 'b>) -> App<'a, 'b> {
+    app.args(&[
+	Arg::from_usage("--auto-tune          'Time some buffer sizes on a sample of the input and use the fastest'")
+	    .conflicts_with("bufsize"),
+	Arg::from_usage("--save-tuning        'Make the --auto-tune choice the default for this k and n'")
+	    .requires("auto-tune"),
+    ])
+}
+
+// Buffer sizes saved by --save-tuning. A broken config file shouldn't
+// stop anything working, so it's only warned about.
+fn tune_config() -> TuneConfig {
+    match config_path().map(|path| TuneConfig::load(&path)) {
+	Some(Ok(config)) => config,
+	Some(Err(e)) => { eprintln!("Ignoring config file: {}", e); TuneConfig::new() },
+	None => TuneConfig::new(),
+    }
+}
+
+// Report the timings, save the choice if asked to and return it
+fn tuned(matches : &ArgMatches, what : TuneFor, timings : &[Timing])
+	 -> IdaResult<usize> {
+    let bufsize = fastest(timings);
+    for timing in timings.iter() {
+	eprintln!("{:>8} columns: {:8.1} MB/s{}", timing.bufsize,
+		  timing.rate / 1e6,
+		  if timing.bufsize == bufsize { " (fastest)" } else { "" });
+    }
+    if matches.is_present("save-tuning") {
+	let path = config_path().ok_or_else(|| usage(
+	    "Nowhere to save the config file; set IDA_CONFIG or HOME"))?;
+	let mut config = TuneConfig::load(&path)?;
+	config.set_bufsize(what, bufsize);
+	config.save(&path)?;
+	eprintln!("Saved buffer size {} to {}", bufsize, path.display());
+    }
+    Ok(bufsize)
+}
+
+// Buffer size for combining k shares: -b, else timed, else saved,
+// else the default. Without shares to look at, there's no k.
+fn combine_bufsize(matches : &ArgMatches, manifest : Option<&Manifest>,
+		   opts : &CombineOptions) -> IdaResult<usize> {
+    if let Some(bufsize) = matches.value_of("bufsize") {
+	return parse_number("bufsize", bufsize)
+    }
+    // only regular files, since looking at a pipe would use it up
+    let mut shares : Vec<&str> = matches.values_of("INFILE")
+	.map_or(Vec::new(), |files| files.collect());
+    if let Some(manifest) = manifest {
+	shares.extend(manifest.shares.iter().map(|s| &s.name[..]));
+    }
+    let mut shares = shares.into_iter()
+	.filter(|file| std::fs::metadata(file).is_ok_and(|m| m.is_file()));
+    if matches.is_present("auto-tune") {
+	let (header, sample) = shares
+	    .find_map(|file| read_share_sample(file).ok())
+	    .ok_or_else(|| usage(
+		"--auto-tune needs a share file (or a manifest) to sample"))?;
+	eprintln!("Timing buffer sizes for combining {} shares", header.k);
+	let timings = tune_combine(&sample, header.k, opts);
+	return tuned(matches, TuneFor::Combine { k : header.k }, &timings)
+    }
+    let k = manifest.map(|m| m.k).or_else(|| {
+	shares.find_map(|file| probe_share(file).ok()).map(|(_, h, _)| h.k)
+    });
+    Ok(k.and_then(|k| tune_config().bufsize(TuneFor::Combine { k }))
+       .unwrap_or(opts.bufsize))
+}
+
+// Say which multiply is being used with -v, or if -s can't be honoured
+fn report_kernel(matches : &ArgMatches, poly : u16) {
+    let (kernel, why) = choose_kernel(matches.is_present("r"), poly);
+    if matches.is_present("verbose") {
+	eprintln!("Matrix mul: {}", why);
+    } else if matches.is_present("s") && kernel == Kernel::Reference {
+	eprintln!("Can't use SIMD matrix mul; using {}", why);
+    }
+}
+
+fn mmap_arg<'a, 'b>(app : App<'a, 'b>) -> App<'a, 'b> {
+    app.args_from_usage(
+	"--mmap               'Map input and output files into memory where possible'")
+}
+
+fn threads_arg<'a, 'b>(app : App<'a, 'b>) -> App<'a, 'b> {
+    app.args_from_usage(
+	"-t, --threads=[int]  'Most threads to multiply with (default: one per CPU)'")
+}
+
+fn scheme_args<'a, 'b>(app : App<'a, 'b>) -> App<'a, 'b> {
+    app.args_from_usage(
+	"-n=[int]             'number of shares (default 16)'
+	 --key=[list]         'k + n comma-separated key values (default 1,2,...)'")
+}
+
+fn json_arg<'a, 'b>(app : App<'a, 'b>) -> App<'a, 'b> {
+    app.args_from_usage(
+	"--json               'Print results as JSON on stdout'")
+}
+
+fn outdir_arg<'a, 'b>(app : App<'a, 'b>) -> App<'a, 'b> {
+    app.args_from_usage(
+	"-d, --outdir=[DIR]   'Directory to write output files to'")
+}
+
+// Where split and repair put their shares
+fn naming_args<'a, 'b>(app : App<'a, 'b>) -> App<'a, 'b> {
+    app.arg(Arg::from_usage(
+	"-d, --outdir=[DIR]   'Directory for shares (repeat to spread shares across directories)'")
+	    .multiple(true).number_of_values(1))
+	.args_from_usage(
+	    "--template=[T]       'Share name template, eg {name}.{index:02}.sf'")
+}
+
+fn manifest_arg<'a, 'b>(app : App<'a, 'b>, help : &'b str) -> App<'a, 'b> {
+    app.arg(Arg::with_name("manifest").long("manifest").value_name("FILE")
+	    .takes_value(true).help(help))
+}
+
+// Share names listed in a manifest, for subcommands run without any
+fn manifest_shares(opts : &CombineOptions) -> Vec<&str> {
+    opts.manifest.as_ref()
+	.map_or(Vec::new(), |m| m.shares.iter().map(|s| &s.name[..]).collect())
+}
+
+fn output_args<'a, 'b>(app : App<'a, 'b>) -> App<'a, 'b> {
+    app.args_from_usage(
+	"--force              'Overwrite existing output files'
+	 --sync               'Flush output files to disk before finishing'")
+}
+
+fn infiles_arg<'a, 'b>(app : App<'a, 'b>, multiple : bool) -> App<'a, 'b> {
+    app.arg(Arg::with_name("INFILE")
+	    .multiple(multiple)
+	    .help(if multiple { "Sets the input file(s) to use" }
+		  else { "Sets the input file to use" })
+	    .required(true)
+	    .index(1))
+}
+
+// Input files that can be left out if another argument is given
+fn infiles_unless_arg<'a, 'b>(app : App<'a, 'b>, other : &'a str) -> App<'a, 'b> {
+    app.arg(Arg::with_name("INFILE")
+	    .multiple(true)
+	    .help("Sets the input file(s) to use")
+	    .required_unless(other)
+	    .index(1))
+}
+
+// Numbers can be decimal or 0x-prefixed hex
+fn parse_number(name : &str, value : &str) -> IdaResult<usize> {
+    let parsed = if let Some(hex) = value.strip_prefix("0x") {
+	usize::from_str_radix(hex, 16)
+    } else {
+	value.parse()
+    };
+    parsed.map_err(|_| usage(format!("Invalid value '{}' for {}", value, name)))
+}
+
+fn number(matches : &ArgMatches, name : &str, default : usize)
+	  -> IdaResult<usize> {
+    match matches.value_of(name) {
+	Some(value) => parse_number(name, value),
+	None => Ok(default),
+    }
+}
+
+fn key_option(matches : &ArgMatches) -> IdaResult<Option<Vec<u8>>> {
+    let list = match matches.value_of("key") {
+	Some(list) => list,
+	None => return Ok(None),
+    };
+    let mut key = Vec::new();
+    for value in list.split(',') {
+	let x = parse_number("key", value.trim())?;
+	if x > 255 {
+	    return Err(usage(format!("Key value {} doesn't fit in a byte", x)))
+	}
+	key.push(x as u8);
+    }
+    Ok(Some(key))
+}
+
+fn poly_option(matches : &ArgMatches) -> IdaResult<u16> {
+    let poly = number(matches, "field", DEFAULT_POLY as usize)?;
+    if !(0x100..=0x1ff).contains(&poly) {
+	return Err(usage(format!("Field polynomial {:#x} is not of degree 8",
+				 poly)))
+    }
+    Ok(poly as u16)
+}
+
+fn template_option(matches : &ArgMatches) -> IdaResult<Option<NameTemplate>> {
+    match matches.value_of("template") {
+	Some(template) => Ok(Some(NameTemplate::parse(template)?)),
+	None => Ok(None),
+    }
+}
+
+fn outdirs_option(matches : &ArgMatches) -> Vec<String> {
+    matches.values_of("outdir")
+	.map_or(Vec::new(), |dirs| dirs.map(|d| d.to_string()).collect())
+}
+
+fn output_options(matches : &ArgMatches) -> OutputOptions {
+    OutputOptions {
+	force : matches.is_present("force"),
+	sync  : matches.is_present("sync"),
+    }
+}
+
+fn combine_options(matches : &ArgMatches) -> IdaResult<CombineOptions> {
+    let defaults = CombineOptions::default();
+    let manifest = match matches.value_of("manifest") {
+	Some(file) => Some(Manifest::load(file)?),
+	None => None,
+    };
+    // the manifest knows the field, unless --field says otherwise
+    let poly = match &manifest {
+	Some(manifest) if !matches.is_present("field") => manifest.poly,
+	_ => poly_option(matches)?,
+    };
+    let use_ref = matches.is_present("r");
+    report_kernel(matches, poly);
+    let bufsize = combine_bufsize(matches, manifest.as_ref(), &CombineOptions {
+	poly, use_ref, ..CombineOptions::default()
+    })?;
+    if bufsize == 0 {
+	return Err(usage("Buffer size must be at least one column"))
+    }
+    Ok(CombineOptions {
+	poly,
+	use_ref,
+	matrix  : match matches.value_of("matrix") {
+	    Some(file) => Some(read_matrix(file)?),
+	    None => None,
+	},
+	output  : output_options(matches),
+	bufsize,
+	threads : number(matches, "threads", defaults.threads)?,
+	mmap    : matches.is_present("mmap"),
+	manifest,
+    })
+}
+
+fn usage<S : Into<String>>(message : S) -> IdaError {
+    IdaError::new(ErrorClass::Usage, message)
+}
+
+// Like App::get_matches(), but exit with our usage error code for bad
+// arguments (--help and --version still exit with 0)
+pub fn get_matches<'a>(app : App<'a, '_>) -> ArgMatches<'a> {
+    app.get_matches_safe().unwrap_or_else(|e| {
+	if e.use_stderr() {
+	    eprintln!("{}", e.message);
+	    std::process::exit(ErrorClass::Usage.exit_code())
+	}
+	e.exit()
+    })
+}
+
+// Report an error and exit with the code for its class, or just
+// return
+pub fn finish(result : IdaResult<()>) {
+    if let Err(e) = result {
+	eprintln!("{}", e);
+	std::process::exit(e.exit_code())
+    }
+}
+
+// split
+
+pub fn split_args<'a, 'b>(app : App<'a, 'b>) -> App<'a, 'b> {
+    let app = app
+	.about("Rabin IDA split")
+	.args_from_usage(
+	    "-k=[int]             'quorum value (default 8)'
+	     -w=[int]             'field width in bytes (only 1 supported)'
+	     -p, --perl-compat    'Name shares as Crypt::IDA (Perl) does'
+	     --matrix=[FILE]      'Also save the transform matrix to FILE'
+	     --tar=[ARCHIVE]      'Put shares and a manifest in one tar archive (- for stdout)'")
+	.arg(Arg::from_usage(
+	    "--manifest=[FILE]    'Also write a manifest describing the split'")
+	     .conflicts_with("tar"))
+	.arg(Arg::from_usage(
+	    "-f, --whole          'Split the whole file as one block, in memory'")
+	     .conflicts_with_all(&["bufsize", "auto-tune", "threads"]));
+    let app = mmap_arg(tune_args(threads_arg(kernel_args(app))));
+    infiles_arg(json_arg(naming_args(output_args(scheme_args(app)))), false)
+}
+
+pub fn run_split(matches : &ArgMatches) -> IdaResult<()> {
+    let infile = matches.value_of("INFILE").unwrap();
+    let json = matches.is_present("json");
+    let tar = matches.value_of("tar");
+    let to_stdout = tar == Some("-");
+    if json && to_stdout {
+	return Err(usage("Can't print JSON when writing the archive to stdout"))
+    }
+    if !json && !to_stdout {
+	println!("Using input file: {}", infile);
+    }
+
+    let defaults = SplitOptions::default();
+    let mut opts = SplitOptions {
+	k : number(matches, "k", defaults.k)?,
+	n : number(matches, "n", defaults.n)?,
+	w : number(matches, "w", defaults.w)?,
+	poly : poly_option(matches)?,
+	key  : key_option(matches)?,
+	bufsize : number(matches, "bufsize", defaults.bufsize)?,
+	use_ref : matches.is_present("r"),
+	threads : number(matches, "threads", defaults.threads)?,
+	mmap    : matches.is_present("mmap"),
+	whole   : matches.is_present("whole"),
+	outdirs : outdirs_option(matches),
+	template : template_option(matches)?,
+	perl_compat : matches.is_present("perl-compat"),
+	output  : output_options(matches),
+    };
+    report_kernel(matches, opts.poly);
+    // -b, else timed, else saved, else the default
+    let what = TuneFor::Split { k : opts.k, n : opts.n };
+    if matches.is_present("auto-tune") {
+	eprintln!("Timing buffer sizes for k = {}, n = {}", opts.k, opts.n);
+	let timings = tune_split(&read_sample(infile)?, &opts)?;
+	opts.bufsize = tuned(matches, what, &timings)?;
+    } else if !matches.is_present("bufsize") {
+	if let Some(bufsize) = tune_config().bufsize(what) {
+	    opts.bufsize = bufsize;
+	}
+    }
+
+    let matrix_out = match matches.value_of("matrix") {
+	Some(file) => Some(OutputFile::create(file, &opts.output)?),
+	None => None,
+    };
+
+    eprintln!("Doing {} split", if opts.whole { "whole-file" } else { "block-wise" });
+    let shares = match (tar, matches.value_of("manifest")) {
+	(Some(archive), _) => split_to_tar(infile, archive, &opts)?,
+	(None, Some(manifest)) => split_with_manifest(infile, manifest, &opts)?.0,
+	(None, None) => split_file(infile, &opts)?,
+    };
+    if let Some(out) = matrix_out {
+	let matrix : Vec<u8> = shares.iter()
+	    .flat_map(|s| s.header.xform_data.iter().cloned())
+	    .collect();
+	write_matrix(out, &matrix)?;
+    }
+
+    if json {
+	println!("{}", split_json(infile, opts.k, opts.n, opts.w, opts.poly,
+				  &shares));
+    }
+    Ok(())
+}
+
+// combine
+
+pub fn combine_args<'a, 'b>(app : App<'a, 'b>) -> App<'a, 'b> {
+    let app = app
+	.about("Rabin IDA combine")
+	.args_from_usage(
+	    "-o <FILE>            'Output file name (required)'
+	     --scan=[DIR|GLOB]... 'Find shares in directories or globs'
+	     --template=[T]       'Only use shares named as by this template'
+	     --matrix=[FILE]      'Transform matrix for shares without rows'")
+	.arg(Arg::from_usage(
+	    "--tar=[ARCHIVE]...   'Find shares in tar archives (eg, one from each site)'")
+	     .number_of_values(1)
+	     .conflicts_with("scan"))
+	.arg(Arg::with_name("INFILE")
+	     .multiple(true)
+	     .help("Sets the input file(s) to use")
+	     .required_unless_one(&["scan", "template", "tar", "manifest"])
+	     .conflicts_with_all(&["scan", "template", "tar"])
+	     .index(1))
+	.arg(Arg::from_usage(
+	    "--manifest=[FILE]    'Share list, transform rows and checksums from a manifest'")
+	     .conflicts_with_all(&["scan", "template"]));
+    let app = mmap_arg(tune_args(threads_arg(kernel_args(app))));
+    json_arg(outdir_arg(output_args(app)))
+}
+
+pub fn run_combine(matches : &ArgMatches) -> IdaResult<()> {
+    let opts = combine_options(matches)?;
+    let outfile = match matches.value_of("outdir") {
+	Some(dir) => std::path::Path::new(dir)
+	    .join(matches.value_of("o").unwrap())
+	    .to_string_lossy().into_owned(),
+	None => matches.value_of("o").unwrap().to_string(),
+    };
+    let template = template_option(matches)?;
+    let archives : Vec<_> = matches.values_of("tar")
+	.map_or(Vec::new(), |archives| archives.collect());
+    let used = match matches.values_of("INFILE") {
+	None if !archives.is_empty() => {
+	    let mut store = UnionStore::new();
+	    for archive in archives.iter() {
+		store.add(archive, Box::new(TarStore::open(archive)?));
+	    }
+	    let used = combine_store(&store, &archives.join(" "),
+				     template.as_ref(), &outfile, &opts)?;
+	    let names : Vec<_> = used.iter().map(|s| &s.name[..]).collect();
+	    eprintln!("Combined shares {}", names.join(" "));
+	    used
+	},
+	None if opts.manifest.is_some() => {
+	    // whichever of the manifest's shares are still intact
+	    let manifest = opts.manifest.as_ref().unwrap();
+	    let store = LocalStore::new(opts.output);
+	    let files : Vec<_> = manifest_shares(&opts).into_iter()
+		.filter(|name| match manifest.check_share(&store, name) {
+		    Some(problem) => { eprintln!("Skipping {}: {}", name, problem); false },
+		    None => true,
+		})
+		.collect();
+	    let used = combine_files(&files, &outfile, &opts)?;
+	    let names : Vec<_> = used.iter().map(|s| &s.name[..]).collect();
+	    eprintln!("Combined shares {}", names.join(" "));
+	    used
+	},
+	None => {
+	    let specs : Vec<_> = matches.values_of("scan")
+		.map_or(Vec::new(), |specs| specs.collect());
+	    let used = combine_discovered(&specs, template.as_ref(),
+					  &outfile, &opts)?;
+	    let names : Vec<_> = used.iter().map(|s| &s.name[..]).collect();
+	    eprintln!("Combined shares {}", names.join(" "));
+	    used
+	},
+	Some(files) => {
+	    let files : Vec<_> = files.collect();
+	    combine_files(&files, &outfile, &opts)?
+	},
+    };
+
+    if matches.is_present("json") {
+	let header = &used[0].header;
+	let report = Json::object()
+	    .with("output", outfile)
+	    .with("bytes", header.chunk_next - header.chunk_start)
+	    .with("k", header.k)
+	    .with("chunk_start", header.chunk_start)
+	    .with("chunk_next", header.chunk_next)
+	    .with("shares", used.iter().map(share_json).collect::<Vec<_>>());
+	println!("{}", report);
+    }
+    Ok(())
+}
+
+// info
+
+pub fn info_args<'a, 'b>(app : App<'a, 'b>) -> App<'a, 'b> {
+    let app = app.about("Display sharefile headers, or check a set of shares")
+	.args_from_usage(
+	    "--key=[list]         'Key the shares were made with, to number them'
+	     --dump               'Annotated hex dump of each header'
+	     --rewrite            'Rewrite headers, keeping the share data'");
+    // edits for --rewrite
+    let app = app.args(&[
+	Arg::from_usage("--strip-row         'Remove the transform row'")
+	    .requires("rewrite").conflicts_with("matrix"),
+	Arg::from_usage("--matrix=[FILE]     'Attach a transform row from a matrix file'")
+	    .requires("rewrite"),
+	Arg::from_usage("--row=[N]           'Matrix row to attach (default: share number in name)'")
+	    .requires("matrix"),
+	Arg::from_usage("--chunk-start=[N]   'Set chunk_start'").requires("rewrite"),
+	Arg::from_usage("--chunk-next=[N]    'Set chunk_next'").requires("rewrite"),
+	Arg::from_usage("--output=[FILE]     'Write the new share here instead of in place'")
+	    .requires("rewrite"),
+	Arg::from_usage("--force             'Overwrite an existing --output file'")
+	    .requires("output"),
+	Arg::from_usage("--sync              'Flush the --output file to disk'")
+	    .requires("output"),
+    ]);
+    infiles_arg(json_arg(app), true)
+}
+
+// With more than one file, report on them as a set
+pub fn run_info(matches : &ArgMatches) -> IdaResult<()> {
+    let files : Vec<_> = matches.values_of("INFILE").unwrap().collect();
+    if matches.is_present("dump") {
+	return run_info_dump(matches, &files)
+    }
+    if matches.is_present("rewrite") {
+	return run_info_rewrite(matches, &files)
+    }
+    if files.len() > 1 {
+	return run_info_set(matches, &files)
+    }
+    let file = files[0];
+
+    let mut fh = File::open(file)
+	.map_err(|e| IdaError::io(file, e))?;
+    let header = read_sharefile_header(&mut fh)
+	.map_err(|e| IdaError::new(ErrorClass::BadHeader,
+				   format!("{}: {}", file, e)))?;
+
+    let header_len = fh.stream_position()
+	.map_err(|e| IdaError::io(file, e))? as usize;
+    let file_len = fh.metadata()
+	.map_err(|e| IdaError::io(file, e))?.len() as usize;
+    let data_len = file_len - header_len;
+
+    if matches.is_present("json") {
+	let report = Json::object()
+	    .with("path", file)
+	    .with("header", header_json(&header))
+	    .with("header_length", header_len)
+	    .with("data_length", data_len)
+	    .with("expected_data_length", header.share_len());
+	println!("{}", report);
+    } else {
+	println!("File {} sharefile header info", file);
+	println!("quorum (k)  = {}", header.k);
+	println!("width  (w)  = {}", header.w);
+	println!("chunk_start = {}", header.chunk_start);
+	println!("chunk_next  = {}", header.chunk_next);
+	if header.xform {
+	    println!("Header has embedded xform row:");
+	    println!("{:x?}", header.xform_data);
+	} else {
+	    println!("Header has no embedded xform row");
+	}
+	println!("data length = {} (expected {})", data_len, header.share_len());
+    }
+    check_share_len(file, &header, data_len)
+}
+
+fn run_info_rewrite(matches : &ArgMatches, files : &[&str]) -> IdaResult<()> {
+    let output = matches.value_of("output");
+    if output.is_some() && files.len() > 1 {
+	return Err(usage("Can't use --output with more than one share"))
+    }
+    let matrix = match matches.value_of("matrix") {
+	Some(file) => Some(read_matrix(file)?),
+	None => None,
+    };
+    let row = match matches.value_of("row") {
+	Some(value) => match parse_number("row", value)? {
+	    0 => return Err(usage("Share numbers start from 1")),
+	    row => Some(row - 1),
+	},
+	None => None,
+    };
+    let chunk_start = match matches.value_of("chunk-start") {
+	Some(value) => Some(parse_number("chunk-start", value)?),
+	None => None,
+    };
+    let chunk_next = match matches.value_of("chunk-next") {
+	Some(value) => Some(parse_number("chunk-next", value)?),
+	None => None,
+    };
+
+    let mut written = Vec::with_capacity(files.len());
+    for &file in files.iter() {
+	let edit_row = match &matrix {
+	    Some(matrix) => {
+		let mut fh = File::open(file)
+		    .map_err(|e| IdaError::io(file, e))?;
+		let header = read_sharefile_header(&mut fh)
+		    .map_err(|e| IdaError::new(ErrorClass::BadHeader,
+					       format!("{}: {}", file, e)))?;
+		let share = row.or_else(|| share_number(file))
+		    .ok_or_else(|| usage(format!(
+			"{}: can't tell share number from the name; use --row",
+			file)))?;
+		RowEdit::Attach(matrix_row(matrix, header.k * header.w, share)?)
+	    },
+	    None if matches.is_present("strip-row") => RowEdit::Strip,
+	    None => RowEdit::Keep,
+	};
+	let edit = HeaderEdit { row : edit_row, chunk_start, chunk_next };
+	written.push(rewrite_header(file, output, &edit,
+				    &output_options(matches))?);
+    }
+
+    if matches.is_present("json") {
+	let shares : Vec<_> = written.iter().map(share_json).collect();
+	println!("{}", Json::object().with("written", shares));
+    } else {
+	for share in written.iter() {
+	    println!("Rewrote header of {}", share.name);
+	}
+    }
+    Ok(())
+}
+
+// Each field gets its offset, up to 8 bytes of hex per line and its
+// notes, with "!" marking fields that are wrong
+fn print_dump(dump : &HeaderDump) {
+    for field in dump.fields.iter() {
+	let mark = if field.bad { '!' } else { ' ' };
+	let mut hex = field.bytes.chunks(8).map(|line| {
+	    line.iter().map(|b| format!("{:02x}", b))
+		.collect::<Vec<_>>().join(" ")
+	});
+	let mut notes = field.notes.iter();
+	let mut offset = Some(field.offset);
+	loop {
+	    let (bytes, note) = (hex.next(), notes.next());
+	    if bytes.is_none() && note.is_none() && offset.is_none() { break }
+	    match offset.take() {
+		Some(offset) => print!("{} {:>6}  ", mark, offset),
+		None => print!("          "),
+	    }
+	    println!("{:<24}{}", bytes.unwrap_or_default(),
+		     note.map_or("", |n| n.as_str()));
+	}
+    }
+    match dump.payload_offset {
+	Some(offset) => {
+	    print!("Payload at offset {}: {} bytes", offset, dump.payload_len);
+	    match dump.expected_len {
+		Some(expect) if expect == dump.payload_len =>
+		    println!(", as expected"),
+		Some(expect) => println!(", expected {}", expect),
+		None => println!(),
+	    }
+	},
+	None => println!("File ends inside the header"),
+    }
+}
+
+fn run_info_dump(matches : &ArgMatches, files : &[&str]) -> IdaResult<()> {
+    let mut dumps = Vec::with_capacity(files.len());
+    for &file in files.iter() {
+	dumps.push(dump_share(file).map_err(|e| IdaError::io(file, e))?);
+    }
+
+    if matches.is_present("json") {
+	let reports : Vec<_> = files.iter().zip(dumps.iter())
+	    .map(|(&file, dump)| Json::object()
+		 .with("path", file)
+		 .with("fields", dump.fields.iter().map(|f| Json::object()
+		       .with("name", f.name)
+		       .with("offset", f.offset)
+		       .with("bytes", f.bytes.clone())
+		       .with("notes", f.notes.clone())
+		       .with("ok", !f.bad))
+		       .collect::<Vec<_>>())
+		 .with("payload_offset", dump.payload_offset)
+		 .with("payload_length", dump.payload_len)
+		 .with("expected_payload_length", dump.expected_len))
+	    .collect();
+	println!("{}", Json::from(reports));
+    } else {
+	for (i, (file, dump)) in files.iter().zip(dumps.iter()).enumerate() {
+	    if i > 0 { println!() }
+	    println!("File {} sharefile header dump", file);
+	    print_dump(dump);
+	}
+    }
+
+    let bad : Vec<&str> = files.iter().zip(dumps.iter())
+	.filter(|(_, d)| d.bad()).map(|(&f, _)| f).collect();
+    if !bad.is_empty() {
+	return Err(IdaError::new(ErrorClass::BadHeader, format!(
+	    "Bad header in {}", bad.join(", "))))
+    }
+    let short : Vec<&str> = files.iter().zip(dumps.iter())
+	.filter(|(_, d)| d.expected_len.is_some_and(|e| e != d.payload_len))
+	.map(|(&f, _)| f).collect();
+    if !short.is_empty() {
+	return Err(IdaError::new(ErrorClass::Damaged, format!(
+	    "Wrong payload length in {}", short.join(", "))))
+    }
+    Ok(())
+}
+
+fn chunk_range(start : usize, next : usize) -> String {
+    format!("{}..{}", start, next)
+}
+
+fn run_info_set(matches : &ArgMatches, files : &[&str]) -> IdaResult<()> {
+    let key = key_option(matches)?;
+    let report = inspect_shares(files, key.as_deref());
+
+    if matches.is_present("json") {
+	let shares : Vec<_> = report.shares.iter().map(|s| Json::object()
+	    .with("path", s.name.as_str())
+	    .with("header", s.header.as_ref().map(header_json))
+	    .with("index", s.index)
+	    .with("data_length", s.data_len)
+	    .with("expected_data_length", s.expected_len)
+	    .with("usable", s.usable())
+	    .with("problems", s.problems.clone()))
+	    .collect();
+	let chunks : Vec<_> = report.chunks.iter().map(|c| Json::object()
+	    .with("k", c.k)
+	    .with("chunk_start", c.chunk_start)
+	    .with("chunk_next", c.chunk_next)
+	    .with("is_final", c.is_final)
+	    .with("usable", c.usable)
+	    .with("independent", c.independent)
+	    .with("decodable", c.decodable()))
+	    .collect();
+	let gaps : Vec<_> = report.gaps.iter()
+	    .map(|&(start, next)| Json::object()
+		 .with("start", start).with("next", next))
+	    .collect();
+	let summary = Json::object()
+	    .with("shares", shares)
+	    .with("chunks", chunks)
+	    .with("gaps", gaps)
+	    .with("complete", report.complete)
+	    .with("decodable", report.decodable())
+	    .with("spare", report.spare());
+	println!("{}", summary);
+    } else {
+	// "!" marks shares that can't be used
+	println!("  {:<24} {:>5} {:>3} {:>2} {:<20} TRANSFORM",
+		 "FILE", "SHARE", "K", "W", "CHUNK");
+	for share in report.shares.iter() {
+	    let mark = if share.usable() { ' ' } else { '!' };
+	    match &share.header {
+		Some(h) => println!(
+		    "{} {:<24} {:>5} {:>3} {:>2} {:<20} {}", mark, share.name,
+		    share.index.map_or("?".to_string(), |i| i.to_string()),
+		    h.k, h.w, chunk_range(h.chunk_start, h.chunk_next),
+		    h.xform_data.iter().map(|b| format!("{:02x}", b))
+			.collect::<Vec<_>>().join(" ")),
+		None => println!("{} {:<24} (unreadable)", mark, share.name),
+	    }
+	}
+	println!();
+	for share in report.shares.iter() {
+	    for problem in share.problems.iter() {
+		println!("{}: {}", share.name, problem);
+	    }
+	}
+	for chunk in report.chunks.iter() {
+	    println!("Chunk {}{}: {} usable share{}, {} independent, need {}",
+		     chunk_range(chunk.chunk_start, chunk.chunk_next),
+		     if chunk.is_final { " (final)" } else { "" },
+		     chunk.usable, if chunk.usable == 1 { "" } else { "s" },
+		     chunk.independent, chunk.k);
+	}
+	for &(start, next) in report.gaps.iter() {
+	    println!("No shares cover bytes {}", chunk_range(start, next));
+	}
+	if !report.chunks.is_empty() && !report.complete && report.gaps.is_empty() {
+	    println!("No shares for the final chunk");
+	}
+	match report.spare() {
+	    Some(spare) => println!("Set is decodable; it can lose {} more share{}",
+				    spare, if spare == 1 { "" } else { "s" }),
+	    None => println!("Set is not decodable"),
+	}
+    }
+
+    if report.decodable() { Ok(()) } else {
+	Err(IdaError::new(ErrorClass::Quorum,
+			  "Not enough usable shares to decode"))
+    }
+}
+
+// verify
+
+pub fn verify_args<'a, 'b>(app : App<'a, 'b>) -> App<'a, 'b> {
+    let app = manifest_arg(app.about("Check that shares agree"),
+			   "Check shares against a manifest's checksums");
+    infiles_unless_arg(json_arg(threads_arg(kernel_args(app))), "manifest")
+}
+
+pub fn run_verify(matches : &ArgMatches) -> IdaResult<()> {
+    let opts = combine_options(matches)?;
+    let files : Vec<_> = match matches.values_of("INFILE") {
+	Some(files) => files.collect(),
+	None => manifest_shares(&opts),
+    };
+    let checks = verify_shares(&files, &opts)?;
+
+    let bad = checks.iter().filter(|c| c.problem.is_some()).count();
+    if matches.is_present("json") {
+	let shares : Vec<_> = checks.iter().map(|c| Json::object()
+	    .with("path", c.name.as_str())
+	    .with("ok", c.problem.is_none())
+	    .with("problem", c.problem.clone()))
+	    .collect();
+	let report = Json::object()
+	    .with("ok", bad == 0)
+	    .with("shares", shares);
+	println!("{}", report);
+    } else {
+	for check in checks.iter() {
+	    match &check.problem {
+		None => println!("{}: OK", check.name),
+		Some(problem) => println!("{}: {}", check.name, problem),
+	    }
+	}
+    }
+    if bad > 0 {
+	return Err(IdaError::new(ErrorClass::Damaged, format!(
+	    "{} of {} shares failed verification", bad, checks.len())))
+    }
+    Ok(())
+}
+
+// repair
+
+pub fn repair_args<'a, 'b>(app : App<'a, 'b>) -> App<'a, 'b> {
+    let app = app
+	.about("Regenerate missing or damaged shares")
+	.args_from_usage(
+	    "--shares=[list]      'Share numbers to write (default: missing ones)'
+	     --name=[FILE]        'Original file name (default: from share names)'
+	     -p, --perl-compat    'Name shares as Crypt::IDA (Perl) does'");
+    let app = manifest_arg(app, "Find and rewrite the shares that don't match a manifest");
+    let app = threads_arg(kernel_args(app));
+    infiles_unless_arg(json_arg(naming_args(output_args(scheme_args(app)))),
+		       "manifest")
+}
+
+// Comma-separated share numbers (from 1) for --shares, counting from 0
+fn shares_option(matches : &ArgMatches, n : usize)
+		 -> IdaResult<Option<Vec<usize>>> {
+    let list = match matches.value_of("shares") {
+	Some(list) => list,
+	None => return Ok(None),
+    };
+    let mut wanted = Vec::new();
+    for value in list.split(',') {
+	let share = parse_number("shares", value.trim())?;
+	if share < 1 || share > n {
+	    return Err(usage(format!("Share number {} not in 1..{}",
+				     share, n)))
+	}
+	wanted.push(share - 1);
+    }
+    Ok(Some(wanted))
+}
+
+// The manifest says what every share should be, so no need for
+// INFILEs, -n, --key or names
+fn repair_from_manifest(matches : &ArgMatches, opts : &CombineOptions)
+			-> IdaResult<()> {
+    let n = opts.manifest.as_ref().map_or(0, |m| m.shares.len());
+    let wanted = shares_option(matches, n)?;
+    let store = LocalStore::new(opts.output);
+    let written = repair_manifest(&store, wanted.as_deref(), opts)?;
+    if matches.is_present("json") {
+	let shares : Vec<_> = written.iter().map(share_json).collect();
+	println!("{}", Json::object().with("written", shares));
+    } else if written.is_empty() {
+	println!("Nothing to repair");
+    } else {
+	for share in written.iter() {
+	    println!("Wrote {}", share.name);
+	}
+    }
+    Ok(())
+}
+
+// Recover the original file name from a share name, and whether it
+// uses Crypt::IDA naming (the only kind ending in ".sf")
+fn share_base(name : &str) -> Option<(String, bool)> {
+    split_share_name(name)
+	.map(|(base, _)| (base.to_string(), name.ends_with(".sf")))
+}
+
+pub fn run_repair(matches : &ArgMatches) -> IdaResult<()> {
+    let opts = combine_options(matches)?;
+    if opts.manifest.is_some() && matches.values_of("INFILE").is_none() {
+	return repair_from_manifest(matches, &opts)
+    }
+    let files : Vec<_> = matches.values_of("INFILE").unwrap().collect();
+
+    // work out which rows of the transform we already have
+    let set = ShareSet::open(&files, false)?;
+    let k = set.k();
+    let n = number(matches, "n", SplitOptions::default().n)?;
+    if n < k || k + n > 255 {
+	return Err(usage(format!("Bad number of shares n = {} for k = {}",
+				 n, k)))
+    }
+    let key = key_option(matches)?.unwrap_or_else(|| default_key(k, n));
+    check_key(&key, k, n).map_err(usage)?;
+    let matrix = transform_matrix(&new_field(opts.poly), &key, k, n);
+    let rows : Vec<_> = matrix.chunks(k).collect();
+
+    let mut have = vec![false; n];
+    for (name, header) in set.names.iter().zip(set.headers.iter()) {
+	match rows.iter().position(|row| *row == &header.xform_data[..]) {
+	    Some(share) => have[share] = true,
+	    None => return Err(IdaError::new(ErrorClass::Mismatch, format!(
+		"{}: transform row doesn't match the key (check -n and --key)",
+		name))),
+	}
+    }
+    drop(set);
+
+    // shares to write, counting from zero
+    let wanted : Vec<usize> = match shares_option(matches, n)? {
+	Some(wanted) => wanted,
+	None => (0..n).filter(|&share| !have[share]).collect(),
+    };
+    let json = matches.is_present("json");
+    if wanted.is_empty() {
+	if json {
+	    println!("{}", Json::object().with("written", Vec::<Json>::new()));
+	} else {
+	    println!("Nothing to repair");
+	}
+	return Ok(())
+    }
+
+    let template = template_option(matches)?;
+    let cant_tell = || usage(format!(
+	"Can't tell original file name from {}; use --name", files[0]));
+    let (base, perl) = match (matches.value_of("name"), &template) {
+	(Some(name), _) => (name.to_string(), false),
+	(None, Some(template)) => {
+	    // a template without its own directories puts the new
+	    // shares next to the first one
+	    let name = template.match_name(files[0]).and_then(|m| m.name)
+		.ok_or_else(cant_tell)?;
+	    let dir = match template.glob(None).contains('/') {
+		true => std::path::Path::new(""),
+		false => std::path::Path::new(files[0]).parent()
+		    .unwrap_or(std::path::Path::new("")),
+	    };
+	    (dir.join(name).to_string_lossy().into_owned(), false)
+	},
+	(None, None) => share_base(files[0]).ok_or_else(cant_tell)?,
+    };
+    let naming = SplitOptions {
+	k, n,
+	outdirs  : outdirs_option(matches),
+	template,
+	perl_compat : perl || matches.is_present("perl-compat"),
+	..SplitOptions::default()
+    };
+    let outfiles : Vec<String> = wanted.iter()
+	.map(|&share| naming.share_name(&base, share))
+	.collect();
+    if let Some(name) = outfiles.iter().find(|name| files.contains(&&name[..])) {
+	return Err(usage(format!("Won't overwrite input share {}", name)))
+    }
+
+    let new_rows : Vec<Vec<u8>> = wanted.iter()
+	.map(|&share| rows[share].to_vec())
+	.collect();
+    let written = repair_shares(&files, &new_rows, &outfiles, &opts)?;
+    if json {
+	let shares : Vec<_> = written.iter().map(share_json).collect();
+	println!("{}", Json::object().with("written", shares));
+    } else {
+	for name in outfiles.iter() {
+	    println!("Wrote {}", name);
+	}
+    }
+    Ok(())
+}
+
+// convert
+
+pub fn convert_args<'a, 'b>(app : App<'a, 'b>) -> App<'a, 'b> {
+    let app = app
+	.about("Convert zfec shares to/from sharefiles")
+	.args_from_usage(
+	    "-z, --to-zfec        'Convert sharefiles to zfec .fec files'
+	     -V, --vandermonde    'Use Vandermonde matrix for sharefiles (default Cauchy)'
+	     -k=[int]             'quorum value (default: same as input)'
+	     -n=[int]             'number of shares (default: zfec m value)'
+	     -o <PREFIX>          'Output file name prefix (required)'");
+    infiles_arg(output_args(app), true)
+}
+
+pub fn run_convert(matches : &ArgMatches) -> IdaResult<()> {
+    let files : Vec<&str> = matches.values_of("INFILE").unwrap().collect();
+    let prefix = matches.value_of("o").unwrap();
+    let k = matches.value_of("k").map(|v| parse_number("k", v)).transpose()?;
+    let n = matches.value_of("n").map(|v| parse_number("n", v)).transpose()?;
+    let opts = output_options(matches);
+
+    if matches.is_present("to-zfec") {
+	let n = n.ok_or_else(|| usage(
+	    "Need -n to say how many zfec shares to write"))?;
+	let k = match k {
+	    Some(k) => k,
+	    None => probe_share(files[0])?.1.k,
+	};
+	let names = sharefiles_to_zfec(&files, prefix, k, n, &opts)?;
+	eprintln!("Wrote {} zfec shares", names.len());
+	return Ok(())
+    }
+
+    let (zfec_k, zfec_m) = zfec_scheme(files[0])?;
+    if k.is_some_and(|k| k != zfec_k) {
+	return Err(usage(format!(
+	    "Can't change k ({}) when converting to sharefiles", zfec_k)))
+    }
+    let n = n.unwrap_or(zfec_m);
+    if n < zfec_k || zfec_k + n > 255 {
+	return Err(usage(format!(
+	    "Need k <= n and k + n <= 255 (got k = {}, n = {})", zfec_k, n)))
+    }
+    let xform = sharefile_transform(zfec_k, n, matches.is_present("vandermonde"));
+    let outfiles = (1..=n)
+	.map(|ext| format!("{}-block.{}", prefix, ext))
+	.collect();
+    let size = zfec_to_sharefiles(&files, outfiles, &xform, &opts)?;
+    eprintln!("Converted {} bytes", size);
+    Ok(())
+}
+
+// The ida tool
+
+// selftest
+
+pub fn selftest_args<'a, 'b>(app : App<'a, 'b>) -> App<'a, 'b> {
+    let app = app.about("Check the multiply routines on this host")
+	.args_from_usage(
+	    "--seed=[int]         'Seed for the random data (default 1)'");
+    json_arg(app)
+}
+
+pub fn run_selftest(matches : &ArgMatches) -> IdaResult<()> {
+    let seed = number(matches, "seed", 1)?;
+    let checks = selftest(seed as u64);
+    if matches.is_present("json") {
+	let results : Vec<_> = checks.iter().map(|c| {
+	    let (status, problem) = match &c.outcome {
+		Outcome::Passed => ("passed", None),
+		Outcome::Failed(problem) => ("failed", Some(problem.as_str())),
+		Outcome::Skipped(why) => ("skipped", Some(why.as_str())),
+	    };
+	    Json::object()
+		.with("check", c.name.as_str())
+		.with("result", status)
+		.with("problem", problem)
+	}).collect();
+	let report = Json::object()
+	    .with("ok", selftest_result(&checks).is_ok())
+	    .with("seed", seed)
+	    .with("checks", results);
+	println!("{}", report);
+    } else {
+	for check in checks.iter() {
+	    match &check.outcome {
+		Outcome::Passed => println!("{}: OK", check.name),
+		Outcome::Failed(problem) => println!("{}: FAILED, {}",
+						     check.name, problem),
+		Outcome::Skipped(why) => println!("{}: skipped, {}",
+						  check.name, why),
+	    }
+	}
+    }
+    selftest_result(&checks)
+}
+
+pub fn ida_app<'a, 'b>() -> App<'a, 'b> {
+    App::new("ida")
+	.version(VERSION)
+	.author(AUTHOR)
+	.about("Rabin IDA share files")
+	.setting(AppSettings::SubcommandRequiredElseHelp)
+	.subcommand(split_args(SubCommand::with_name("split")))
+	.subcommand(combine_args(SubCommand::with_name("combine")))
+	.subcommand(info_args(SubCommand::with_name("info")))
+	.subcommand(verify_args(SubCommand::with_name("verify")))
+	.subcommand(repair_args(SubCommand::with_name("repair")))
+	.subcommand(selftest_args(SubCommand::with_name("selftest")))
+	.subcommand(convert_args(SubCommand::with_name("convert")))
+}
+
+pub fn run_ida(matches : &ArgMatches) -> IdaResult<()> {
+    match matches.subcommand() {
+	("split",   Some(m)) => run_split(m),
+	("combine", Some(m)) => run_combine(m),
+	("info",    Some(m)) => run_info(m),
+	("verify",  Some(m)) => run_verify(m),
+	("repair",  Some(m)) => run_repair(m),
+	("selftest", Some(m)) => run_selftest(m),
+	("convert", Some(m)) => run_convert(m),
+	_ => Err(usage(matches.usage())),
+    }
+}
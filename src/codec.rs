@@ -0,0 +1,332 @@
+// Field, key and matrix setup shared by split, combine and friends
+//
+// The matrix types in guff-matrix have a couple of restrictions that
+// callers shouldn't have to think about:
+//
+// * a matrix must have at least 16 elements
+//
+// * the SIMD multiply walks the output matrix diagonally, so the
+//   number of output rows and columns must be coprime
+//
+// Transform, below, takes care of both. Small transforms are padded
+// with zero rows. Rather than padding every block with an unused
+// column to get coprime dimensions, the SIMD multiply does as many
+// columns as it can and the one or two left over are done a column
+// at a time. Callers can use any block size (say, a multiple of the
+// file system's block size) without it costing anything extra.
+
+use guff::*;
+use guff_ida::*;
+use guff_matrix::*;
+
+// Crypt::IDA always uses this polynomial for w = 1, and it's the only
+// one that the SIMD multiply supports.
+pub const DEFAULT_POLY : u16 = 0x11b;
+
+pub fn new_field(poly : u16) -> F8 {
+    new_gf8(poly, (poly & 0xff) as u8)
+}
+
+// Key used when none is given: 1, 2, ..., k + n
+pub fn default_key(k : usize, n : usize) -> Vec<u8> {
+    (1..=k + n).map(|x| x as u8).collect()
+}
+
+// The Cauchy matrix needs k + n distinct non-zero field values (the
+// first k are the y values, the rest are the x values)
+pub fn check_key(key : &[u8], k : usize, n : usize) -> Result<(), String> {
+    if key.len() != k + n {
+        return Err(format!("Key should have k + n = {} values, not {}",
+                           k + n, key.len()))
+    }
+    let mut seen = [false; 256];
+    for x in key.iter() {
+        if *x == 0 {
+            return Err("Key values must be non-zero".to_string())
+        }
+        if seen[*x as usize] {
+            return Err(format!("Key value {} is repeated", x))
+        }
+        seen[*x as usize] = true;
+    }
+    Ok(())
+}
+
+// n rows of k, one row per share
+pub fn transform_matrix(field : &F8, key : &[u8], k : usize, n : usize)
+                        -> Vec<u8> {
+    cauchy_matrix(field, &key.to_vec(), k, n)
+}
+
+// Gauss-Jordan inverse of a k x k (rowwise) matrix
+//
+// Matrix::new can't make matrices smaller than 16 elements, so this
+// works on plain vectors. Returns None if the matrix is singular.
+pub fn invert_matrix(field : &F8, matrix : &[u8], k : usize)
+                     -> Option<Vec<u8>> {
+    let mut a = matrix.to_vec();
+    let mut b = vec![0u8; k * k];
+    for i in 0..k { b[i * k + i] = 1 }
+
+    for col in 0..k {
+        let pivot = (col..k).find(|&row| a[row * k + col] != 0)?;
+        for c in 0..k {
+            a.swap(col * k + c, pivot * k + c);
+            b.swap(col * k + c, pivot * k + c);
+        }
+        let scale = field.inv(a[col * k + col]);
+        for c in 0..k {
+            a[col * k + c] = field.mul(a[col * k + c], scale);
+            b[col * k + c] = field.mul(b[col * k + c], scale);
+        }
+        for row in 0..k {
+            let factor = a[row * k + col];
+            if row == col || factor == 0 { continue }
+            for c in 0..k {
+                a[row * k + c] ^= field.mul(factor, a[col * k + c]);
+                b[row * k + c] ^= field.mul(factor, b[col * k + c]);
+            }
+        }
+    }
+    Some(b)
+}
+
+// Rank of a rows x cols (rowwise) matrix
+pub fn rank(field : &F8, matrix : &[u8], rows : usize, cols : usize)
+            -> usize {
+    let mut a = matrix.to_vec();
+    let mut rank = 0;
+    for col in 0..cols {
+        let pivot = match (rank..rows).find(|&row| a[row * cols + col] != 0) {
+            Some(row) => row,
+            None => continue,
+        };
+        for c in 0..cols {
+            a.swap(rank * cols + c, pivot * cols + c);
+        }
+        let scale = field.inv(a[rank * cols + col]);
+        for row in rank + 1..rows {
+            let factor = field.mul(a[row * cols + col], scale);
+            if factor == 0 { continue }
+            for c in 0..cols {
+                a[row * cols + c] ^= field.mul(factor, a[rank * cols + c]);
+            }
+        }
+        rank += 1;
+    }
+    rank
+}
+
+// The SIMD multiply needs CPU features that a portable build can't
+// assume, so they're checked for when it runs. Ok says what's being
+// used, Err what's missing.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub fn simd_support() -> Result<&'static str, &'static str> {
+    if !is_x86_feature_detected!("sse3") { return Err("SSE3") }
+    if !is_x86_feature_detected!("ssse3") { return Err("SSSE3") }
+    if !is_x86_feature_detected!("sse4.1") { return Err("SSE4.1") }
+    Ok("x86 SSSE3/SSE4.1")
+}
+
+#[cfg(target_arch = "aarch64")]
+pub fn simd_support() -> Result<&'static str, &'static str> {
+    if !std::arch::is_aarch64_feature_detected!("neon") { return Err("Neon") }
+    Ok("Arm Neon")
+}
+
+// (no stable runtime check on 32-bit Arm, so it has to be built in)
+#[cfg(target_arch = "arm")]
+pub fn simd_support() -> Result<&'static str, &'static str> {
+    if cfg!(target_feature = "neon") { Ok("Arm Neon") } else { Err("Neon") }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Kernel {
+    Simd,
+    Reference,
+}
+
+// Which multiply to use (and why) when the reference one is or isn't
+// asked for. SIMD only does the default field, and only on CPUs that
+// can run it; anything else falls back to the reference multiply.
+pub fn choose_kernel(use_ref : bool, poly : u16) -> (Kernel, String) {
+    if use_ref {
+        return (Kernel::Reference, "reference (asked for)".to_string())
+    }
+    if poly != DEFAULT_POLY {
+        return (Kernel::Reference, format!(
+            "reference (SIMD only does field {:#x})", DEFAULT_POLY))
+    }
+    match simd_support() {
+        Ok(what) => (Kernel::Simd, format!("SIMD ({})", what)),
+        Err(missing) => (Kernel::Reference, format!(
+            "reference (CPU doesn't have {})", missing)),
+    }
+}
+
+// Choice of multiply routines. The SIMD one is never run on a CPU
+// that can't, whatever the caller says. It's only fast if the build
+// enables SSSE3 and SSE4.1, though: guff-matrix doesn't, and its
+// helpers can't be inlined into a #[target_feature] function here
+// (see the README).
+pub fn multiply(xform : &mut Matrix, input : &mut Matrix,
+                output : &mut Matrix, field : &F8, use_ref : bool) {
+    if use_ref || simd_support().is_err() {
+        reference_matrix_multiply(xform, input, output, field);
+    } else {
+        unsafe {
+            simd_warm_multiply(xform, input, output);
+        }
+    }
+}
+
+// A rows x k transform applied to blocks of up to cols input columns
+//
+// The matrices only cover simd_cols columns, the most that are
+// coprime with the (padded) number of rows. Their buffers are
+// extended so that the rest of the columns follow straight on, so
+// the caller still sees one block of input and (colwise) output. The
+// SIMD multiply reads a little past the end of its input (into the
+// guard bytes every matrix has), so the few bytes there are set
+// aside while it runs. Rowwise output keeps the leftover columns
+// apart, since each row of the matrix ends at simd_cols.
+pub struct Transform {
+    rows   : usize,             // rows the caller asked for
+    k      : usize,
+    cols   : usize,             // columns the caller asked for
+    simd_cols : usize,          // columns in the matrices
+    data   : Vec<u8>,           // the transform, for the leftover columns
+    xform  : Matrix,
+    input  : Matrix,
+    output : Matrix,
+    leftover : Vec<u8>,         // rowwise output past simd_cols
+}
+
+// Guard bytes past the end of a matrix (see X86Matrix::new)
+const GUARD : usize = 15;
+
+impl Transform {
+
+    // matrix has rows x k elements (rowwise). The input is always
+    // colwise (k consecutive bytes per column); the output can be
+    // either.
+    pub fn new(matrix : &[u8], rows : usize, k : usize, cols : usize,
+               rowwise_output : bool) -> Transform {
+        assert_eq!(matrix.len(), rows * k);
+        assert!(cols > 0);
+
+        // pad with zero rows to get at least 16 elements
+        let mut matrix_rows = rows;
+        while matrix_rows * k < 16 { matrix_rows += 1 }
+
+        // Tiny blocks (fewer than 16 elements in the input or
+        // output, or a single column) still need padding columns, but
+        // otherwise take columns off until they're coprime with the
+        // number of rows. The multiply can't do one column: gcd is
+        // then the column count, which it won't have.
+        let min_cols = 16usize.div_ceil(k).max(16usize.div_ceil(matrix_rows))
+            .max(2);
+        let mut simd_cols = cols;
+        while simd_cols >= min_cols && gcd(matrix_rows, simd_cols) != 1 {
+            simd_cols -= 1
+        }
+        if simd_cols < min_cols {
+            simd_cols = min_cols;
+            while gcd(matrix_rows, simd_cols) != 1 { simd_cols += 1 }
+        }
+
+        let mut data = matrix.to_vec();
+        data.resize(matrix_rows * k, 0);
+        let mut xform = Matrix::new(matrix_rows, k, true);
+        xform.fill(&data);
+        data.truncate(rows * k);
+
+        let total_cols = cols.max(simd_cols);
+        let mut input = Matrix::new(k, simd_cols, false);
+        input.array.resize(k * total_cols + GUARD, 0);
+        let mut output = Matrix::new(matrix_rows, simd_cols, rowwise_output);
+        let leftover = cols.saturating_sub(simd_cols);
+        let leftover = if rowwise_output {
+            vec![0u8; rows * leftover]
+        } else {
+            output.array.resize(matrix_rows * total_cols + GUARD, 0);
+            Vec::new()
+        };
+
+        Transform {
+            rows, k, cols, simd_cols, data, xform, input, output, leftover,
+        }
+    }
+
+    pub fn rows(&self) -> usize { self.rows }
+    pub fn cols(&self) -> usize { self.cols }
+
+    // space for up to cols columns of k bytes
+    pub fn input(&mut self) -> &mut [u8] {
+        let len = self.cols * self.k;
+        &mut self.input.array[..len]
+    }
+
+    pub fn apply(&mut self, field : &F8, use_ref : bool) {
+        // the multiply reads a little way past its input, and needs
+        // zeros there rather than the next columns
+        let end = self.k * self.simd_cols;
+        let mut guard = [0u8; GUARD];
+        guard.copy_from_slice(&self.input.array[end..end + GUARD]);
+        for byte in self.input.array[end..end + GUARD].iter_mut() {
+            *byte = 0
+        }
+        multiply(&mut self.xform, &mut self.input, &mut self.output,
+                 field, use_ref);
+        self.input.array[end..end + GUARD].copy_from_slice(&guard);
+
+        // then any columns the matrices don't cover
+        let k = self.k;
+        let rowwise = self.output.is_rowwise();
+        let stride = self.output.rows();
+        let leftover = self.cols.saturating_sub(self.simd_cols);
+        for c in 0..leftover {
+            let col = self.simd_cols + c;
+            let input = &self.input.array[col * k..][..k];
+            for (r, row) in self.data.chunks(k).enumerate() {
+                let mut sum = 0;
+                for (&x, &y) in row.iter().zip(input.iter()) {
+                    sum ^= field.mul(x, y);
+                }
+                if rowwise {
+                    self.leftover[r * leftover + c] = sum;
+                } else {
+                    self.output.array[col * stride + r] = sum;
+                }
+            }
+        }
+    }
+
+    // first cols bytes of output row r (rowwise output only). They
+    // come in two parts: the columns from the matrix and the ones
+    // after it.
+    pub fn row(&self, r : usize, cols : usize) -> [&[u8]; 2] {
+        assert!(r < self.rows && cols <= self.cols);
+        let stride = self.simd_cols;
+        let leftover = self.cols.saturating_sub(stride);
+        let matrix_part = &self.output.as_slice()[r * stride..][..cols.min(stride)];
+        let rest = &self.leftover[r * leftover..][..cols.saturating_sub(stride)];
+        [matrix_part, rest]
+    }
+
+    // the first cols output columns (colwise output only), without
+    // any padding rows
+    pub fn columns(&mut self, cols : usize) -> &[u8] {
+        assert!(cols <= self.cols);
+        let stride = self.output.rows();
+        let rows = self.rows;
+        let data = &mut self.output.array;
+        if stride != rows {
+            // squeeze out the padding rows, in place
+            for col in 1..cols {
+                data.copy_within(col * stride..col * stride + rows, col * rows);
+            }
+        }
+        &data[..cols * rows]
+    }
+}
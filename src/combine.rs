@@ -0,0 +1,771 @@
+// Combine sharefiles, and check or regenerate shares from a quorum
+
+use std::io;
+use std::io::prelude::*;
+use std::io::ErrorKind::*;
+use std::mem;
+use std::sync::mpsc;
+use std::thread;
+
+use guff::*;
+use memmap2::Mmap;
+use guff_matrix::simulator::*;
+
+use crate::*;
+use crate::codec::*;
+use crate::error::*;
+use crate::rewrite::*;
+use crate::output::*;
+use crate::parallel::*;
+use crate::store::*;
+use crate::manifest::*;
+use crate::sha256::*;
+
+pub struct CombineOptions {
+    pub poly    : u16,
+    pub bufsize : usize,		// columns per block
+    pub use_ref : bool,
+    pub threads : usize,		// for the multiply; 0 for one per CPU
+    pub mmap    : bool,		// map the shares and output (see mapped.rs)
+    pub matrix  : Option<Vec<u8>>,	// rows for shares without them
+    pub manifest : Option<Manifest>,	// see manifest.rs
+    pub output  : OutputOptions,
+}
+
+impl Default for CombineOptions {
+    fn default() -> Self {
+	CombineOptions {
+	    poly : DEFAULT_POLY,
+	    bufsize : 16384,
+	    use_ref : false,
+	    threads : 0,
+	    mmap    : false,
+	    matrix  : None,
+	    manifest : None,
+	    output  : OutputOptions::default(),
+	}
+    }
+}
+
+impl CombineOptions {
+    // The SIMD multiply is hard-wired for the default polynomial, and
+    // needs a CPU that can run it
+    pub fn use_ref(&self) -> bool {
+	choose_kernel(self.use_ref, self.poly).0 == Kernel::Reference
+    }
+
+    // Where to find rows for shares without them; a manifest knows
+    // better than a bare matrix
+    pub fn rows(&self) -> Option<RowSource<'_>> {
+	match (&self.manifest, &self.matrix) {
+	    (Some(manifest), _) => Some(RowSource::Manifest(manifest)),
+	    (None, Some(matrix)) => Some(RowSource::Matrix(matrix)),
+	    (None, None) => None,
+	}
+    }
+}
+
+// Transform rows for shares whose headers don't have them
+#[derive(Clone, Copy)]
+pub enum RowSource<'a> {
+    Matrix(&'a [u8]),			// picked by share_number()
+    Manifest(&'a Manifest),		// picked by name
+}
+
+impl RowSource<'_> {
+    fn row(&self, name : &str, k : usize) -> IdaResult<Vec<u8>> {
+	match self {
+	    RowSource::Matrix(matrix) => {
+		let share = share_number(name).ok_or_else(|| IdaError::new(
+		    ErrorClass::BadHeader, format!(
+			"{}: can't tell which matrix row to use from the name",
+			name)))?;
+		matrix_row(matrix, k, share)
+		    .map_err(|e| IdaError::new(e.class,
+					       format!("{}: {}", name, e)))
+	    },
+	    RowSource::Manifest(manifest) => manifest.share(name)
+		.map(|(_, share)| share.transform.clone())
+		.ok_or_else(|| IdaError::new(ErrorClass::BadHeader, format!(
+		    "{}: no transform row in header, and not in the manifest",
+		    name))),
+	}
+    }
+}
+
+// Open a share file and check that we can handle its header
+//
+// Returns the file (positioned at the start of its data), the header
+// and the number of bytes after the header.
+pub fn probe_share(name : &str) -> IdaResult<(ShareReader, HeaderV1, usize)> {
+    probe_share_with(name, None)
+}
+
+// As probe_share(), but a share without a transform row in its header
+// gets one from matrix, picked by the share number in its name
+pub fn probe_share_with(name : &str, matrix : Option<&[u8]>)
+			-> IdaResult<(ShareReader, HeaderV1, usize)> {
+    probe_share_in(&LocalStore::new(OutputOptions::default()), name,
+		   matrix.map(RowSource::Matrix))
+}
+
+// As probe_share_with(), for a share in store
+pub fn probe_share_in(store : &dyn ShareStore, name : &str,
+		      rows : Option<RowSource>)
+		      -> IdaResult<(ShareReader, HeaderV1, usize)> {
+    let (fh, header, len) = open_share(store, name, rows)?;
+    check_share_len(name, &header, len)?;
+    Ok((fh, header, len))
+}
+
+// Counts what's read through it, to find where the header ends
+struct Counted<'a> {
+    inner : &'a mut dyn Read,
+    count : usize,
+}
+
+impl Read for Counted<'_> {
+    fn read(&mut self, buf : &mut [u8]) -> io::Result<usize> {
+	let got = self.inner.read(buf)?;
+	self.count += got;
+	Ok(got)
+    }
+}
+
+// Everything probe_share_in() does except check the data length
+fn open_share(store : &dyn ShareStore, name : &str, rows : Option<RowSource>)
+	      -> IdaResult<(ShareReader, HeaderV1, usize)> {
+    let (mut fh, len) = store.open_read(name)?;
+    let mut counted = Counted { inner : &mut fh, count : 0 };
+    let mut header = read_sharefile_header(&mut counted)
+	.map_err(|e| IdaError::new(ErrorClass::BadHeader,
+				   format!("{}: {}", name, e)))?;
+    let start = counted.count;
+
+    if header.w != 1 {
+	return Err(IdaError::new(ErrorClass::BadHeader, format!(
+	    "{}: Sorry, can't combine files with {}-byte fields yet",
+	    name, header.w)))
+    }
+    if header.chunk_start != 0 {
+	return Err(IdaError::new(ErrorClass::BadHeader, format!(
+	    "{}: Sorry, combining chunks not supported yet", name)))
+    }
+    if !header.xform {
+	if let Some(rows) = rows {
+	    header.xform_data = rows.row(store.inner_name(name), header.k)?;
+	    header.xform = true;
+	}
+    }
+    if !header.xform || header.xform_data.len() != header.k {
+	return Err(IdaError::new(ErrorClass::BadHeader,
+				 format!("{}: no transform row in header", name)))
+    }
+    Ok((fh, header, len - start))
+}
+
+// The header says exactly how much data should follow it, so catch
+// truncated (or over-long) shares before anything is decoded
+pub fn check_share_len(name : &str, header : &HeaderV1, len : usize)
+		       -> IdaResult<()> {
+    match share_len_problem(header, len) {
+	Some(problem) => Err(IdaError::new(ErrorClass::Damaged,
+					   format!("{}: {}", name, problem))),
+	None => Ok(()),
+    }
+}
+
+fn share_len_problem(header : &HeaderV1, len : usize) -> Option<String> {
+    let expect = header.share_len();
+    if len == expect { return None }
+    Some(format!("has {} bytes of data, expected {} ({})", len, expect,
+		 if len < expect { "truncated" } else { "too long" }))
+}
+
+// A set of share files with consistent headers, positioned at the
+// start of their data
+pub struct ShareSet {
+    pub names    : Vec<String>,
+    pub headers  : Vec<HeaderV1>,
+    pub data_len : Vec<usize>,	// bytes after the header
+    pub duplicates : Vec<(String, String)>,	// (file, same share as)
+    pub failed   : Option<usize>,	// share that decode() couldn't read
+    files : Vec<ShareReader>,
+    maps  : Vec<Option<(Mmap, usize)>>,	// mapped files, and where their data starts
+}
+
+impl ShareSet {
+
+    // Shares with the same transform row as an earlier one are
+    // duplicates. They're moved to the end of the set (or, with
+    // quorum_only, skipped) so that the first k shares are distinct
+    // whenever enough files are supplied. With quorum_only, files
+    // after the first k distinct shares are ignored.
+    pub fn open(infiles : &[&str], quorum_only : bool)
+		-> IdaResult<ShareSet> {
+	ShareSet::open_with(infiles, quorum_only, None)
+    }
+
+    // As open(), taking missing transform rows from matrix
+    pub fn open_with(infiles : &[&str], quorum_only : bool,
+		     matrix : Option<&[u8]>) -> IdaResult<ShareSet> {
+	ShareSet::open_in(&LocalStore::new(OutputOptions::default()),
+			  infiles, quorum_only, matrix.map(RowSource::Matrix))
+    }
+
+    // As open_with(), for shares in store
+    pub fn open_in(store : &dyn ShareStore, infiles : &[&str],
+		   quorum_only : bool, rows : Option<RowSource>)
+		   -> IdaResult<ShareSet> {
+	if infiles.is_empty() {
+	    return Err(IdaError::new(ErrorClass::Usage,
+				     "No share files supplied"))
+	}
+	let mut set = ShareSet {
+	    names : Vec::new(), headers : Vec::new(),
+	    data_len : Vec::new(), duplicates : Vec::new(),
+	    failed : None, files : Vec::new(), maps : Vec::new(),
+	};
+	let mut later = Vec::new();
+	for name in infiles.iter() {
+	    if quorum_only && !set.headers.is_empty()
+		&& set.headers.len() == set.k() {
+		eprintln!("File {} (and beyond) ignored as quorum reached",
+			  name);
+		break
+	    }
+	    let (fh, header, len) = probe_share_in(store, name, rows)?;
+	    let map = match store.open_mapped(name)? {
+		// the data is whatever follows the header
+		Some(map) if map.len() >= len => {
+		    let start = map.len() - len;
+		    Some((map, start))
+		},
+		_ => None,
+	    };
+	    if let Some(first) = set.headers.first() {
+		if header.k != first.k {
+		    return Err(IdaError::new(ErrorClass::Mismatch, format!(
+			"Mismatched k value for file {}", name)))
+		}
+		if header.chunk_next != first.chunk_next {
+		    return Err(IdaError::new(ErrorClass::Mismatch, format!(
+			"Mismatched chunk_next value for file {}", name)))
+		}
+	    }
+
+	    if let Some(same) = set.headers.iter()
+		.position(|h| h.xform_data == header.xform_data) {
+		eprintln!("File {} is a duplicate of {} (same transform row)",
+			  name, set.names[same]);
+		set.duplicates.push((name.to_string(), set.names[same].clone()));
+		if !quorum_only {
+		    later.push((name.to_string(), header, len, fh, map));
+		}
+		continue
+	    }
+
+	    set.names.push(name.to_string());
+	    set.headers.push(header);
+	    set.data_len.push(len);
+	    set.files.push(fh);
+	    set.maps.push(map);
+	}
+
+	let distinct = set.headers.len();
+	if distinct < set.k() {
+	    let mut msg = format!("Not enough files supplied to satisfy quorum {}",
+				  set.k());
+	    if !set.duplicates.is_empty() {
+		msg += &format!(" (only {} distinct shares; duplicates:", distinct);
+		for (dup, same) in set.duplicates.iter() {
+		    msg += &format!(" {} = {}", dup, same);
+		}
+		msg += ")";
+	    }
+	    return Err(IdaError::new(ErrorClass::Quorum, msg))
+	}
+	for (name, header, len, fh, map) in later {
+	    set.names.push(name);
+	    set.headers.push(header);
+	    set.data_len.push(len);
+	    set.files.push(fh);
+	    set.maps.push(map);
+	}
+	Ok(set)
+    }
+
+    // The shares that decode() uses
+    pub fn used(&self) -> Vec<ShareFile> {
+	self.names.iter().zip(self.headers.iter()).take(self.k())
+	    .map(|(name, header)| ShareFile {
+		name : name.clone(), header : header.clone() })
+	    .collect()
+    }
+
+    pub fn k(&self) -> usize {
+	self.headers.first().map_or(0, |h| h.k)
+    }
+
+    pub fn chunk_next(&self) -> usize {
+	self.headers[0].chunk_next
+    }
+
+    // bytes of data in each share
+    pub fn share_len(&self) -> usize {
+	self.headers[0].share_len()
+    }
+
+    // Decode using the first k shares, passing each block of the
+    // original file to each() in turn. each() runs on a thread of its
+    // own (see parallel.rs) while later blocks are read. Any other
+    // shares are left unread.
+    pub fn decode<F>(&mut self, opts : &CombineOptions, mut each : F)
+		     -> IdaResult<()>
+    where F : FnMut(&[u8]) -> IdaResult<()> + Send {
+
+	let k = self.k();
+	let field = new_field(opts.poly);
+	let use_ref = opts.use_ref();
+
+	// open() has already checked that the shares are the right
+	// length
+	let expect_read_bytes = self.share_len();
+
+	// Invert the matrix formed by the xform rows
+	let mut array = Vec::with_capacity(k * k);
+	for header in self.headers[..k].iter() {
+	    array.extend(header.xform_data.iter())
+	}
+	let inverse = invert_matrix(&field, &array, k)
+	    .ok_or_else(|| IdaError::new(ErrorClass::Mismatch, format!(
+		"No Matrix inverse: transform rows of {} are not independent",
+		self.names[..k].join(", "))))?;
+
+	// Both multiply routines require that the input matrix is in
+	// colwise format. (The reference multiply checks dimensions,
+	// but it indexes the input as colwise regardless of its
+	// layout.) The output matrix can be in either format, but for
+	// sequential output the most logical choice is colwise.
+	//
+	// This means that we will need to interleave the input streams.
+	// That's done along with the multiply, on the worker threads, so
+	// each block keeps its own copy of the share data. Mapped shares
+	// are interleaved straight from the mappings instead.
+	let mapped : Option<Vec<&[u8]>> = self.maps[..k].iter()
+	    .map(|map| map.as_ref()
+		 .map(|(map, start)| &map[*start..*start + expect_read_bytes]))
+	    .collect();
+	let bufsize = opts.bufsize;
+	let threads = thread_count(opts.threads);
+	let blocks = (0..block_count(threads)).map(|_| DecodeBlock {
+	    transform : Transform::new(&inverse, k, k, bufsize, false),
+	    reads : vec![Vec::new(); k],
+	    start : 0, cols : 0,
+	}).collect();
+
+	let work = |block : &mut DecodeBlock| {
+	    // TODO: add interleaver to main guff-matrix lib
+	    // The simulator module has a working version, so can use
+	    // that for now.
+	    let (start, cols) = (block.start, block.cols);
+	    let source_slices : Vec<_> = match &mapped {
+		Some(maps) => maps.iter().map(|map| &map[start..start + cols])
+		    .collect(),
+		None => block.reads.iter().map(|read| &read[..cols]).collect(),
+	    };
+	    interleave_streams(&mut block.transform.input()[..k * cols],
+			       &source_slices);
+	    block.transform.apply(&field, use_ref)
+	};
+
+	// The final column may contain padding if the original input
+	// was not a multiple of k, so never pass on more than
+	// chunk_next bytes in total.
+	let chunk_next = self.chunk_next();
+	let drain = |block : &mut DecodeBlock| {
+	    let plain = block.transform.columns(block.cols);
+	    let len = plain.len().min(chunk_next - block.start * k);
+	    each(&plain[..len])
+	};
+
+	if mapped.is_some() {
+	    // nothing to read, so blocks just say where they are
+	    let mut columns_read = 0;
+	    let fill = |block : &mut DecodeBlock| {
+		if columns_read == expect_read_bytes { return Ok(false) }
+		block.start = columns_read;
+		block.cols = bufsize.min(expect_read_bytes - columns_read);
+		columns_read += block.cols;
+		Ok(true)
+	    };
+	    return run_blocks(threads, blocks, fill, work, drain)
+	}
+
+	let (files, names, failed) = (&mut self.files[..k], &self.names,
+				      &mut self.failed);
+	thread::scope(|scope| {
+	    // Each share is read on a thread of its own, a couple of
+	    // blocks ahead, so shares on different disks are read at
+	    // the same time. Buffers come back once a block has taken
+	    // a copy.
+	    let mut streams = Vec::with_capacity(k);
+	    for (file, name) in files.iter_mut().zip(names.iter()) {
+		let (to_fill, from_share) = mpsc::sync_channel(2);
+		let (to_share, spares) = mpsc::channel::<Vec<u8>>();
+		scope.spawn(move || {
+		    let mut done = 0;
+		    while done < expect_read_bytes {
+			// final block can be less than bufsize
+			let cols = bufsize.min(expect_read_bytes - done);
+			let mut buf = spares.try_recv().unwrap_or_default();
+			buf.resize(cols, 0);
+			let read = read_block(file, name, &mut buf);
+			let stop = read.is_err();
+			if to_fill.send(read.map(|_| buf)).is_err() || stop {
+			    break
+			}
+			done += cols;
+		    }
+		});
+		streams.push((from_share, to_share));
+	    }
+
+	    // blocks take the next piece of each share in turn
+	    let mut columns_read = 0;
+	    let fill = move |block : &mut DecodeBlock| {
+		if columns_read == expect_read_bytes { return Ok(false) }
+		let cols = bufsize.min(expect_read_bytes - columns_read);
+		for (i, (from_share, to_share)) in streams.iter().enumerate() {
+		    let read = from_share.recv().unwrap_or_else(|_| {
+			Err(IdaError::new(ErrorClass::Io, format!(
+			    "Reading {} stopped unexpectedly", names[i])))
+		    });
+		    match read {
+			Ok(buf) => {
+			    let _ = to_share.send(mem::replace(&mut block.reads[i],
+							       buf));
+			},
+			Err(e) => {
+			    *failed = Some(i);
+			    return Err(e)
+			},
+		    }
+		}
+		block.start = columns_read;
+		block.cols = cols;
+		columns_read += cols;
+		Ok(true)
+	    };
+
+	    run_blocks(threads, blocks, fill, work, drain)
+	})
+    }
+}
+
+// A block of columns from each share, and the matrices to decode them
+struct DecodeBlock {
+    transform : Transform,
+    reads : Vec<Vec<u8>>,		// data from each share, unless mapped
+    start : usize,		// first column
+    cols  : usize,
+}
+
+// Fill buf from a share, complaining about premature EOF
+fn read_block(fh : &mut dyn Read, name : &str, buf : &mut [u8])
+	      -> IdaResult<()> {
+    let expect = buf.len();
+    let mut got = 0;
+    while got < expect {
+	match fh.read(&mut buf[got..]) {
+	    Err(e) => {
+		if e.kind() == Interrupted {
+		    // apparently we just retry
+		    continue
+		} else {
+		    return Err(IdaError::new(ErrorClass::Io, format!(
+			"I/O error on {}: {}", name, e)))
+		}
+	    },
+	    Ok(0) => {
+		return Err(IdaError::new(ErrorClass::Damaged, format!(
+		    "Premature EOF on {}; got {}, expected {}",
+		    name, got, expect)))
+	    },
+	    Ok(n) => { got += n },
+	}
+    }
+    Ok(())
+}
+
+// Put a block of the original file into transform's input (zero
+// padding any partial final column), apply it and return the number
+// of columns
+fn encode_block(transform : &mut Transform, plain : &[u8], k : usize,
+		field : &F8, use_ref : bool) -> usize {
+    let cols = plain.len().div_ceil(k);
+    let input = transform.input();
+    input[..plain.len()].copy_from_slice(plain);
+    for byte in input[plain.len()..cols * k].iter_mut() {
+	*byte = 0
+    }
+    transform.apply(field, use_ref);
+    cols
+}
+
+// Combine shares into outfile, returning the shares that were used
+pub fn combine_files(infiles : &[&str], outfile : &str,
+		     opts : &CombineOptions) -> IdaResult<Vec<ShareFile>> {
+    combine_in(&LocalStore::new(opts.output).with_mmap(opts.mmap), infiles,
+	       outfile, opts)
+}
+
+// As combine_files(), with the shares and outfile in store
+pub fn combine_in(store : &dyn ShareStore, infiles : &[&str], outfile : &str,
+		  opts : &CombineOptions) -> IdaResult<Vec<ShareFile>> {
+    let mut set = ShareSet::open_in(store, infiles, true, opts.rows())?;
+    if let Some(manifest) = &opts.manifest {
+	if set.k() != manifest.k {
+	    return Err(IdaError::new(ErrorClass::Mismatch, format!(
+		"Shares have k = {}, but the manifest says {}",
+		set.k(), manifest.k)))
+	}
+    }
+
+    // All error-checking complete, so open output file
+    let mut out = store.create_sized(outfile, set.chunk_next())?;
+    let mut hash = Sha256::new();
+    set.decode(opts, |plain| {
+	hash.update(plain);
+	out.write_all(plain).map_err(|e| IdaError::io(outfile, e))
+    })?;
+    check_output(opts.manifest.as_ref(), hash, set.chunk_next(), outfile)?;
+    out.commit()?;
+    Ok(set.used())
+}
+
+// Make sure a rebuilt file is the one the manifest describes. This
+// catches damage that share lengths can't, since there's nothing
+// else to check the data against with only k shares.
+pub fn check_output(manifest : Option<&Manifest>, hash : Sha256,
+		    size : usize, outfile : &str) -> IdaResult<()> {
+    let manifest = match manifest {
+	Some(manifest) => manifest,
+	None => return Ok(()),
+    };
+    let hash = hash.hex();
+    if size != manifest.size || hash != manifest.sha256 {
+	return Err(IdaError::new(ErrorClass::Damaged, format!(
+	    "{}: rebuilt file doesn't match the manifest ({} bytes, sha256 {}; \
+	     expected {} bytes, sha256 {}). Use verify to find the bad share.",
+	    outfile, size, hash, manifest.size, manifest.sha256)))
+    }
+    Ok(())
+}
+
+pub struct ShareCheck {
+    pub name    : String,
+    pub problem : Option<String>,	// None if the share is fine
+}
+
+// Check that a set of shares are consistent with each other
+//
+// The first k shares are used to rebuild the original file, which is
+// then re-encoded using the transform rows of the remaining shares
+// and compared with their contents. A mismatch means that either
+// that share or one of the first k is damaged; running again with a
+// different share order will narrow it down.
+//
+// Shares of the wrong length are reported, but left out of the
+// comparison. With only k shares there's nothing to compare against,
+// so only their lengths are checked.
+pub fn verify_shares(infiles : &[&str], opts : &CombineOptions)
+		     -> IdaResult<Vec<ShareCheck>> {
+    verify_in(&LocalStore::new(opts.output), infiles, opts)
+}
+
+// As verify_shares(), for shares in store
+pub fn verify_in(store : &dyn ShareStore, infiles : &[&str],
+		 opts : &CombineOptions) -> IdaResult<Vec<ShareCheck>> {
+    // a manifest has each share's hash, so no need to decode
+    if let Some(manifest) = &opts.manifest {
+	return Ok(infiles.iter().map(|&name| ShareCheck {
+	    name : name.to_string(),
+	    problem : manifest.check_share(store, name),
+	}).collect())
+    }
+    let rows = opts.rows();
+    let mut usable = Vec::with_capacity(infiles.len());
+    let mut bad_len = Vec::new();
+    for &name in infiles.iter() {
+	let (_, header, len) = open_share(store, name, rows)?;
+	match share_len_problem(&header, len) {
+	    None => usable.push(name),
+	    Some(problem) => bad_len.push(ShareCheck {
+		name : name.to_string(), problem : Some(problem) }),
+	}
+    }
+    // if that leaves too few, the first bad share is the real problem
+    let mut set = match ShareSet::open_in(store, &usable, false, rows) {
+	Err(e) if e.class == ErrorClass::Quorum && !bad_len.is_empty() => {
+	    let bad = &bad_len[0];
+	    return Err(IdaError::new(ErrorClass::Damaged, format!(
+		"{}: {}", bad.name, bad.problem.as_ref().unwrap())))
+	},
+	result => result?,
+    };
+    let k = set.k();
+    let field = new_field(opts.poly);
+    let use_ref = opts.use_ref();
+
+    let mut checks : Vec<ShareCheck> = set.names.iter()
+	.map(|name| ShareCheck { name : name.clone(), problem : None })
+	.collect();
+    let mut extra_files = set.files.split_off(k);
+    let extras = extra_files.len();
+    if extras == 0 {
+	return Ok(in_order(checks, bad_len, infiles))
+    }
+
+    let mut rows = Vec::with_capacity(extras * k);
+    for header in set.headers[k..].iter() {
+	rows.extend(header.xform_data.iter())
+    }
+    let mut transform = Transform::new(&rows, extras, k, opts.bufsize, true);
+    let mut buf = vec![0u8; opts.bufsize];
+    let mut offset = 0;
+
+    set.decode(opts, |plain| {
+	let cols = encode_block(&mut transform, plain, k, &field, use_ref);
+	for (i, fh) in extra_files.iter_mut().enumerate() {
+	    let check = &mut checks[k + i];
+	    if check.problem.is_some() { continue }
+	    let got = &mut buf[..cols];
+	    if read_block(fh, &check.name, got).is_err() {
+		check.problem = Some("premature EOF".to_string());
+		continue
+	    }
+	    let [first, rest] = transform.row(i, cols);
+	    if let Some(pos) = first.iter().chain(rest.iter()).zip(got.iter())
+		.position(|(a, b)| a != b) {
+		check.problem = Some(format!("data mismatch at offset {}",
+					     offset + pos));
+	    }
+	}
+	offset += cols;
+	Ok(())
+    })?;
+    Ok(in_order(checks, bad_len, infiles))
+}
+
+// Report shares in the order they were given
+fn in_order(mut checks : Vec<ShareCheck>, bad_len : Vec<ShareCheck>,
+	    infiles : &[&str]) -> Vec<ShareCheck> {
+    checks.extend(bad_len);
+    checks.sort_by_key(|c| infiles.iter().position(|&f| f == c.name));
+    checks
+}
+
+// Regenerate shares from any k existing ones
+//
+// Each new share gets one transform row (k values) from rows, and is
+// written to the corresponding file in outfiles. Returns the new
+// shares.
+pub fn repair_shares(infiles : &[&str], rows : &[Vec<u8>],
+		     outfiles : &[String], opts : &CombineOptions)
+		     -> IdaResult<Vec<ShareFile>> {
+    repair_in(&LocalStore::new(opts.output), infiles, rows, outfiles, opts)
+}
+
+// As repair_shares(), with the old and new shares in store
+pub fn repair_in(store : &dyn ShareStore, infiles : &[&str],
+		 rows : &[Vec<u8>], outfiles : &[String],
+		 opts : &CombineOptions) -> IdaResult<Vec<ShareFile>> {
+    assert_eq!(rows.len(), outfiles.len());
+    let mut set = ShareSet::open_in(store, infiles, true, opts.rows())?;
+    let k = set.k();
+    let field = new_field(opts.poly);
+    let use_ref = opts.use_ref();
+
+    let mut matrix = Vec::with_capacity(rows.len() * k);
+    for row in rows.iter() {
+	if row.len() != k {
+	    return Err(IdaError::new(ErrorClass::Usage, format!(
+		"Transform row should have {} values", k)))
+	}
+	matrix.extend(row.iter())
+    }
+
+    let mut handles = Vec::with_capacity(outfiles.len());
+    let mut written = Vec::with_capacity(outfiles.len());
+    for (name, row) in outfiles.iter().zip(rows.iter()) {
+	let mut out = store.create_write(name)?;
+	let first = &set.headers[0];
+	let header = HeaderV1 {
+	    k, w : first.w,
+	    chunk_start : first.chunk_start,
+	    chunk_next  : first.chunk_next,
+	    large_k  : first.large_k,
+	    large_w  : first.large_w,
+	    is_final : first.is_final,
+	    xform    : true,
+	    xform_data : row.clone(),
+	};
+	write_sharefile_header(&mut out, &header)
+	    .map_err(|e| IdaError::io(name, e))?;
+	handles.push(out);
+	written.push(ShareFile { name : name.clone(), header });
+    }
+
+    let mut transform = Transform::new(&matrix, rows.len(), k,
+				       opts.bufsize, true);
+    set.decode(opts, |plain| {
+	let cols = encode_block(&mut transform, plain, k, &field, use_ref);
+	for (i, out) in handles.iter_mut().enumerate() {
+	    for part in transform.row(i, cols).iter() {
+		out.write_all(part)
+		    .map_err(|e| IdaError::io(&outfiles[i], e))?;
+	    }
+	}
+	Ok(())
+    })?;
+    commit_all(handles)?;
+    Ok(written)
+}
+
+// Use a manifest to find missing or damaged shares and rewrite them
+// under their original names, from the shares that are still good.
+// wanted (counting from zero) limits which shares are rewritten.
+// Damaged shares are only replaced with opts.output.force. Returns
+// the new shares.
+pub fn repair_manifest(store : &dyn ShareStore, wanted : Option<&[usize]>,
+		       opts : &CombineOptions) -> IdaResult<Vec<ShareFile>> {
+    let manifest = opts.manifest.as_ref().ok_or_else(|| IdaError::new(
+	ErrorClass::Usage, "No manifest to repair from"))?;
+    let mut good = Vec::new();
+    let mut bad = Vec::new();
+    for (i, share) in manifest.shares.iter().enumerate() {
+	match manifest.check_share(store, &share.name) {
+	    None => good.push(&share.name[..]),
+	    Some(problem) => {
+		eprintln!("{}: {}", share.name, problem);
+		bad.push(i)
+	    },
+	}
+    }
+    let wanted = match wanted {
+	Some(wanted) => wanted.to_vec(),
+	None => bad,
+    };
+    if let Some(&share) = wanted.iter().find(|&&i| i >= manifest.shares.len()) {
+	return Err(IdaError::new(ErrorClass::Usage, format!(
+	    "Share number {} not in 1..{}", share + 1, manifest.shares.len())))
+    }
+    if wanted.is_empty() { return Ok(Vec::new()) }
+    let rows : Vec<Vec<u8>> = wanted.iter()
+	.map(|&i| manifest.shares[i].transform.clone()).collect();
+    let outfiles : Vec<String> = wanted.iter()
+	.map(|&i| manifest.shares[i].name.clone()).collect();
+    good.retain(|name| !outfiles.iter().any(|out| out == name));
+    repair_in(store, &good, &rows, &outfiles, opts)
+}
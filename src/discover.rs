@@ -0,0 +1,462 @@
+// Find a usable set of shares in a directory or glob
+//
+// Rather than having to name exactly k share files, combine can be
+// pointed at directories (every file in them is a candidate) or globs
+// such as "backup/16m-*" or "/mnt/disk*/16m.*.sf" (wildcards "*" and
+// "?" can appear in any path component). Given a naming template (see
+// naming.rs), only files whose names fit it are considered, and with
+// no directories or globs the template itself says where to look.
+// discover_in() does the same for everything in a ShareStore, such as
+// a set of tar archives.
+//
+// Every candidate's header is read, and files are grouped by the
+// share set they belong to (same k, chunk_start, chunk_next and final
+// flag, and made from the same file). Files that can't be read, have
+// bad headers or have the wrong amount of data are skipped, as are
+// duplicate copies of a share (same transform row).
+//
+// Shares without transform rows get them from the manifest or
+// --matrix, as when they're listed.
+//
+// Headers don't record the original file's name, so that comes from
+// the manifest (if the share is in it), the template's {name} field,
+// or the "<file>-block.<N>" or "<file>-<c>-<s>.sf" naming. Only the
+// last path component counts, since shares of one file can be spread
+// over several directories. A share whose name says nothing goes with
+// the one named set its header fits. If it fits more than one, there's
+// no telling which file it came from, and combine gives up rather than
+// risk mixing shares of different files.
+//
+// If exactly one group has enough shares, k of them with independent
+// transform rows are combined. Should reading one of those fail part
+// way through, it's dropped and the combine is restarted with the
+// remaining shares.
+
+use std::fs;
+use std::io::prelude::*;
+use std::path::Path;
+
+use crate::*;
+use crate::codec::*;
+use crate::combine::*;
+use crate::error::*;
+use crate::output::*;
+use crate::naming::*;
+use crate::manifest::*;
+use crate::sha256::*;
+use crate::store::*;
+
+// "*" matches any run of characters, "?" any single character
+fn wildcard(pattern : &[u8], name : &[u8]) -> bool {
+    match pattern.split_first() {
+	None => name.is_empty(),
+	Some((b'*', rest)) => {
+	    (0..=name.len()).any(|skip| wildcard(rest, &name[skip..]))
+	},
+	Some((b'?', rest)) => {
+	    !name.is_empty() && wildcard(rest, &name[1..])
+	},
+	Some((c, rest)) => {
+	    name.first() == Some(c) && wildcard(rest, &name[1..])
+	},
+    }
+}
+
+// List candidate files for a directory or glob, sorted by name
+pub fn find_candidates(spec : &str) -> IdaResult<Vec<String>> {
+    let path = Path::new(spec);
+    let mut names = Vec::new();
+    if path.is_dir() {
+	list_files(path, None, &mut names)?;
+    } else if !spec.contains(['*', '?']) {
+	return Err(IdaError::new(ErrorClass::Usage, format!(
+	    "{}: not a directory or glob", spec)))
+    } else {
+	// for a bare "pattern", names are returned without a leading "./"
+	let (start, rest) = match spec.strip_prefix('/') {
+	    Some(rest) => ("/", rest),
+	    None => ("", spec),
+	};
+	let components : Vec<&str> = rest.split('/')
+	    .filter(|c| !c.is_empty()).collect();
+	expand(Path::new(start), &components, &mut names)?;
+    }
+    names.sort();
+    Ok(names)
+}
+
+// Follow a glob down through directories. Earlier components match
+// directories, and the last matches files or directories to look in.
+fn expand(dir : &Path, components : &[&str], names : &mut Vec<String>)
+	  -> IdaResult<()> {
+    let (first, rest) = match components.split_first() {
+	Some(split) => split,
+	None => return Ok(()),
+    };
+    if rest.is_empty() {
+	list_files(dir, Some(first), names)?;
+    }
+    if !first.contains(['*', '?']) {
+	if rest.is_empty() { return Ok(()) }
+	return expand(&dir.join(first), rest, names)
+    }
+    let listing = if dir == Path::new("") { Path::new(".") } else { dir };
+    let entries = match fs::read_dir(listing) {
+	Ok(entries) => entries,
+	Err(_) => return Ok(()),	// other matches may still work
+    };
+    let mut subdirs = Vec::new();
+    for entry in entries.flatten() {
+	let name = entry.file_name().to_string_lossy().into_owned();
+	if entry.path().is_dir() && wildcard(first.as_bytes(), name.as_bytes()) {
+	    subdirs.push(name);
+	}
+    }
+    subdirs.sort();
+    for sub in subdirs {
+	if rest.is_empty() {
+	    list_files(&dir.join(sub), None, names)?;
+	} else {
+	    expand(&dir.join(sub), rest, names)?;
+	}
+    }
+    Ok(())
+}
+
+// Files in dir, optionally only those matching pattern
+fn list_files(dir : &Path, pattern : Option<&str>, names : &mut Vec<String>)
+	      -> IdaResult<()> {
+    let listing = if dir == Path::new("") { Path::new(".") } else { dir };
+    let entries = fs::read_dir(listing)
+	.map_err(|e| IdaError::io(&listing.display().to_string(), e))?;
+    for entry in entries {
+	let entry = entry.map_err(|e| IdaError::io(&listing.display().to_string(), e))?;
+	if !entry.file_type().map(|t| t.is_file()).unwrap_or(false) {
+	    continue
+	}
+	let file_name = entry.file_name().to_string_lossy().into_owned();
+	if let Some(pattern) = pattern {
+	    if !wildcard(pattern.as_bytes(), file_name.as_bytes()) {
+		continue
+	    }
+	}
+	names.push(dir.join(&file_name).to_string_lossy().into_owned());
+    }
+    Ok(())
+}
+
+// Shares that look like they were made from the same file
+pub struct ShareGroup {
+    pub name : Option<String>,			// original file, if known
+    pub k : usize,
+    pub chunk_start : usize,
+    pub chunk_next  : usize,
+    pub is_final    : bool,
+    pub shares : Vec<ShareFile>,		// one per transform row
+    pub duplicates : Vec<(String, String)>,	// (skipped, kept)
+}
+
+impl ShareGroup {
+
+    fn matches(&self, header : &HeaderV1) -> bool {
+	header.k == self.k && header.chunk_start == self.chunk_start
+	    && header.chunk_next == self.chunk_next
+	    && header.is_final == self.is_final
+    }
+
+    // Add a share, unless it's a copy of one we already have
+    fn add(&mut self, share : ShareFile) {
+	let same = self.shares.iter()
+	    .find(|s| s.header.xform_data == share.header.xform_data)
+	    .map(|s| s.name.clone());
+	match same {
+	    Some(kept) => self.duplicates.push((share.name, kept)),
+	    None => self.shares.push(share),
+	}
+    }
+
+    // How the group is described in messages
+    fn describe(&self) -> String {
+	match &self.name {
+	    Some(name) => format!("{} ({} bytes, k = {})",
+				  name, self.chunk_next, self.k),
+	    None => format!("{}-byte file, k = {}", self.chunk_next, self.k),
+	}
+    }
+
+    // Pick k shares with independent transform rows (in the field
+    // made from poly), skipping any marked in exclude. Returns indexes
+    // into shares.
+    pub fn select(&self, exclude : &[bool], poly : u16)
+		  -> Option<Vec<usize>> {
+	let field = new_field(poly);
+	let k = self.k;
+	let mut picked = Vec::with_capacity(k);
+	let mut rows = Vec::with_capacity(k * k);
+	for (i, share) in self.shares.iter().enumerate() {
+	    if exclude[i] { continue }
+	    rows.extend(share.header.xform_data.iter());
+	    if rank(&field, &rows, picked.len() + 1, k) == picked.len() + 1 {
+		picked.push(i);
+		if picked.len() == k { return Some(picked) }
+	    } else {
+		rows.truncate(picked.len() * k);
+	    }
+	}
+	None
+    }
+}
+
+pub struct Discovery {
+    pub groups   : Vec<ShareGroup>,
+    pub rejected : Vec<(String, String)>,	// (file, reason)
+    pub ambiguous : Vec<(String, Vec<String>)>,	// (file, sets it fits)
+}
+
+// Last path component of the original file's name, going by the
+// share's name
+fn original_name(store : &dyn ShareStore, name : &str,
+		 template : Option<&NameTemplate>,
+		 rows : Option<RowSource>) -> Option<String> {
+    let name = store.inner_name(name);
+    let manifest = match rows {
+	Some(RowSource::Manifest(manifest)) => Some(manifest),
+	_ => None,
+    };
+    let base = match (manifest.filter(|m| m.share(name).is_some()), template) {
+	(Some(manifest), _) => manifest.input.clone(),
+	(None, Some(template)) => template.match_name(name)?.name?,
+	(None, None) => split_share_name(name)?.0.to_string(),
+    };
+    Path::new(&base).file_name().map(|f| f.to_string_lossy().into_owned())
+}
+
+// Look through all the specs for shares. Only files that fit template
+// (if given) are considered; with no specs, the template is turned
+// into a glob. rows gives transform rows for shares without them.
+pub fn discover(specs : &[&str], template : Option<&NameTemplate>,
+		rows : Option<RowSource>) -> IdaResult<Discovery> {
+
+    let globbed;
+    let specs = match template {
+	Some(template) if specs.is_empty() => {
+	    globbed = template.glob(None);
+	    vec![&globbed[..]]
+	},
+	_ => specs.to_vec(),
+    };
+    let mut candidates = Vec::new();
+    for spec in specs.iter() {
+	for name in find_candidates(spec)? {
+	    if !candidates.contains(&name) { candidates.push(name) }
+	}
+    }
+    if let Some(template) = template {
+	candidates.retain(|name| template.match_name(name).is_some());
+    }
+    Ok(group_shares(&LocalStore::new(OutputOptions::default()), candidates,
+		    template, rows))
+}
+
+// As discover(), looking at everything in store (except manifests)
+pub fn discover_in(store : &dyn ShareStore, template : Option<&NameTemplate>,
+		   rows : Option<RowSource>) -> IdaResult<Discovery> {
+    let mut candidates = store.list()?;
+    candidates.retain(|name| !name.ends_with(MANIFEST_SUFFIX));
+    if let Some(template) = template {
+	candidates.retain(|name| template.match_name(name).is_some());
+    }
+    Ok(group_shares(store, candidates, template, rows))
+}
+
+fn group_shares(store : &dyn ShareStore, candidates : Vec<String>,
+		template : Option<&NameTemplate>, rows : Option<RowSource>)
+		-> Discovery {
+    let mut found = Discovery {
+	groups : Vec::new(), rejected : Vec::new(), ambiguous : Vec::new(),
+    };
+    for name in candidates {
+	let (_, header, _) = match probe_share_in(store, &name, rows) {
+	    Ok(probe) => probe,
+	    Err(e) => { found.rejected.push((name, e.message)); continue },
+	};
+	let original = original_name(store, &name, template, rows);
+	let index = match found.groups.iter()
+	    .position(|g| g.name == original && g.matches(&header)) {
+	    Some(index) => index,
+	    None => {
+		found.groups.push(ShareGroup {
+		    name : original,
+		    k : header.k,
+		    chunk_start : header.chunk_start,
+		    chunk_next  : header.chunk_next,
+		    is_final    : header.is_final,
+		    shares : Vec::new(), duplicates : Vec::new(),
+		});
+		found.groups.len() - 1
+	    },
+	};
+	found.groups[index].add(ShareFile { name, header });
+    }
+
+    // shares with names that don't say which file they're from
+    let (named, unnamed) : (Vec<_>, Vec<_>) = found.groups.into_iter()
+	.partition(|g| g.name.is_some());
+    found.groups = named;
+    for group in unnamed {
+	let fits : Vec<usize> = found.groups.iter().enumerate()
+	    .filter(|(_, g)| g.matches(&group.shares[0].header))
+	    .map(|(i, _)| i)
+	    .collect();
+	match fits.len() {
+	    0 => found.groups.push(group),
+	    1 => {
+		let into = &mut found.groups[fits[0]];
+		into.duplicates.extend(group.duplicates);
+		for share in group.shares { into.add(share) }
+	    },
+	    _ => {
+		let sets : Vec<String> = fits.iter()
+		    .filter_map(|&i| found.groups[i].name.clone())
+		    .collect();
+		let files = group.shares.into_iter().map(|s| s.name)
+		    .chain(group.duplicates.into_iter().map(|(d, _)| d));
+		for file in files {
+		    found.ambiguous.push((file, sets.clone()));
+		}
+	    },
+	}
+    }
+    found
+}
+
+// Combine whatever complete share set can be found from spec.
+// Returns the shares that were used.
+pub fn combine_discovered(specs : &[&str], template : Option<&NameTemplate>,
+			  outfile : &str, opts : &CombineOptions)
+			  -> IdaResult<Vec<ShareFile>> {
+    let found = discover(specs, template, opts.rows())?;
+    let spec = match template {
+	Some(template) if specs.is_empty() => template.glob(None),
+	_ => specs.join(" "),
+    };
+    combine_found(&LocalStore::new(OutputOptions::default()).with_mmap(opts.mmap),
+		  found, &spec,
+		  outfile, opts.manifest.as_ref(), opts)
+}
+
+// Combine whatever complete share set can be found in store (eg, a
+// UnionStore of tar archives); spec describes the store in messages.
+// If the store has a manifest, the output is checked against it.
+pub fn combine_store(store : &dyn ShareStore, spec : &str,
+		     template : Option<&NameTemplate>, outfile : &str,
+		     opts : &CombineOptions) -> IdaResult<Vec<ShareFile>> {
+    let stored = match &opts.manifest {
+	Some(_) => None,
+	None => Manifest::find_in(store).unwrap_or_else(|e| {
+	    eprintln!("Ignoring manifest: {}", e);
+	    None
+	}),
+    };
+    let manifest = opts.manifest.as_ref().or(stored.as_ref());
+    let found = discover_in(store, template, rows(manifest, opts))?;
+    combine_found(store, found, spec, outfile, manifest, opts)
+}
+
+// Where to find missing transform rows, as CombineOptions::rows(), but
+// with the manifest that's actually in use
+fn rows<'a>(manifest : Option<&'a Manifest>, opts : &'a CombineOptions)
+	    -> Option<RowSource<'a>> {
+    manifest.map(RowSource::Manifest)
+	.or_else(|| opts.matrix.as_deref().map(RowSource::Matrix))
+}
+
+fn combine_found(store : &dyn ShareStore, found : Discovery, spec : &str,
+		 outfile : &str, manifest : Option<&Manifest>,
+		 opts : &CombineOptions) -> IdaResult<Vec<ShareFile>> {
+    let rows = rows(manifest, opts);
+    for (_, reason) in found.rejected.iter() {
+	eprintln!("Skipping {}", reason);
+    }
+    for group in found.groups.iter() {
+	for (skipped, kept) in group.duplicates.iter() {
+	    eprintln!("Skipping {}: same share as {}", skipped, kept);
+	}
+    }
+    if !found.ambiguous.is_empty() {
+	let mut msg = format!("Can't tell which file some shares in {} are from",
+			      spec);
+	for (file, sets) in found.ambiguous.iter() {
+	    msg += &format!("\n  {} could be from {}", file, sets.join(" or "));
+	}
+	msg += "\nRename or remove them, or use --template";
+	return Err(IdaError::new(ErrorClass::Mismatch, msg))
+    }
+
+    let none = |g : &ShareGroup| vec![false; g.shares.len()];
+    let viable : Vec<&ShareGroup> = found.groups.iter()
+	.filter(|g| g.select(&none(g), opts.poly).is_some())
+	.collect();
+    let group = match viable.len() {
+	1 => viable[0],
+	0 => {
+	    let mut msg = format!("No complete share set found in {}", spec);
+	    for g in found.groups.iter() {
+		msg += &format!("\n  {} of {} shares for {}",
+				g.shares.len(), g.k, g.describe());
+	    }
+	    return Err(IdaError::new(ErrorClass::Quorum, msg))
+	},
+	_ => {
+	    let mut msg = format!("Found more than one share set in {}", spec);
+	    for g in viable.iter() {
+		msg += &format!("\n  {}: {}", g.describe(),
+				g.shares.iter().map(|s| &s.name[..])
+				.collect::<Vec<_>>().join(" "));
+	    }
+	    return Err(IdaError::new(ErrorClass::Mismatch, msg))
+	},
+    };
+
+    let mut failed = none(group);
+    loop {
+	let picked = group.select(&failed, opts.poly).ok_or_else(|| IdaError::new(
+	    ErrorClass::Quorum, format!(
+		"Not enough readable shares left in {} to satisfy quorum {}",
+		spec, group.k)))?;
+
+	// things might have changed since we looked
+	if let Some(&i) = picked.iter()
+	    .find(|&&i| probe_share_in(store, &group.shares[i].name, rows)
+		   .is_err()) {
+	    eprintln!("Can't open {} any more; trying others",
+		      group.shares[i].name);
+	    failed[i] = true;
+	    continue
+	}
+
+	let names : Vec<&str> = picked.iter()
+	    .map(|&i| &group.shares[i].name[..])
+	    .collect();
+	let mut set = ShareSet::open_in(store, &names, true, rows)?;
+	let mut out = LocalStore::new(opts.output).with_mmap(opts.mmap)
+	    .create_sized(outfile, set.chunk_next())?;
+	let mut hash = Sha256::new();
+	let result = set.decode(opts, |plain| {
+	    hash.update(plain);
+	    out.write_all(plain).map_err(|e| IdaError::io(outfile, e))
+	});
+	match (result, set.failed) {
+	    (Ok(()), _) => {
+		check_output(manifest, hash, set.chunk_next(), outfile)?;
+		out.commit()?;
+		return Ok(set.used())
+	    },
+	    (Err(e), Some(bad)) => {
+		eprintln!("{}; trying others", e);
+		failed[picked[bad]] = true;
+	    },
+	    (Err(e), None) => return Err(e),
+	}
+    }
+}
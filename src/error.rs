@@ -0,0 +1,69 @@
+// Errors from split, combine and friends
+//
+// Each error has a class, which decides the exit code that the
+// command-line tools use, and a message that names the file involved
+// (where there is one). Expected problems like a missing share or a
+// bad header should always come back as one of these rather than a
+// panic.
+
+use std::fmt;
+use std::io;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    Usage,			// bad options or parameters
+    Io,				// couldn't open, read or write a file
+    BadHeader,			// not a sharefile, or one we can't handle
+    Mismatch,			// shares don't belong together
+    Quorum,			// not enough usable shares
+    Damaged,			// share data is short or inconsistent
+    SelfTest,			// a multiply gave a wrong answer
+}
+
+impl ErrorClass {
+
+    // 1 is left for anything unexpected (including panics, which
+    // exit with 101)
+    pub fn exit_code(self) -> i32 {
+	match self {
+	    ErrorClass::Usage     => 2,
+	    ErrorClass::Io        => 3,
+	    ErrorClass::BadHeader => 4,
+	    ErrorClass::Mismatch  => 5,
+	    ErrorClass::Quorum    => 6,
+	    ErrorClass::Damaged   => 7,
+	    ErrorClass::SelfTest  => 8,
+	}
+    }
+}
+
+#[derive(Debug)]
+pub struct IdaError {
+    pub class   : ErrorClass,
+    pub message : String,
+}
+
+impl IdaError {
+
+    pub fn new<S : Into<String>>(class : ErrorClass, message : S) -> IdaError {
+	IdaError { class, message : message.into() }
+    }
+
+    pub fn io(file : &str, e : io::Error) -> IdaError {
+	IdaError::new(ErrorClass::Io, format!("{}: {}", file, e))
+    }
+
+    pub fn exit_code(&self) -> i32 {
+	self.class.exit_code()
+    }
+}
+
+impl fmt::Display for IdaError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+	f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for IdaError {}
+
+pub type IdaResult<T> = Result<T, IdaError>;
@@ -0,0 +1,419 @@
+// Look over a collection of share files without decoding anything
+//
+// This is meant for triage: given whatever files survived, which
+// share is which, do they belong together, and can the original
+// still be rebuilt? Unlike ShareSet::open, nothing here is fatal.
+// Files that can't be read, or that disagree with the rest, are
+// reported along with everything else.
+//
+// Shares are numbered from 1 (as in "<file>-block.<N>") by matching
+// their transform row against the Cauchy matrix made from the key.
+// With the default key, row i doesn't depend on n, so no other
+// information is needed.
+
+use std::fs::File;
+use std::io;
+use std::fs::metadata;
+use std::io::prelude::*;
+
+use crate::*;
+use crate::codec::*;
+
+pub struct ShareReport {
+    pub name     : String,
+    pub header   : Option<HeaderV1>,	// None if it couldn't be read
+    pub data_len : usize,
+    pub expected_len : usize,
+    pub index    : Option<usize>,	// share number, if known
+    pub problems : Vec<String>,
+}
+
+impl ShareReport {
+    // Can this share be used to decode its chunk?
+    pub fn usable(&self) -> bool {
+	self.header.is_some() && self.problems.is_empty()
+    }
+}
+
+pub struct ChunkReport {
+    pub k : usize,
+    pub chunk_start : usize,
+    pub chunk_next  : usize,
+    pub is_final    : bool,
+    pub usable      : usize,	// usable shares, without duplicates
+    pub independent : usize,	// rank of their transform rows
+}
+
+impl ChunkReport {
+    pub fn decodable(&self) -> bool {
+	self.independent >= self.k
+    }
+}
+
+pub struct SetReport {
+    pub shares : Vec<ShareReport>,
+    pub chunks : Vec<ChunkReport>,	// sorted by chunk_start
+    pub gaps   : Vec<(usize, usize)>,	// byte ranges with no chunk
+    pub complete : bool,		// chunks run from 0 to a final one
+}
+
+impl SetReport {
+
+    pub fn decodable(&self) -> bool {
+	self.complete && !self.chunks.is_empty()
+	    && self.chunks.iter().all(|c| c.decodable())
+    }
+
+    // How many more shares (of each chunk) can be lost
+    pub fn spare(&self) -> Option<usize> {
+	if !self.decodable() { return None }
+	self.chunks.iter().map(|c| c.independent - c.k).min()
+    }
+}
+
+fn read_share(name : &str) -> Result<(HeaderV1, usize), String> {
+    let mut fh = File::open(name).map_err(|e| e.to_string())?;
+    let header = read_sharefile_header(&mut fh)?;
+    let start = fh.stream_position().map_err(|e| e.to_string())?;
+    let len = metadata(name).map_err(|e| e.to_string())?.len();
+    Ok((header, len.saturating_sub(start) as usize))
+}
+
+// Share number (from 1) of a transform row
+fn share_index(header : &HeaderV1, key : Option<&[u8]>) -> Option<usize> {
+    let k = header.k;
+    if header.w != 1 || !header.xform || k == 0 || k >= 255 {
+	return None
+    }
+    let key = match key {
+	Some(key) if key.len() > k => key.to_vec(),
+	Some(_) => return None,
+	None => default_key(k, 255 - k),
+    };
+    let n = key.len() - k;
+    if check_key(&key, k, n).is_err() { return None }
+    let matrix = transform_matrix(&new_field(DEFAULT_POLY), &key, k, n);
+    matrix.chunks(k).position(|row| row == &header.xform_data[..])
+	.map(|i| i + 1)
+}
+
+// The most common value of f among readable shares
+fn majority<F : Fn(&HeaderV1) -> usize>(shares : &[ShareReport], f : F)
+					-> Option<usize> {
+    let mut counts : Vec<(usize, usize)> = Vec::new();
+    for header in shares.iter().filter_map(|s| s.header.as_ref()) {
+	let value = f(header);
+	match counts.iter_mut().find(|(v, _)| *v == value) {
+	    Some((_, count)) => *count += 1,
+	    None => counts.push((value, 1)),
+	}
+    }
+    // first-seen wins a tie
+    let mut best : Option<(usize, usize)> = None;
+    for (value, count) in counts {
+	if best.is_none_or(|(_, c)| count > c) { best = Some((value, count)) }
+    }
+    best.map(|(value, _)| value)
+}
+
+pub fn inspect_shares(files : &[&str], key : Option<&[u8]>) -> SetReport {
+    let mut shares : Vec<ShareReport> = files.iter().map(|name| {
+	match read_share(name) {
+	    Ok((header, data_len)) => {
+		let expected_len = header.share_len();
+		ShareReport {
+		    name : name.to_string(),
+		    index : share_index(&header, key),
+		    header : Some(header),
+		    data_len, expected_len,
+		    problems : Vec::new(),
+		}
+	    },
+	    Err(e) => ShareReport {
+		name : name.to_string(), header : None,
+		data_len : 0, expected_len : 0, index : None,
+		problems : vec![e],
+	    },
+	}
+    }).collect();
+
+    // things that stop a share being used at all
+    let common_k = majority(&shares, |h| h.k);
+    let common_w = majority(&shares, |h| h.w);
+    for share in shares.iter_mut() {
+	let header = match &share.header { Some(h) => h, None => continue };
+	let mut problems = Vec::new();
+	if Some(header.k) != common_k {
+	    problems.push(format!("k = {} differs from k = {} used by most shares",
+				  header.k, common_k.unwrap()));
+	}
+	if Some(header.w) != common_w {
+	    problems.push(format!("w = {} differs from w = {} used by most shares",
+				  header.w, common_w.unwrap()));
+	}
+	if header.w != 1 {
+	    problems.push(format!("{}-byte fields not supported", header.w));
+	}
+	if !header.xform || header.xform_data.len() != header.k * header.w {
+	    problems.push("no transform row in header".to_string());
+	}
+	if header.chunk_next <= header.chunk_start {
+	    problems.push("empty chunk range".to_string());
+	}
+	if share.data_len != share.expected_len {
+	    problems.push(format!("has {} bytes of data, expected {}",
+				  share.data_len, share.expected_len));
+	}
+	share.problems = problems;
+    }
+
+    // duplicates of an earlier usable share in the same chunk
+    for i in 0..shares.len() {
+	if !shares[i].usable() { continue }
+	let header = shares[i].header.as_ref().unwrap();
+	let same = shares[..i].iter().find(|other| {
+	    let h = match &other.header { Some(h) => h, None => return false };
+	    other.usable() && h.chunk_start == header.chunk_start
+		&& h.chunk_next == header.chunk_next
+		&& h.xform_data == header.xform_data
+	}).map(|other| other.name.clone());
+	if let Some(same) = same {
+	    shares[i].problems.push(format!(
+		"duplicate of {} (same transform row)", same));
+	}
+    }
+
+    // group usable shares into chunks
+    let mut chunks : Vec<ChunkReport> = Vec::new();
+    let mut rows : Vec<Vec<u8>> = Vec::new();
+    for header in shares.iter().filter(|s| s.usable())
+	.map(|s| s.header.as_ref().unwrap()) {
+	let index = chunks.iter().position(|c| c.chunk_start == header.chunk_start
+					   && c.chunk_next == header.chunk_next);
+	let index = index.unwrap_or_else(|| {
+	    chunks.push(ChunkReport {
+		k : header.k,
+		chunk_start : header.chunk_start,
+		chunk_next  : header.chunk_next,
+		is_final    : header.is_final,
+		usable : 0, independent : 0,
+	    });
+	    rows.push(Vec::new());
+	    chunks.len() - 1
+	});
+	chunks[index].usable += 1;
+	chunks[index].is_final |= header.is_final;
+	rows[index].extend(header.xform_data.iter());
+    }
+    let field = new_field(DEFAULT_POLY);
+    for (chunk, rows) in chunks.iter_mut().zip(rows.iter()) {
+	chunk.independent = rank(&field, rows, chunk.usable, chunk.k);
+    }
+    chunks.sort_by_key(|c| (c.chunk_start, c.chunk_next));
+
+    // which parts of the file are covered
+    let mut gaps = Vec::new();
+    let mut covered = 0;
+    let mut complete = false;
+    for chunk in chunks.iter() {
+	if chunk.chunk_start > covered {
+	    gaps.push((covered, chunk.chunk_start));
+	}
+	covered = covered.max(chunk.chunk_next);
+	if chunk.is_final && chunk.chunk_next == covered {
+	    complete = gaps.is_empty();
+	}
+    }
+
+    SetReport { shares, chunks, gaps, complete }
+}
+
+// Annotated dump of a sharefile header
+//
+// read_sharefile_header gives up at the first thing it doesn't like,
+// which is no help in finding out what's wrong with a damaged share.
+// This walks the same fields, but carries on past bad values (only
+// running out of file stops it) and records where each field is and
+// what it decodes as.
+
+pub struct HeaderField {
+    pub offset : usize,
+    pub bytes  : Vec<u8>,
+    pub name   : &'static str,
+    pub notes  : Vec<String>,		// one line each
+    pub bad    : bool,
+}
+
+pub struct HeaderDump {
+    pub fields : Vec<HeaderField>,
+    pub truncated : bool,		// file ended inside the header
+    pub payload_offset : Option<usize>,
+    pub payload_len  : usize,
+    pub expected_len : Option<usize>,
+}
+
+impl HeaderDump {
+    pub fn bad(&self) -> bool {
+	self.truncated || self.fields.iter().any(|f| f.bad)
+    }
+}
+
+struct Dumper<'a> {
+    data : &'a [u8],
+    offset : usize,
+    fields : Vec<HeaderField>,
+}
+
+impl Dumper<'_> {
+    // Take the next n bytes as a field. If the file ends first, what
+    // there is becomes a bad field and None is returned.
+    fn take(&mut self, n : usize, name : &'static str) -> Option<&mut HeaderField> {
+	let end = (self.offset + n).min(self.data.len());
+	self.fields.push(HeaderField {
+	    offset : self.offset,
+	    bytes  : self.data[self.offset..end].to_vec(),
+	    name, notes : Vec::new(), bad : false,
+	});
+	self.offset = end;
+	let field = self.fields.last_mut().unwrap();
+	if field.bytes.len() < n {
+	    Self::note(field, true, format!(
+		"{}: needs {} byte{}, but the file ends", name, n,
+		if n == 1 { "" } else { "s" }));
+	    return None
+	}
+	Some(field)
+    }
+
+    fn note(field : &mut HeaderField, bad : bool, note : String) {
+	field.notes.push(note);
+	field.bad |= bad;
+    }
+
+    // k or w, which take 1 or 2 bytes
+    fn size(&mut self, name : &'static str, large : bool, flag : &str)
+	    -> Option<usize> {
+	let field = self.take(if large { 2 } else { 1 }, name)?;
+	let value = field.bytes.iter().fold(0, |v, &b| (v << 8) | b as usize);
+	Self::note(field, value == 0, format!(
+	    "{} = {} ({} byte{}, {} {}){}", name, value,
+	    field.bytes.len(), if large { "s" } else { "" }, flag,
+	    if large { "set" } else { "clear" },
+	    if value == 0 { "; must not be 0" } else { "" }));
+	Some(value)
+    }
+
+    // chunk_start or chunk_next: a count byte, then that many bytes
+    // of value, as written by encode_length
+    fn length(&mut self, name : &'static str) -> Option<usize> {
+	let count = match self.data.get(self.offset) {
+	    Some(&count) => count as usize,
+	    None => { self.take(1, name); return None },
+	};
+	let field = self.take(1 + count, name)?;
+	let value = field.bytes[1..].iter()
+	    .fold(0u128, |v, &b| (v << 8) | b as u128);
+	Self::note(field, false, format!(
+	    "{}: {} length byte{} -> {}", name, count,
+	    if count == 1 { "" } else { "s" }, value));
+	if value > usize::MAX as u128 {
+	    Self::note(field, true, "too large for this machine".to_string());
+	    return Some(usize::MAX)
+	}
+	let value = value as usize;
+	let shortest = encode_length(value);
+	if field.bytes != shortest {
+	    Self::note(field, false, format!(
+		"not the shortest encoding (would be {})", hex(&shortest)));
+	}
+	Some(value)
+    }
+}
+
+fn hex(bytes : &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" ")
+}
+
+pub fn dump_header(data : &[u8], file_len : usize) -> HeaderDump {
+    let mut d = Dumper { data, offset : 0, fields : Vec::new() };
+    let mut dump = HeaderDump {
+	fields : Vec::new(), truncated : true,
+	payload_offset : None, payload_len : 0, expected_len : None,
+    };
+    if let Some(result) = dump_fields(&mut d) {
+	let (k, w, chunk_start, chunk_next) = result;
+	dump.truncated = false;
+	dump.payload_offset = Some(d.offset);
+	dump.payload_len = file_len.saturating_sub(d.offset);
+	if k > 0 && w > 0 && chunk_next >= chunk_start {
+	    dump.expected_len = Some((chunk_next - chunk_start)
+				     .div_ceil(k * w) * w);
+	}
+    }
+    dump.fields = d.fields;
+    dump
+}
+
+// Dump the header of a share file. Headers are normally tiny, but a
+// large k means a long transform row, so read more until it fits.
+pub fn dump_share(name : &str) -> io::Result<HeaderDump> {
+    let file_len = metadata(name)?.len() as usize;
+    let mut fh = File::open(name)?;
+    let mut data = Vec::new();
+    let mut want = 4096;
+    loop {
+	let have = data.len();
+	(&mut fh).take((want - have) as u64).read_to_end(&mut data)?;
+	let dump = dump_header(&data, file_len);
+	if !dump.truncated || data.len() < want { return Ok(dump) }
+	want *= 2;
+    }
+}
+
+// Returns (k, w, chunk_start, chunk_next) if the header is all there
+fn dump_fields(d : &mut Dumper) -> Option<(usize, usize, usize, usize)> {
+    let field = d.take(2, "magic")?;
+    let ok = field.bytes == b"SF";
+    Dumper::note(field, !ok, if ok { "magic \"SF\"".to_string() } else {
+	"magic: expected 53 46 (\"SF\")".to_string()
+    });
+
+    let field = d.take(1, "version")?;
+    let version = field.bytes[0];
+    Dumper::note(field, version != 1, if version == 1 {
+	"version 1".to_string()
+    } else {
+	format!("version {}: only version 1 is known", version)
+    });
+
+    let field = d.take(1, "options")?;
+    let options = field.bytes[0];
+    let bits = ["large_k", "large_w", "final", "transform"];
+    Dumper::note(field, false, "options".to_string());
+    for (bit, name) in bits.iter().enumerate() {
+	Dumper::note(field, false, format!(
+	    "  bit {} {:<9} = {}", bit, name, (options >> bit) & 1));
+    }
+    let high = options >> 4;
+    Dumper::note(field, high != 0, format!(
+	"  bits 4-7 {:<6} = {:x}{}", "", high,
+	if high != 0 { "; must be 0" } else { "" }));
+    let large_k = options & 0x01 != 0;
+    let large_w = options & 0x02 != 0;
+    let xform   = options & 0x08 != 0;
+
+    let k = d.size("k", large_k, "large_k")?;
+    let w = d.size("w", large_w, "large_w")?;
+    let chunk_start = d.length("chunk_start")?;
+    let chunk_next  = d.length("chunk_next")?;
+    if chunk_next <= chunk_start {
+	Dumper::note(d.fields.last_mut().unwrap(), true, format!(
+	    "must be more than chunk_start ({})", chunk_start));
+    }
+
+    if xform {
+	let field = d.take(k * w, "transform")?;
+	Dumper::note(field, false, format!("transform row (k x w = {} bytes)", k * w));
+    }
+    Some((k, w, chunk_start, chunk_next))
+}
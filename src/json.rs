@@ -0,0 +1,342 @@
+// Just enough JSON to describe shares for other programs
+//
+// Objects keep their keys in the order they were added, so output is
+// stable and reads in the same order as the text reports.
+//
+// parse() reads it back (for manifests). Numbers have to be
+// non-negative integers, since that's all we ever write.
+
+use std::fmt;
+
+use crate::*;
+
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(u64),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+
+    pub fn object() -> Json {
+	Json::Object(Vec::new())
+    }
+
+    // Add a key to an object
+    pub fn with<V : Into<Json>>(mut self, key : &str, value : V) -> Json {
+	if let Json::Object(ref mut fields) = self {
+	    fields.push((key.to_string(), value.into()));
+	}
+	self
+    }
+
+    fn write(&self, f : &mut fmt::Formatter, indent : usize) -> fmt::Result {
+	let pad = |f : &mut fmt::Formatter, n| write!(f, "{:1$}", "", n);
+	match self {
+	    Json::Null      => f.write_str("null"),
+	    Json::Bool(b)   => write!(f, "{}", b),
+	    Json::Number(n) => write!(f, "{}", n),
+	    Json::Str(s)    => write_string(f, s),
+	    Json::Array(items) => {
+		// arrays of numbers (transform rows) stay on one line
+		if items.iter().all(|i| matches!(i, Json::Number(_))) {
+		    f.write_str("[")?;
+		    for (i, item) in items.iter().enumerate() {
+			if i > 0 { f.write_str(", ")? }
+			item.write(f, indent)?;
+		    }
+		    return f.write_str("]")
+		}
+		f.write_str("[\n")?;
+		for (i, item) in items.iter().enumerate() {
+		    pad(f, indent + 2)?;
+		    item.write(f, indent + 2)?;
+		    f.write_str(if i + 1 < items.len() { ",\n" } else { "\n" })?;
+		}
+		pad(f, indent)?;
+		f.write_str("]")
+	    },
+	    Json::Object(fields) => {
+		if fields.is_empty() { return f.write_str("{}") }
+		f.write_str("{\n")?;
+		for (i, (key, value)) in fields.iter().enumerate() {
+		    pad(f, indent + 2)?;
+		    write_string(f, key)?;
+		    f.write_str(": ")?;
+		    value.write(f, indent + 2)?;
+		    f.write_str(if i + 1 < fields.len() { ",\n" } else { "\n" })?;
+		}
+		pad(f, indent)?;
+		f.write_str("}")
+	    },
+	}
+    }
+}
+
+impl Json {
+
+    pub fn parse(text : &str) -> Result<Json, String> {
+	let mut parser = Parser { text : text.as_bytes(), pos : 0 };
+	let value = parser.value()?;
+	parser.space();
+	if parser.pos < text.len() {
+	    return Err(parser.error("trailing characters"))
+	}
+	Ok(value)
+    }
+
+    // Value of a key in an object
+    pub fn get(&self, key : &str) -> Option<&Json> {
+	match self {
+	    Json::Object(fields) => fields.iter()
+		.find(|(k, _)| k == key).map(|(_, v)| v),
+	    _ => None,
+	}
+    }
+
+    pub fn as_number(&self) -> Option<u64> {
+	match self { Json::Number(n) => Some(*n), _ => None }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+	match self { Json::Str(s) => Some(s), _ => None }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+	match self { Json::Bool(b) => Some(*b), _ => None }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+	match self { Json::Array(items) => Some(items), _ => None }
+    }
+}
+
+struct Parser<'a> {
+    text : &'a [u8],
+    pos  : usize,
+}
+
+impl Parser<'_> {
+
+    fn error(&self, msg : &str) -> String {
+	format!("{} at offset {}", msg, self.pos)
+    }
+
+    fn space(&mut self) {
+	while self.pos < self.text.len()
+	    && matches!(self.text[self.pos], b' ' | b'\t' | b'\n' | b'\r') {
+	    self.pos += 1
+	}
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+	self.space();
+	self.text.get(self.pos).cloned()
+    }
+
+    fn expect(&mut self, c : u8) -> Result<(), String> {
+	if self.peek() != Some(c) {
+	    return Err(self.error(&format!("expected '{}'", c as char)))
+	}
+	self.pos += 1;
+	Ok(())
+    }
+
+    fn word(&mut self, word : &str, value : Json) -> Result<Json, String> {
+	if !self.text[self.pos..].starts_with(word.as_bytes()) {
+	    return Err(self.error("unexpected character"))
+	}
+	self.pos += word.len();
+	Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+	match self.peek() {
+	    None => Err(self.error("unexpected end")),
+	    Some(b'n') => self.word("null", Json::Null),
+	    Some(b't') => self.word("true", Json::Bool(true)),
+	    Some(b'f') => self.word("false", Json::Bool(false)),
+	    Some(b'"') => Ok(Json::Str(self.string()?)),
+	    Some(b'[') => {
+		self.pos += 1;
+		let mut items = Vec::new();
+		if self.peek() == Some(b']') { self.pos += 1; return Ok(Json::Array(items)) }
+		loop {
+		    items.push(self.value()?);
+		    match self.peek() {
+			Some(b',') => self.pos += 1,
+			_ => { self.expect(b']')?; return Ok(Json::Array(items)) },
+		    }
+		}
+	    },
+	    Some(b'{') => {
+		self.pos += 1;
+		let mut fields = Vec::new();
+		if self.peek() == Some(b'}') { self.pos += 1; return Ok(Json::Object(fields)) }
+		loop {
+		    if self.peek() != Some(b'"') {
+			return Err(self.error("expected a key"))
+		    }
+		    let key = self.string()?;
+		    self.expect(b':')?;
+		    fields.push((key, self.value()?));
+		    match self.peek() {
+			Some(b',') => self.pos += 1,
+			_ => { self.expect(b'}')?; return Ok(Json::Object(fields)) },
+		    }
+		}
+	    },
+	    Some(c) if c.is_ascii_digit() => {
+		let start = self.pos;
+		while self.pos < self.text.len() && self.text[self.pos].is_ascii_digit() {
+		    self.pos += 1
+		}
+		if matches!(self.text.get(self.pos), Some(b'.' | b'e' | b'E')) {
+		    return Err(self.error("only whole numbers are supported"))
+		}
+		std::str::from_utf8(&self.text[start..self.pos]).unwrap()
+		    .parse().map(Json::Number)
+		    .map_err(|_| self.error("number too large"))
+	    },
+	    Some(b'-') => Err(self.error("negative numbers aren't supported")),
+	    Some(_) => Err(self.error("unexpected character")),
+	}
+    }
+
+    // at the opening quote
+    fn string(&mut self) -> Result<String, String> {
+	self.pos += 1;
+	let mut out = Vec::new();
+	loop {
+	    let c = *self.text.get(self.pos)
+		.ok_or_else(|| self.error("unterminated string"))?;
+	    self.pos += 1;
+	    match c {
+		b'"' => break,
+		b'\\' => {
+		    let e = *self.text.get(self.pos)
+			.ok_or_else(|| self.error("unterminated string"))?;
+		    self.pos += 1;
+		    match e {
+			b'"' | b'\\' | b'/' => out.push(e),
+			b'b' => out.push(8),
+			b'f' => out.push(12),
+			b'n' => out.push(b'\n'),
+			b'r' => out.push(b'\r'),
+			b't' => out.push(b'\t'),
+			b'u' => {
+			    let c = self.hex4()?;
+			    // a surrogate pair is two escapes
+			    let c = if (0xd800..0xdc00).contains(&c)
+				&& self.text[self.pos..].starts_with(b"\\u") {
+				self.pos += 2;
+				let low = self.hex4()?;
+				0x10000 + ((c - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff)
+			    } else { c };
+			    let c = char::from_u32(c)
+				.ok_or_else(|| self.error("bad \\u escape"))?;
+			    out.extend(c.to_string().as_bytes());
+			},
+			_ => return Err(self.error("bad escape")),
+		    }
+		},
+		c => out.push(c),
+	    }
+	}
+	String::from_utf8(out).map_err(|_| self.error("string isn't UTF-8"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+	let digits = self.text.get(self.pos..self.pos + 4)
+	    .and_then(|d| std::str::from_utf8(d).ok())
+	    .and_then(|d| u32::from_str_radix(d, 16).ok())
+	    .ok_or_else(|| self.error("bad \\u escape"))?;
+	self.pos += 4;
+	Ok(digits)
+    }
+}
+
+fn write_string(f : &mut fmt::Formatter, s : &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+	match c {
+	    '"'  => f.write_str("\\\"")?,
+	    '\\' => f.write_str("\\\\")?,
+	    '\n' => f.write_str("\\n")?,
+	    '\r' => f.write_str("\\r")?,
+	    '\t' => f.write_str("\\t")?,
+	    c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+	    c => write!(f, "{}", c)?,
+	}
+    }
+    f.write_str("\"")
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+	self.write(f, 0)
+    }
+}
+
+impl From<bool>   for Json { fn from(b : bool)   -> Json { Json::Bool(b) } }
+impl From<u8>     for Json { fn from(n : u8)     -> Json { Json::Number(n as u64) } }
+impl From<usize>  for Json { fn from(n : usize)  -> Json { Json::Number(n as u64) } }
+impl From<&str>   for Json { fn from(s : &str)   -> Json { Json::Str(s.to_string()) } }
+impl From<String> for Json { fn from(s : String) -> Json { Json::Str(s) } }
+
+impl<T : Into<Json>> From<Vec<T>> for Json {
+    fn from(v : Vec<T>) -> Json {
+	Json::Array(v.into_iter().map(|x| x.into()).collect())
+    }
+}
+
+impl<T : Into<Json>> From<Option<T>> for Json {
+    fn from(v : Option<T>) -> Json {
+	v.map_or(Json::Null, |x| x.into())
+    }
+}
+
+// All of a sharefile header's fields
+pub fn header_json(header : &HeaderV1) -> Json {
+    Json::object()
+	.with("version", 1usize)
+	.with("k", header.k)
+	.with("w", header.w)
+	.with("large_k", header.large_k)
+	.with("large_w", header.large_w)
+	.with("is_final", header.is_final)
+	.with("chunk_start", header.chunk_start)
+	.with("chunk_next", header.chunk_next)
+	.with("transform", if header.xform {
+	    Some(header.xform_data.clone())
+	} else {
+	    None
+	})
+}
+
+// A share's path along with its header
+pub fn share_json(share : &ShareFile) -> Json {
+    Json::object()
+	.with("path", share.name.as_str())
+	.with("header", header_json(&share.header))
+}
+
+// What split made: the scheme and the shares
+pub fn split_json(infile : &str, k : usize, n : usize, w : usize, poly : u16,
+		  shares : &[ShareFile]) -> Json {
+    let size = shares[0].header.chunk_next;
+    Json::object()
+	.with("input", infile)
+	.with("size", size)
+	.with("k", k)
+	.with("n", n)
+	.with("w", w)
+	.with("field", format!("{:#x}", poly))
+	.with("chunk_start", 0usize)
+	.with("chunk_next", size)
+	.with("share_length", shares[0].header.share_len())
+	.with("shares", shares.iter().map(share_json).collect::<Vec<_>>())
+}
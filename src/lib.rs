@@ -29,12 +29,29 @@
 // chunk_next. That's why it's called chunk_next rather than
 // chunk_end.
 
-use std::fs::File;
 use std::io::prelude::*;
-use std::io::ErrorKind;
-
-use guff::*;
 
+pub mod error;
+pub mod codec;
+pub mod parallel;
+pub mod split;
+pub mod combine;
+pub mod discover;
+pub mod inspect;
+pub mod rewrite;
+pub mod output;
+pub mod mapped;
+pub mod sha256;
+pub mod store;
+pub mod naming;
+pub mod manifest;
+pub mod tune;
+pub mod selftest;
+pub mod cli;
+pub mod json;
+pub mod zfec;
+
+#[derive(Clone)]
 pub struct HeaderV1 {
 
     // magic and version elided
@@ -54,16 +71,31 @@ pub struct HeaderV1 {
     pub xform    : bool,
 }
 
-use byteorder::{ByteOrder, LittleEndian,BigEndian};
+impl HeaderV1 {
+    // Bytes of data that should follow the header: the chunk, split
+    // into columns of k values (w bytes each), the last one padded
+    pub fn share_len(&self) -> usize {
+	let width = self.k * self.w;
+	if width == 0 { return 0 }
+	self.chunk_next.saturating_sub(self.chunk_start).div_ceil(width)
+	    * self.w
+    }
+}
+
+// A share file's name along with its header
+#[derive(Clone)]
+pub struct ShareFile {
+    pub name   : String,
+    pub header : HeaderV1,
+}
 
-pub fn read_sharefile_header(file : &mut File)
+use byteorder::{ByteOrder, BigEndian};
+
+pub fn read_sharefile_header<R : Read + ?Sized>(file : &mut R)
 			 -> Result<HeaderV1,String>
 {
-    let k;
-    let w;
     let mut chunk_start = 0;
     let mut chunk_next  = 0;
-    let mut xform_data;
 
     let mut buf = [0u8; 256];
 
@@ -74,8 +106,8 @@ pub fn read_sharefile_header(file : &mut File)
         Ok(_) => {
             let got = BigEndian::read_u16(&buf);
             if got != 0x5346 {
-                eprintln!("Expected 0x5346, got {:4x}", got);
-                return Err("No Magic".to_string())
+                return Err(format!("No Magic (expected 0x5346, got {:04x})",
+                                   got))
             }
         },
         _ => {
@@ -102,16 +134,16 @@ pub fn read_sharefile_header(file : &mut File)
     }
 
     // extract options
-    let large_k  : bool = if options & 0x01 != 0 { true } else { false };
-    let large_w  : bool = if options & 0x02 != 0 { true } else { false };
-    let is_final : bool = if options & 0x04 != 0 { true } else { false };
-    let xform    : bool = if options & 0x08 != 0 { true } else { false };
+    let large_k  : bool = options & 0x01 != 0;
+    let large_w  : bool = options & 0x02 != 0;
+    let is_final : bool = options & 0x04 != 0;
+    let xform    : bool = options & 0x08 != 0;
 
     // k
-    if large_k {
+    let k = if large_k {
         match file.read_exact(&mut buf[0..2]) {
             Ok(_) => {
-                k = BigEndian::read_u16(&buf) as usize;
+                BigEndian::read_u16(&buf) as usize
             },
             _ => {
                 return Err("Problem reading k".to_string());
@@ -120,19 +152,19 @@ pub fn read_sharefile_header(file : &mut File)
     } else {
         match file.read_exact(&mut buf[0..1]) {
             Ok(_) => {
-                k = buf[0] as usize;
+                buf[0] as usize
             },
             _ => {
                 return Err("Problem reading k".to_string());
             }
         }
-    }    
+    };
     
     // w
-    if large_w {
+    let w = if large_w {
         match file.read_exact(&mut buf[0..2]) {
             Ok(_) => {
-                w = BigEndian::read_u16(&buf) as usize;
+                BigEndian::read_u16(&buf) as usize
             },
             _ => {
                 return Err("Problem reading w".to_string());
@@ -141,31 +173,33 @@ pub fn read_sharefile_header(file : &mut File)
     } else {
         match file.read_exact(&mut buf[0..1]) {
             Ok(_) => {
-                w = buf[0] as usize;
+                buf[0] as usize
             },
             _ => {
                 return Err("Problem reading w".to_string());
             }
         }
-    }
+    };
 
     // chunk_start and chunk_next use variable length encoding
 
-    let start_len;
-    match file.read_exact(&mut buf[0..1]) {
+    let start_len = match file.read_exact(&mut buf[0..1]) {
         Ok(_) => {
-            start_len = buf[0] as usize;
+            buf[0] as usize
         },
         _ => {
             return Err("Problem reading chunk start".to_string());
         }
+    };
+    if start_len > std::mem::size_of::<usize>() {
+        return Err(format!("chunk start length {} is too big", start_len));
     }
     match file.read_exact(&mut buf[0..start_len]) {
         Ok(_) => {
             let mut index = 0;
             while index < start_len {
                 chunk_start <<= 8;
-                chunk_start += buf[index as usize] as usize;
+                chunk_start += buf[index] as usize;
                 index += 1;
             }
         },
@@ -174,21 +208,23 @@ pub fn read_sharefile_header(file : &mut File)
         }
     }
     
-    let next_len;
-    match file.read_exact(&mut buf[0..1]) {
+    let next_len = match file.read_exact(&mut buf[0..1]) {
         Ok(_) => {
-            next_len = buf[0] as usize;
+            buf[0] as usize
         },
         _ => {
             return Err("Problem reading chunk next".to_string());
         }
+    };
+    if next_len > std::mem::size_of::<usize>() {
+        return Err(format!("chunk next length {} is too big", next_len));
     }
     match file.read_exact(&mut buf[0..next_len]) {
         Ok(_) => {
             let mut index = 0;
             while index < next_len {
                 chunk_next <<= 8;
-                chunk_next += buf[index as usize] as usize;
+                chunk_next += buf[index] as usize;
                 index += 1;
             }
         },
@@ -199,12 +235,15 @@ pub fn read_sharefile_header(file : &mut File)
 
     // TODO: use an enum or something (GenericArray?) to return
     // transform as a vector of u16 if w is 2, u32 if 4, etc.
-    xform_data = Vec::with_capacity(w * k);
+    //
+    // The row can be bigger than buf, so read it into its own vector.
+    // A bad header can claim up to 64K * 64K bytes, though, so let
+    // the file tell us how much there really is before allocating.
+    let mut xform_data = Vec::new();
     if xform {
-        match file.read_exact(&mut buf[0..w * k]) {
-            Ok(_) => {
-                xform_data.extend((&buf[..w * k]).iter());
-            },
+        let want = w * k;
+        match (&mut *file).take(want as u64).read_to_end(&mut xform_data) {
+            Ok(got) if got == want => { },
             _ => {
                 return Err("Problem reading transform row".to_string());
             }
@@ -232,7 +271,7 @@ pub fn encode_length(mut n : usize) -> Vec<u8> {
     let mut v = Vec::<u8>::with_capacity(8);
     while n > 0 {
 	v.push(n as u8);
-	n = n >> 8;
+	n >>= 8;
 	shifts += 1;
     }
     v.push(shifts);
@@ -241,14 +280,14 @@ pub fn encode_length(mut n : usize) -> Vec<u8> {
     v
 }
 
-pub fn write_sharefile_header(file : &mut File, header : &HeaderV1)
+pub fn write_sharefile_header<W : Write + ?Sized>(file : &mut W, header : &HeaderV1)
 			  -> Result<usize, std::io::Error>
 {
 
     let mut buffer = Vec::<u8>::with_capacity(30);
 
-    buffer.push('S' as u8);
-    buffer.push('F' as u8);
+    buffer.push(b'S');
+    buffer.push(b'F');
     buffer.push(1);
 
     let mut options = 0;
@@ -280,8 +319,7 @@ pub fn write_sharefile_header(file : &mut File, header : &HeaderV1)
     match file.write(&buffer) {
 	Ok(n) => {
 	    if n != length {
-		Err(std::io::Error::new(
-		    ErrorKind::Other, 
+		Err(std::io::Error::other(
 		    format!("Failed to write all {} header bytes",
 			    length)))
 	    } else {
@@ -291,3 +329,40 @@ pub fn write_sharefile_header(file : &mut File, header : &HeaderV1)
 	Err(x) => { Err(x) },
     }
 }
+
+// Share file naming compatible with Crypt::IDA::ShareFile
+//
+// The Perl module's sf_split() names its output files using the
+// filespec "%f-%c-%s.sf", where %f is the input file name, %c is the
+// chunk number and %s is the share number. Both numbers count from
+// zero. Since we don't (yet) split files into multiple chunks, the
+// chunk number will always be zero.
+pub fn perl_share_name(infile : &str, chunk : usize, share : usize)
+		       -> String {
+    format!("{}-{}-{}.sf", infile, chunk, share)
+}
+
+// Original file name and share number (counting from zero) from a
+// name made by split, either "<file>-block.<N>" (N from 1) or the Perl
+// module's "<file>-<c>-<s>.sf"
+pub fn split_share_name(name : &str) -> Option<(&str, usize)> {
+    let all_digits = |s : &str| !s.is_empty()
+	&& s.chars().all(|c| c.is_ascii_digit());
+    if let Some(pos) = name.rfind("-block.") {
+	let number = &name[pos + 7..];
+	if all_digits(number) {
+	    let share = number.parse::<usize>().ok()?.checked_sub(1)?;
+	    return Some((&name[..pos], share))
+	}
+    }
+    let parts : Vec<_> = name.strip_suffix(".sf")?.rsplitn(3, '-').collect();
+    if parts.len() == 3 && all_digits(parts[0]) && all_digits(parts[1]) {
+	return Some((parts[2], parts[0].parse().ok()?))
+    }
+    None
+}
+
+// Share number (counting from zero) from a name made by split
+pub fn share_number(name : &str) -> Option<usize> {
+    split_share_name(name).map(|(_, share)| share)
+}
@@ -0,0 +1,296 @@
+// A manifest records everything about a split
+//
+// "split --manifest FILE" (and "split --tar", inside the archive)
+// writes a JSON file giving the scheme (k, n, w, field polynomial and
+// key), the original file's name, size and SHA-256, the chunk layout
+// and, for each share, its name, transform row, data length and
+// SHA-256. Share hashes only cover the data after the header, so
+// rewriting headers (eg, stripping transform rows) doesn't make them
+// wrong.
+//
+// Share names are relative to the manifest's directory (unless they
+// are absolute), so a manifest can be moved along with its shares.
+// Once loaded, they're turned back into paths that can be opened.
+//
+// combine, verify and repair use a manifest when given one: combine
+// gets the share list and any missing transform rows from it and
+// checks the rebuilt file against its hash, verify checks each share
+// against its hash without decoding anything, and repair uses that to
+// find the missing or damaged shares to rewrite. Without one, they
+// go by the share headers as before.
+
+use std::fs;
+use std::io::prelude::*;
+use std::path::{Component, Path, PathBuf};
+
+use crate::*;
+use crate::error::*;
+use crate::json::*;
+use crate::output::*;
+use crate::sha256::*;
+use crate::split::*;
+use crate::store::*;
+
+// What split calls a manifest by default (<name>.manifest.json), and
+// what combine skips when looking through an archive
+pub const MANIFEST_SUFFIX : &str = ".manifest.json";
+
+pub struct ManifestChunk {
+    pub chunk_start : usize,
+    pub chunk_next  : usize,
+    pub share_len   : usize,		// bytes of data in each share
+    pub is_final    : bool,
+}
+
+pub struct ManifestShare {
+    pub name      : String,
+    pub transform : Vec<u8>,
+    pub data_len  : usize,
+    pub sha256    : String,		// of the data after the header
+}
+
+pub struct Manifest {
+    pub input  : String,		// original file name
+    pub size   : usize,
+    pub sha256 : String,
+    pub k : usize,
+    pub n : usize,
+    pub w : usize,
+    pub poly : u16,
+    pub key  : Vec<u8>,
+    pub chunks : Vec<ManifestChunk>,
+    pub shares : Vec<ManifestShare>,	// in share order
+}
+
+// Path with any "." components (eg, a leading "./") taken out
+fn clean(path : &Path) -> PathBuf {
+    path.components().filter(|c| *c != Component::CurDir).collect()
+}
+
+fn absolute(path : &Path) -> PathBuf {
+    if path.is_absolute() { return clean(path) }
+    let cwd = std::env::current_dir().unwrap_or_default();
+    clean(&cwd.join(path))
+}
+
+// name as written in a manifest in dir
+fn relative_to(dir : &Path, name : &str) -> String {
+    if dir == Path::new("") && !Path::new(name).is_absolute() {
+	return clean(Path::new(name)).to_string_lossy().into_owned()
+    }
+    let path = absolute(Path::new(name));
+    match path.strip_prefix(absolute(dir)) {
+	Ok(rel) => rel.to_string_lossy().into_owned(),
+	Err(_) => path.to_string_lossy().into_owned(),
+    }
+}
+
+fn bad(name : &str, msg : &str) -> IdaError {
+    IdaError::new(ErrorClass::BadHeader, format!("{}: {}", name, msg))
+}
+
+// Hash a share's data (everything after its header), returning the
+// data length and hash
+pub fn hash_share(store : &dyn ShareStore, name : &str)
+		  -> IdaResult<(usize, String)> {
+    let (mut fh, _) = store.open_read(name)?;
+    read_sharefile_header(&mut fh).map_err(|e| bad(name, &e))?;
+    let mut hash = Sha256::new();
+    let mut buf = vec![0u8; 65536];
+    let mut len = 0;
+    loop {
+	match fh.read(&mut buf) {
+	    Ok(0) => break,
+	    Ok(got) => { hash.update(&buf[..got]); len += got },
+	    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+	    Err(e) => return Err(IdaError::io(name, e)),
+	}
+    }
+    Ok((len, hash.hex()))
+}
+
+impl Manifest {
+
+    // Describe a split that produced shares. hashes are of the input
+    // and of each share's data.
+    pub fn new(input : &str, opts : &SplitOptions, shares : &[ShareFile],
+	       input_hash : String, share_hashes : Vec<String>)
+	       -> IdaResult<Manifest> {
+	let key = opts.check()?;
+	let first = &shares[0].header;
+	Ok(Manifest {
+	    input : input.to_string(),
+	    size  : first.chunk_next - first.chunk_start,
+	    sha256 : input_hash,
+	    k : opts.k, n : opts.n, w : opts.w,
+	    poly : opts.poly,
+	    key,
+	    chunks : vec![ManifestChunk {
+		chunk_start : first.chunk_start,
+		chunk_next  : first.chunk_next,
+		share_len   : first.share_len(),
+		is_final    : first.is_final,
+	    }],
+	    shares : shares.iter().zip(share_hashes).map(|(share, sha256)| {
+		ManifestShare {
+		    name : share.name.clone(),
+		    transform : share.header.xform_data.clone(),
+		    data_len  : share.header.share_len(),
+		    sha256,
+		}
+	    }).collect(),
+	})
+    }
+
+    // Share names are written relative to dir
+    pub fn to_json(&self, dir : &Path) -> Json {
+	let chunks : Vec<Json> = self.chunks.iter().map(|c| Json::object()
+	    .with("chunk_start", c.chunk_start)
+	    .with("chunk_next", c.chunk_next)
+	    .with("share_length", c.share_len)
+	    .with("is_final", c.is_final))
+	    .collect();
+	let shares : Vec<Json> = self.shares.iter().enumerate()
+	    .map(|(i, s)| Json::object()
+		 .with("index", i + 1)
+		 .with("path", relative_to(dir, &s.name))
+		 .with("transform", s.transform.clone())
+		 .with("data_length", s.data_len)
+		 .with("sha256", s.sha256.as_str()))
+	    .collect();
+	Json::object()
+	    .with("manifest", 1usize)
+	    .with("input", self.input.as_str())
+	    .with("size", self.size)
+	    .with("sha256", self.sha256.as_str())
+	    .with("k", self.k)
+	    .with("n", self.n)
+	    .with("w", self.w)
+	    .with("field", format!("{:#x}", self.poly))
+	    .with("key", self.key.clone())
+	    .with("chunks", chunks)
+	    .with("shares", shares)
+    }
+
+    // Read a manifest (called name, in messages) whose share names are
+    // relative to dir
+    pub fn parse(name : &str, text : &str, dir : &Path) -> IdaResult<Manifest> {
+	let json = Json::parse(text).map_err(|e| bad(name, &e))?;
+	let missing = |key : &str| bad(name, &format!("missing or bad \"{}\"", key));
+	let number = |json : &Json, key : &str| json.get(key)
+	    .and_then(|v| v.as_number()).map(|v| v as usize)
+	    .ok_or_else(|| missing(key));
+	let string = |json : &Json, key : &str| json.get(key)
+	    .and_then(|v| v.as_str()).map(|s| s.to_string())
+	    .ok_or_else(|| missing(key));
+	let bytes = |json : &Json, key : &str| json.get(key)
+	    .and_then(|v| v.as_array())
+	    .and_then(|items| items.iter()
+		      .map(|v| v.as_number().filter(|&b| b < 256).map(|b| b as u8))
+		      .collect::<Option<Vec<u8>>>())
+	    .ok_or_else(|| missing(key));
+	let array = |key : &str| json.get(key).and_then(|v| v.as_array())
+	    .ok_or_else(|| missing(key));
+
+	if number(&json, "manifest")? != 1 {
+	    return Err(bad(name, "not a version 1 manifest"))
+	}
+	let field = string(&json, "field")?;
+	let poly = field.strip_prefix("0x")
+	    .and_then(|hex| u16::from_str_radix(hex, 16).ok())
+	    .ok_or_else(|| missing("field"))?;
+	let mut chunks = Vec::new();
+	for chunk in array("chunks")?.iter() {
+	    chunks.push(ManifestChunk {
+		chunk_start : number(chunk, "chunk_start")?,
+		chunk_next  : number(chunk, "chunk_next")?,
+		share_len   : number(chunk, "share_length")?,
+		is_final    : chunk.get("is_final").and_then(|v| v.as_bool())
+		    .ok_or_else(|| missing("is_final"))?,
+	    });
+	}
+	let mut shares = Vec::new();
+	for share in array("shares")?.iter() {
+	    let path = string(share, "path")?;
+	    shares.push(ManifestShare {
+		name : clean(&dir.join(path)).to_string_lossy().into_owned(),
+		transform : bytes(share, "transform")?,
+		data_len  : number(share, "data_length")?,
+		sha256    : string(share, "sha256")?,
+	    });
+	}
+	let manifest = Manifest {
+	    input : string(&json, "input")?,
+	    size  : number(&json, "size")?,
+	    sha256 : string(&json, "sha256")?,
+	    k : number(&json, "k")?,
+	    n : number(&json, "n")?,
+	    w : number(&json, "w")?,
+	    poly,
+	    key : bytes(&json, "key")?,
+	    chunks, shares,
+	};
+	if let Some(s) = manifest.shares.iter()
+	    .find(|s| s.transform.len() != manifest.k * manifest.w) {
+	    return Err(bad(name, &format!(
+		"{} has a transform row of the wrong length", s.name)))
+	}
+	Ok(manifest)
+    }
+
+    pub fn load(path : &str) -> IdaResult<Manifest> {
+	let text = fs::read_to_string(path).map_err(|e| IdaError::io(path, e))?;
+	let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+	Manifest::parse(path, &text, dir)
+    }
+
+    // Read the first manifest in a store (eg, a tar archive), if any
+    pub fn find_in(store : &dyn ShareStore) -> IdaResult<Option<Manifest>> {
+	let name = match store.list()?.into_iter()
+	    .find(|name| name.ends_with(MANIFEST_SUFFIX)) {
+	    Some(name) => name,
+	    None => return Ok(None),
+	};
+	let (mut fh, _) = store.open_read(&name)?;
+	let mut text = String::new();
+	fh.read_to_string(&mut text).map_err(|e| IdaError::io(&name, e))?;
+	Manifest::parse(&name, &text, Path::new("")).map(Some)
+    }
+
+    pub fn save(&self, path : &str, opts : &OutputOptions) -> IdaResult<()> {
+	let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+	let mut out = OutputFile::create(path, opts)?;
+	writeln!(out, "{}", self.to_json(dir))
+	    .map_err(|e| IdaError::io(path, e))?;
+	out.commit()
+    }
+
+    // Look up a share by its path, returning its number (from 0)
+    pub fn share(&self, name : &str) -> Option<(usize, &ManifestShare)> {
+	let name = clean(Path::new(name));
+	self.shares.iter().enumerate()
+	    .find(|(_, s)| Path::new(&s.name) == name)
+    }
+
+    // Problem with a share, if its data doesn't match (or it can't be
+    // read)
+    pub fn check_share(&self, store : &dyn ShareStore, name : &str)
+		       -> Option<String> {
+	let share = match self.share(name) {
+	    Some((_, share)) => share,
+	    None => return Some("not in the manifest".to_string()),
+	};
+	match hash_share(store, name) {
+	    Err(e) if e.class == ErrorClass::Io => {
+		Some("missing or unreadable".to_string())
+	    },
+	    Err(_) => Some("bad header".to_string()),
+	    Ok((len, _)) if len != share.data_len => Some(format!(
+		"has {} bytes of data, manifest says {}", len, share.data_len)),
+	    Ok((_, hash)) if hash != share.sha256 => {
+		Some("data doesn't match the manifest's sha256".to_string())
+	    },
+	    Ok(_) => None,
+	}
+    }
+}
@@ -0,0 +1,124 @@
+// Memory-mapped input and output files (--mmap)
+//
+// combine reads each share into a buffer, and then copies it again
+// as it interleaves columns into a block. With the shares mapped, the
+// columns come straight from the page cache, saving the first copy.
+//
+// split doesn't gain anything on its input: it already read()s
+// straight into its block, which is the same single copy out of the
+// page cache that a mapping gives. It still reads through a mapping
+// with --mmap, but that's no faster.
+//
+// Output files have a known size, so they can be mapped too. They're
+// preallocated first (where the file system can), so that running
+// out of space shows up as an error here rather than as a SIGBUS
+// when the mapping is written to.
+//
+// Not everything can be mapped: pipes, devices, empty files and files
+// in tar archives are read and written as usual instead. Pipes are
+// read in full first (see read_all()), since their length has to be
+// known up front.
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+
+use memmap2::{Mmap, MmapMut};
+
+use crate::error::*;
+use crate::output::*;
+use crate::store::*;
+
+// The whole of file, if it's a regular file that can be mapped
+pub fn map_file(file : &File) -> Option<Mmap> {
+    match file.metadata() {
+	Ok(meta) if meta.is_file() && meta.len() > 0 => {},
+	_ => return None,
+    }
+    // Safety: the mapping is only read, and the files are ones we're
+    // not expecting anyone else to change while we work on them
+    unsafe { Mmap::map(file) }.ok()
+}
+
+// Everything from a file that isn't a regular one
+pub fn read_all(mut file : File) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+// Reserve len bytes of disk for file. Not every file system can, in
+// which case the file is just extended (sparsely).
+#[cfg(unix)]
+fn preallocate(file : &File, len : usize) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    match unsafe { libc::posix_fallocate(file.as_raw_fd(), 0, len as libc::off_t) } {
+	0 => Ok(()),
+	libc::EINVAL | libc::EOPNOTSUPP => file.set_len(len as u64),
+	err => Err(io::Error::from_raw_os_error(err)),
+    }
+}
+
+#[cfg(not(unix))]
+fn preallocate(file : &File, len : usize) -> io::Result<()> {
+    file.set_len(len as u64)
+}
+
+// An OutputFile of a known size, written through a mapping
+pub struct MappedOutput {
+    file : OutputFile,
+    map  : MmapMut,
+    pos  : usize,
+}
+
+impl MappedOutput {
+
+    // Falls back to writing the OutputFile as usual if it can't be
+    // mapped
+    pub fn create(target : &str, len : usize, opts : &OutputOptions)
+		  -> IdaResult<Box<dyn ShareWriter>> {
+	let mut file = OutputFile::create(target, opts)?;
+	if len == 0 { return Ok(Box::new(file)) }
+	preallocate(file.file(), len).map_err(|e| IdaError::io(target, e))?;
+	// Safety: the file is our own temporary one
+	match unsafe { MmapMut::map_mut(&*file.file()) } {
+	    Ok(map) => Ok(Box::new(MappedOutput { file, map, pos : 0 })),
+	    Err(_) => {
+		file.file().set_len(0).map_err(|e| IdaError::io(target, e))?;
+		Ok(Box::new(file))
+	    },
+	}
+    }
+}
+
+impl Write for MappedOutput {
+    // Anything past the size given to create() is refused (so
+    // write_all() fails)
+    fn write(&mut self, buf : &[u8]) -> io::Result<usize> {
+	let len = buf.len().min(self.map.len() - self.pos);
+	self.map[self.pos..self.pos + len].copy_from_slice(&buf[..len]);
+	self.pos += len;
+	Ok(len)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+	self.map.flush()
+    }
+}
+
+impl ShareWriter for MappedOutput {
+    fn ready(&self) -> IdaResult<()> {
+	self.file.ready()
+    }
+
+    // Anything that wasn't written is cut off
+    fn commit(self : Box<Self>) -> IdaResult<()> {
+	let MappedOutput { mut file, map, pos } = *self;
+	let name = file.name();
+	let len = map.len();
+	drop(map);
+	if pos < len {
+	    file.file().set_len(pos as u64).map_err(|e| IdaError::io(&name, e))?;
+	}
+	file.commit()
+    }
+}
@@ -0,0 +1,232 @@
+// Share file naming templates
+//
+// A template such as "{name}.{index:02}.sf" says how to name each
+// share. The fields are:
+//
+//   {name}    the input file's name (without any directory)
+//   {index}   share number, counting from 1
+//   {index0}  share number, counting from 0
+//   {chunk}   chunk number, counting from 0 (always 0 for now)
+//   {k}, {n}  the quorum and number of shares
+//
+// Numbers can be padded to a minimum width, with zeros ("{index:02}")
+// or spaces ("{index:2}"). "{{" and "}}" are literal braces.
+//
+// The expanded template is taken relative to the share's output
+// directory, so it can include subdirectories of its own (or be an
+// absolute path). The same template can be matched against existing
+// file names, which is how combine finds shares named with it.
+
+use crate::error::*;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Field { Name, Index, Index0, Chunk, K, N }
+
+#[derive(Clone, Debug)]
+enum Part {
+    Literal(String),
+    Field(Field, usize, bool),	// field, width, zero padded
+}
+
+#[derive(Clone, Debug)]
+pub struct NameTemplate {
+    parts : Vec<Part>,
+}
+
+// Values to fill in
+pub struct NameVars<'a> {
+    pub name  : &'a str,
+    pub share : usize,		// from 0
+    pub chunk : usize,
+    pub k : usize,
+    pub n : usize,
+}
+
+// What a file name matched with; fields not in the template are None
+#[derive(Default, Debug, PartialEq, Eq)]
+pub struct NameMatch {
+    pub name  : Option<String>,
+    pub share : Option<usize>,	// from 0
+    pub chunk : Option<usize>,
+}
+
+impl NameTemplate {
+
+    pub fn parse(template : &str) -> IdaResult<NameTemplate> {
+	let bad = |msg : String| IdaError::new(ErrorClass::Usage, format!(
+	    "Bad name template '{}': {}", template, msg));
+	let mut parts = Vec::new();
+	let mut literal = String::new();
+	let mut chars = template.chars().peekable();
+	while let Some(c) = chars.next() {
+	    match c {
+		'{' if chars.peek() == Some(&'{') => { chars.next(); literal.push('{') },
+		'}' if chars.peek() == Some(&'}') => { chars.next(); literal.push('}') },
+		'}' => return Err(bad("unmatched '}'".to_string())),
+		'{' => {
+		    let mut spec = String::new();
+		    loop {
+			match chars.next() {
+			    Some('}') => break,
+			    Some(c) => spec.push(c),
+			    None => return Err(bad("unmatched '{'".to_string())),
+			}
+		    }
+		    let (field, format) = match spec.split_once(':') {
+			Some((field, format)) => (field, format),
+			None => (&spec[..], ""),
+		    };
+		    let field = match field {
+			"name"   => Field::Name,
+			"index"  => Field::Index,
+			"index0" => Field::Index0,
+			"chunk"  => Field::Chunk,
+			"k"      => Field::K,
+			"n"      => Field::N,
+			_ => return Err(bad(format!("unknown field {{{}}}", field))),
+		    };
+		    let zero = format.starts_with('0');
+		    let width = if format.is_empty() { 0 } else {
+			format.parse().map_err(|_| bad(format!(
+			    "bad width '{}' for {{{}}}", format, spec)))?
+		    };
+		    if field == Field::Name && width > 0 {
+			return Err(bad("{name} can't be padded".to_string()))
+		    }
+		    if !literal.is_empty() {
+			parts.push(Part::Literal(std::mem::take(&mut literal)));
+		    }
+		    parts.push(Part::Field(field, width, zero));
+		},
+		c => literal.push(c),
+	    }
+	}
+	if !literal.is_empty() { parts.push(Part::Literal(literal)) }
+
+	let t = NameTemplate { parts };
+	if !t.has(Field::Index) && !t.has(Field::Index0) {
+	    return Err(bad("needs {index} or {index0} to tell shares apart"
+			   .to_string()))
+	}
+	Ok(t)
+    }
+
+    fn has(&self, want : Field) -> bool {
+	self.parts.iter().any(|p| matches!(p, Part::Field(f, _, _) if *f == want))
+    }
+
+    pub fn render(&self, vars : &NameVars) -> String {
+	let mut out = String::new();
+	for part in self.parts.iter() {
+	    let (field, width, zero) = match part {
+		Part::Literal(s) => { out += s; continue },
+		Part::Field(field, width, zero) => (*field, *width, *zero),
+	    };
+	    let value = match field {
+		Field::Name   => { out += vars.name; continue },
+		Field::Index  => vars.share + 1,
+		Field::Index0 => vars.share,
+		Field::Chunk  => vars.chunk,
+		Field::K      => vars.k,
+		Field::N      => vars.n,
+	    };
+	    if zero {
+		out += &format!("{:0width$}", value, width = width);
+	    } else {
+		out += &format!("{:width$}", value, width = width);
+	    }
+	}
+	out
+    }
+
+    // A glob (for find_candidates) matching every name the template
+    // could produce, with {name} filled in if given
+    pub fn glob(&self, name : Option<&str>) -> String {
+	let mut out = String::new();
+	for part in self.parts.iter() {
+	    match part {
+		Part::Literal(s) => out += s,
+		Part::Field(Field::Name, _, _) if name.is_some() => {
+		    out += name.unwrap()
+		},
+		Part::Field(..) => {
+		    if !out.ends_with('*') { out.push('*') }
+		},
+	    }
+	}
+	out
+    }
+
+    // Match a path against the template. Only as many trailing path
+    // components as the template has are compared (all of them, for
+    // an absolute template).
+    pub fn match_name(&self, path : &str) -> Option<NameMatch> {
+	let rendered_parts = self.glob(None).split('/').count();
+	let tail = if self.glob(None).starts_with('/') { path } else {
+	    let cut = path.rmatch_indices('/').nth(rendered_parts - 1)
+		.map_or(0, |(i, _)| i + 1);
+	    &path[cut..]
+	};
+	let mut found = NameMatch::default();
+	if self.match_parts(&self.parts, tail, &mut found) {
+	    Some(found)
+	} else {
+	    None
+	}
+    }
+
+    fn match_parts(&self, parts : &[Part], s : &str, found : &mut NameMatch)
+		   -> bool {
+	let (part, rest) = match parts.split_first() {
+	    None => return s.is_empty(),
+	    Some(split) => split,
+	};
+	match part {
+	    Part::Literal(lit) => {
+		s.starts_with(&lit[..])
+		    && self.match_parts(rest, &s[lit.len()..], found)
+	    },
+	    Part::Field(Field::Name, _, _) => {
+		// any non-empty run within one path component
+		let limit = s.find('/').unwrap_or(s.len());
+		for end in (1..=limit).filter(|&e| s.is_char_boundary(e)) {
+		    if self.match_parts(rest, &s[end..], found) {
+			found.name = Some(s[..end].to_string());
+			return true
+		    }
+		}
+		false
+	    },
+	    Part::Field(field, width, zero) => {
+		let pad = if *zero { '0' } else { ' ' };
+		let digits = s.len() - s.trim_start_matches(
+		    |c : char| c.is_ascii_digit() || c == pad).len();
+		for end in (1..=digits).rev() {
+		    let text = &s[..end];
+		    let value = match text.trim_start_matches(pad) {
+			"" if *zero => 0,
+			v => match v.parse::<usize>() {
+			    Ok(v) => v,
+			    Err(_) => continue,
+			},
+		    };
+		    if end < *width { break }
+		    // a padded number must be exactly as render() writes it
+		    if end > 1 && text.starts_with(pad) && end != *width {
+			continue
+		    }
+		    if !self.match_parts(rest, &s[end..], found) { continue }
+		    match field {
+			Field::Index if value > 0 => found.share = Some(value - 1),
+			Field::Index => return false,
+			Field::Index0 => found.share = Some(value),
+			Field::Chunk  => found.chunk = Some(value),
+			_ => {},
+		    }
+		    return true
+		}
+		false
+	    },
+	}
+    }
+}
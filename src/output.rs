@@ -0,0 +1,122 @@
+// Write output files so that they only appear once they're complete
+//
+// Each output is written to a hidden temporary file in the same
+// directory (".<name>.part-<pid>") and only renamed to its real name
+// by commit(). If anything goes wrong first, including a panic, the
+// temporary file is removed when the OutputFile is dropped. A crash
+// can leave a ".part" file behind, but never a half-written share or
+// output file under its proper name.
+//
+// Existing files aren't overwritten unless force is set. This is
+// checked both when the output is created (so we don't do all the
+// work first) and again just before renaming.
+
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use crate::error::*;
+
+#[derive(Clone, Copy, Default)]
+pub struct OutputOptions {
+    pub force : bool,		// overwrite existing files
+    pub sync  : bool,		// fsync files (and directory) on commit
+}
+
+pub struct OutputFile {
+    target : PathBuf,
+    temp   : PathBuf,
+    file   : Option<File>,	// None once committed
+    opts   : OutputOptions,
+}
+
+fn refuse_overwrite(target : &Path, opts : &OutputOptions) -> IdaResult<()> {
+    if !opts.force && fs::symlink_metadata(target).is_ok() {
+	return Err(IdaError::new(ErrorClass::Usage, format!(
+	    "{}: already exists (use --force to overwrite)",
+	    target.display())))
+    }
+    Ok(())
+}
+
+impl OutputFile {
+
+    pub fn create(target : &str, opts : &OutputOptions) -> IdaResult<OutputFile> {
+	let target = PathBuf::from(target);
+	refuse_overwrite(&target, opts)?;
+	let name = target.file_name()
+	    .map(|s| s.to_string_lossy().into_owned())
+	    .ok_or_else(|| IdaError::new(ErrorClass::Usage, format!(
+		"{}: not a file name", target.display())))?;
+	let temp = target.with_file_name(
+	    format!(".{}.part-{}", name, std::process::id()));
+	// readable too, so that it can be mapped (see mapped.rs)
+	let file = fs::OpenOptions::new().read(true).write(true)
+	    .create(true).truncate(true).open(&temp)
+	    .map_err(|e| IdaError::io(&temp.to_string_lossy(), e))?;
+	Ok(OutputFile { target, temp, file : Some(file), opts : *opts })
+    }
+
+    pub fn name(&self) -> String {
+	self.target.to_string_lossy().into_owned()
+    }
+
+    pub fn file(&mut self) -> &mut File {
+	self.file.as_mut().unwrap()
+    }
+
+    // Would commit() be allowed to put the file in place?
+    pub fn ready(&self) -> IdaResult<()> {
+	refuse_overwrite(&self.target, &self.opts)
+    }
+
+    // Move the finished file into place
+    pub fn commit(mut self) -> IdaResult<()> {
+	let name = self.name();
+	let file = self.file.take().unwrap();
+	let moved = self.ready().and_then(|_| {
+	    if self.opts.sync {
+		file.sync_all().map_err(|e| IdaError::io(&name, e))?
+	    }
+	    drop(file);
+	    fs::rename(&self.temp, &self.target)
+		.map_err(|e| IdaError::io(&name, e))
+	});
+	if moved.is_err() {
+	    let _ = fs::remove_file(&self.temp);
+	    return moved
+	}
+	if self.opts.sync {
+	    sync_dir(&self.target).map_err(|e| IdaError::io(&name, e))?;
+	}
+	Ok(())
+    }
+}
+
+impl Drop for OutputFile {
+    fn drop(&mut self) {
+	if self.file.is_some() {
+	    let _ = fs::remove_file(&self.temp);
+	}
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf : &[u8]) -> io::Result<usize> {
+	self.file().write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+	self.file().flush()
+    }
+}
+
+// Make the rename itself durable
+fn sync_dir(target : &Path) -> io::Result<()> {
+    let dir = match target.parent() {
+	Some(dir) if dir != Path::new("") => dir,
+	_ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
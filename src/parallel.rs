@@ -0,0 +1,143 @@
+// Pipelining reads, multiplies and writes, with several multiplies at
+// once
+//
+// split and combine both work a block at a time: fill a Transform's
+// input, apply it, then do something with the output. Done in that
+// order on one thread, the multiply waits on every read and the next
+// read waits on every write. So the work is split into a pipeline:
+//
+// * the calling thread reads, filling one block after another
+// * worker threads multiply, each taking whole blocks. Each block has
+//   its own Transform (and so its own Matrix buffers), so with more
+//   than one worker several blocks are multiplied at the same time
+// * a writer thread drains the results, strictly in the order the
+//   blocks were filled, so the shares (or the rebuilt file) come out
+//   exactly as they would from a single thread
+//
+// Drained blocks go back to the reader to be filled again. There's a
+// fixed number of them, which keeps the queues between the stages
+// bounded: the reader can't get further ahead of the writer than
+// that, however slow the disks are.
+
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::panic;
+use std::sync::Mutex;
+use std::sync::mpsc;
+use std::thread;
+
+use crate::error::*;
+
+// Number of threads to use for a --threads value, where 0 means one
+// per CPU
+pub fn thread_count(wanted : usize) -> usize {
+    match wanted {
+	0 => thread::available_parallelism().map_or(1, |n| n.get()),
+	n => n,
+    }
+}
+
+// How many blocks to allocate for a number of multiply threads: one
+// for each of them, one being filled and one being drained (so triple
+// buffering with a single thread)
+pub fn block_count(threads : usize) -> usize {
+    threads.max(1) + 2
+}
+
+// What a worker hands to the writer: a multiplied block, or what it
+// panicked with
+type Worked<B> = (usize, Result<B, Box<dyn Any + Send>>);
+
+// Run blocks through work() on up to threads threads
+//
+// fill() gets a free block ready and returns false (leaving it alone)
+// once there's nothing left. It runs on the calling thread. drain()
+// gets each worked block back in the order they were filled, on a
+// thread of its own, after which the block is reused. An error from
+// fill() or drain() stops everything, and a panic in work() is passed
+// on.
+pub fn run_blocks<B, Fill, Work, Drain>(threads : usize, blocks : Vec<B>,
+					mut fill : Fill, work : Work,
+					mut drain : Drain) -> IdaResult<()>
+where B : Send,
+      Fill : FnMut(&mut B) -> IdaResult<bool>,
+      Work : Fn(&mut B) + Sync,
+      Drain : FnMut(&mut B) -> IdaResult<()> + Send {
+
+    assert!(!blocks.is_empty());
+    let (to_workers, jobs) = mpsc::channel::<(usize, B)>();
+    let (finished, done) = mpsc::channel::<Worked<B>>();
+    let (to_reader, drained) = mpsc::channel::<B>();
+    let jobs = Mutex::new(jobs);
+
+    let (filled, written) = thread::scope(|scope| {
+	for _ in 0..threads.max(1) {
+	    let (jobs, finished, work) = (&jobs, finished.clone(), &work);
+	    scope.spawn(move || loop {
+		// holding the lock only while waiting for a job
+		let job = jobs.lock().unwrap().recv();
+		let (seq, mut block) = match job {
+		    Ok(job) => job,
+		    Err(_) => break,	// no more blocks coming
+		};
+		// a panic is handed on rather than leaving the writer
+		// waiting for this block forever
+		let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+		    work(&mut block)
+		}));
+		if finished.send((seq, result.map(|_| block))).is_err() {
+		    break		// the writer has given up
+		}
+	    });
+	}
+	drop(finished);
+
+	// the writer stops once the workers have all finished, or on
+	// the first error
+	let writer = scope.spawn(move || {
+	    let mut waiting = BTreeMap::new();	// worked, but out of order
+	    let mut next = 0;
+	    for (seq, block) in done.iter() {
+		waiting.insert(seq, block?);
+		while let Some(mut block) = waiting.remove(&next) {
+		    if let Err(e) = drain(&mut block) {
+			return Ok(Err(e))
+		    }
+		    next += 1;
+		    let _ = to_reader.send(block);
+		}
+	    }
+	    Ok(Ok(()))
+	});
+
+	// fill blocks until there are no more, or the writer stops
+	// taking them back
+	let to_workers = to_workers;
+	let mut free = blocks;
+	let mut seq = 0;
+	let filled = loop {
+	    let mut block = match free.pop() {
+		Some(block) => block,
+		None => match drained.recv() {
+		    Ok(block) => block,
+		    Err(_) => break Ok(()),
+		},
+	    };
+	    match fill(&mut block) {
+		Ok(true) => {
+		    if to_workers.send((seq, block)).is_err() { break Ok(()) }
+		    seq += 1;
+		},
+		Ok(false) => break Ok(()),
+		Err(e) => break Err(e),
+	    }
+	};
+	drop(to_workers);
+	(filled, writer.join().unwrap())
+    });
+
+    match written {
+	Err(payload) => panic::resume_unwind(payload),
+	Ok(written) => filled.and(written),
+    }
+}
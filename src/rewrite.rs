@@ -0,0 +1,135 @@
+// Change a share's header without touching its data
+//
+// The header format makes the transform row optional (opt_transform),
+// so a set of shares can be stored more compactly by stripping the
+// rows and keeping the matrix somewhere else. This module does the
+// stripping and re-attaching, and can also correct the chunk range.
+//
+// A matrix file is just the transform matrix, one row of k bytes per
+// share, in share order. "ida split --matrix" writes one. Shares that
+// have no row in their header are matched up with the matrix by the
+// share number in their name (see share_number()).
+
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+
+use crate::*;
+use crate::error::*;
+use crate::output::*;
+
+pub enum RowEdit {
+    Keep,
+    Strip,
+    Attach(Vec<u8>),
+}
+
+pub struct HeaderEdit {
+    pub row : RowEdit,
+    pub chunk_start : Option<usize>,
+    pub chunk_next  : Option<usize>,
+}
+
+impl Default for HeaderEdit {
+    fn default() -> Self {
+	HeaderEdit { row : RowEdit::Keep, chunk_start : None, chunk_next : None }
+    }
+}
+
+pub fn read_matrix(name : &str) -> IdaResult<Vec<u8>> {
+    let matrix = fs::read(name).map_err(|e| IdaError::io(name, e))?;
+    if matrix.is_empty() {
+	return Err(IdaError::new(ErrorClass::BadHeader,
+				 format!("{}: empty matrix file", name)))
+    }
+    Ok(matrix)
+}
+
+// out is created before the split, so that an existing matrix file
+// stops it before any shares are written
+pub fn write_matrix(mut out : OutputFile, matrix : &[u8]) -> IdaResult<()> {
+    out.write_all(matrix).map_err(|e| IdaError::io(&out.name(), e))?;
+    out.commit()
+}
+
+// Row for a given share (counting from zero) of a k-column matrix
+pub fn matrix_row(matrix : &[u8], k : usize, share : usize)
+		  -> IdaResult<Vec<u8>> {
+    if k == 0 || !matrix.len().is_multiple_of(k) {
+	return Err(IdaError::new(ErrorClass::Mismatch, format!(
+	    "Matrix of {} bytes doesn't have rows of k = {} values",
+	    matrix.len(), k)))
+    }
+    matrix.chunks(k).nth(share).map(|row| row.to_vec())
+	.ok_or_else(|| IdaError::new(ErrorClass::Mismatch, format!(
+	    "Matrix only has {} rows; no row for share {}",
+	    matrix.len() / k, share + 1)))
+}
+
+// Rewrite infile's header, writing to outfile (or back to infile if
+// None). The new file is written alongside the target and renamed
+// over it, so a failure part way through leaves the original alone.
+// Rewriting in place always overwrites (and syncs, since it replaces
+// the only copy).
+pub fn rewrite_header(infile : &str, outfile : Option<&str>,
+		      edit : &HeaderEdit, opts : &OutputOptions)
+		      -> IdaResult<ShareFile> {
+    let opts = match outfile {
+	None => OutputOptions { force : true, sync : true },
+	Some(_) => *opts,
+    };
+    let outfile = outfile.unwrap_or(infile);
+    let mut fh = File::open(infile)
+	.map_err(|e| IdaError::io(infile, e))?;
+    let mut header = read_sharefile_header(&mut fh)
+	.map_err(|e| IdaError::new(ErrorClass::BadHeader,
+				   format!("{}: {}", infile, e)))?;
+    let start = fh.stream_position()
+	.map_err(|e| IdaError::io(infile, e))? as usize;
+    let data_len = fh.metadata()
+	.map_err(|e| IdaError::io(infile, e))?.len() as usize - start;
+
+    match &edit.row {
+	RowEdit::Keep => {},
+	RowEdit::Strip => {
+	    header.xform = false;
+	    header.xform_data.clear();
+	},
+	RowEdit::Attach(row) => {
+	    if row.len() != header.k * header.w {
+		return Err(IdaError::new(ErrorClass::Usage, format!(
+		    "{}: transform row should have {} values, not {}",
+		    infile, header.k * header.w, row.len())))
+	    }
+	    header.xform = true;
+	    header.xform_data = row.clone();
+	},
+    }
+    if let Some(chunk_start) = edit.chunk_start { header.chunk_start = chunk_start }
+    if let Some(chunk_next)  = edit.chunk_next  { header.chunk_next  = chunk_next }
+
+    // the data has to fit the (possibly new) chunk range
+    if header.k * header.w == 0 {
+	return Err(IdaError::new(ErrorClass::BadHeader,
+				 format!("{}: k and w must not be 0", infile)))
+    }
+    if header.chunk_next <= header.chunk_start {
+	return Err(IdaError::new(ErrorClass::Usage, format!(
+	    "{}: chunk_next ({}) must be more than chunk_start ({})",
+	    infile, header.chunk_next, header.chunk_start)))
+    }
+    let expect = header.share_len();
+    if expect != data_len {
+	return Err(IdaError::new(ErrorClass::Mismatch, format!(
+	    "{}: chunk {}..{} needs {} bytes of data, but share has {}",
+	    infile, header.chunk_start, header.chunk_next, expect, data_len)))
+    }
+
+    let mut out = OutputFile::create(outfile, &opts)?;
+    write_sharefile_header(out.file(), &header)
+	.and_then(|_| io::copy(&mut fh, out.file()))
+	.map_err(|e| IdaError::io(outfile, e))?;
+    out.commit()?;
+    Ok(ShareFile { name : outfile.to_string(), header })
+}
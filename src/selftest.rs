@@ -0,0 +1,234 @@
+// Checking that the multiply routines give the right answers on this
+// host (and with this build) before trusting them with real data
+//
+// Each kernel that can run here (see choose_kernel()) is checked:
+//
+// * against known answers: a few field products, and the shares for a
+//   fixed input
+// * against a plain one-byte-at-a-time multiply, for several (k, n)
+//   and block sizes, with random data
+// * with random split and combine round trips, in memory, using a
+//   random choice of k shares
+//
+// The random data comes from the seed, so a failure can be repeated.
+
+use std::io::Cursor;
+
+use guff::*;
+
+use crate::codec::*;
+use crate::combine::*;
+use crate::error::*;
+use crate::sha256::*;
+use crate::split::*;
+use crate::store::*;
+
+// (k, n, columns per block). Small ones need padding rows and
+// columns, and the rest have leftover columns or partial blocks.
+pub const SHAPES : [(usize, usize, usize); 11] = [
+    (1, 1, 16), (2, 3, 1), (2, 3, 5), (3, 5, 17), (4, 6, 100),
+    (10, 12, 333), (8, 16, 1000), (8, 16, 4096), (16, 20, 1),
+    (16, 20, 2), (16, 20, 4099),
+];
+
+// SHA-256 of the 6 rows of output from a 4 x 6 split (default key)
+// of the bytes 0 to 255, 64 columns at a time
+const KNOWN_SHARES : &str =
+    "07a60fed6a2a2698056925a7937bc9d899204888d7996171497699275941e0fc";
+
+pub enum Outcome {
+    Passed,
+    Failed(String),
+    Skipped(String),
+}
+
+pub struct Check {
+    pub name    : String,
+    pub outcome : Outcome,
+}
+
+// xorshift64*, which is plenty for test data
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+	self.0 ^= self.0 >> 12;
+	self.0 ^= self.0 << 25;
+	self.0 ^= self.0 >> 27;
+	self.0.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+
+    fn bytes(&mut self, len : usize) -> Vec<u8> {
+	(0..len).map(|_| (self.next() >> 56) as u8).collect()
+    }
+
+    fn below(&mut self, n : usize) -> usize {
+	(self.next() % n as u64) as usize
+    }
+}
+
+fn outcome(result : Result<(), String>) -> Outcome {
+    match result {
+	Ok(()) => Outcome::Passed,
+	Err(problem) => Outcome::Failed(problem),
+    }
+}
+
+fn field_answers(field : &F8) -> Result<(), String> {
+    // from FIPS-197 (the AES field is the same one)
+    for &(a, b, product) in [(0x57, 0x83, 0xc1), (0x57, 0x13, 0xfe),
+			      (0x53, 0xca, 0x01)].iter() {
+	let got = field.mul(a, b);
+	if got != product {
+	    return Err(format!("{:#04x} x {:#04x} gave {:#04x}, not {:#04x}",
+			       a, b, got, product))
+	}
+    }
+    match field.inv(0x53) {
+	0xca => Ok(()),
+	got => Err(format!("inverse of 0x53 gave {:#04x}, not 0xca", got)),
+    }
+}
+
+fn share_answers(field : &F8, use_ref : bool) -> Result<(), String> {
+    let (k, n, cols) = (4, 6, 64);
+    let matrix = transform_matrix(field, &default_key(k, n), k, n);
+    let mut transform = Transform::new(&matrix, n, k, cols, true);
+    let input : Vec<u8> = (0..=255).collect();
+    let mut rows = vec![Vec::new(); n];
+    for block in input.chunks(k * cols) {
+	transform.input().copy_from_slice(block);
+	transform.apply(field, use_ref);
+	for (r, row) in rows.iter_mut().enumerate() {
+	    let [first, rest] = transform.row(r, cols);
+	    row.extend_from_slice(first);
+	    row.extend_from_slice(rest);
+	}
+    }
+    let mut hash = Sha256::new();
+    for row in rows.iter() {
+	hash.update(row);
+    }
+    match hash.hex() {
+	hex if hex == KNOWN_SHARES => Ok(()),
+	hex => Err(format!("shares hash to {}", hex)),
+    }
+}
+
+// Two blocks each way, so that anything left over from the first
+// would show up in the second
+fn multiply_matches(field : &F8, use_ref : bool, k : usize, n : usize,
+		    cols : usize, rng : &mut Rng) -> Result<(), String> {
+    let matrix = transform_matrix(field, &default_key(k, n), k, n);
+    let mut rowwise = Transform::new(&matrix, n, k, cols, true);
+    let mut colwise = Transform::new(&matrix, n, k, cols, false);
+    for _ in 0..2 {
+	let input = rng.bytes(k * cols);
+	let want : Vec<Vec<u8>> = matrix.chunks(k).map(|row| {
+	    input.chunks(k).map(|col| {
+		row.iter().zip(col.iter())
+		    .fold(0, |sum, (&x, &y)| sum ^ field.mul(x, y))
+	    }).collect()
+	}).collect();
+
+	rowwise.input().copy_from_slice(&input);
+	rowwise.apply(field, use_ref);
+	colwise.input().copy_from_slice(&input);
+	colwise.apply(field, use_ref);
+	let columns = colwise.columns(cols);
+	for (r, want) in want.iter().enumerate() {
+	    let [first, rest] = rowwise.row(r, cols);
+	    if [first, rest].concat() != *want {
+		return Err(format!("row {} of rowwise output is wrong", r))
+	    }
+	    if columns.chunks(n).zip(want.iter()).any(|(col, &b)| col[r] != b) {
+		return Err(format!("row {} of colwise output is wrong", r))
+	    }
+	}
+    }
+    Ok(())
+}
+
+// Split a few blocks (and a bit) in memory, and combine a random k of
+// the shares
+fn round_trip(use_ref : bool, k : usize, n : usize, cols : usize,
+	      rng : &mut Rng) -> Result<(), String> {
+    let len = 3 * k * cols + rng.below(k * cols);
+    let data = rng.bytes(len);
+    let store = MemoryStore::new();
+    let opts = SplitOptions { k, n, bufsize : cols, use_ref,
+			      ..SplitOptions::default() };
+    let shares = split_into(&mut Cursor::new(&data), len, "selftest", &store,
+			    &opts).map_err(|e| e.to_string())?;
+
+    let mut names : Vec<&str> = shares.iter().map(|s| &s.name[..]).collect();
+    for i in 0..k {
+	let j = i + rng.below(n - i);
+	names.swap(i, j);
+    }
+    names.truncate(k);
+    let opts = CombineOptions { bufsize : cols, use_ref,
+				..CombineOptions::default() };
+    let mut set = ShareSet::open_in(&store, &names, true, None)
+	.map_err(|e| e.to_string())?;
+    let mut output = Vec::with_capacity(len);
+    set.decode(&opts, |block| { output.extend_from_slice(block); Ok(()) })
+	.map_err(|e| e.to_string())?;
+    if output != data {
+	return Err(format!("combining shares {} gave different data",
+			   names.join(" ")))
+    }
+    Ok(())
+}
+
+// Run all the checks, in order
+pub fn selftest(seed : u64) -> Vec<Check> {
+    let field = new_field(DEFAULT_POLY);
+    let mut rng = Rng(seed.max(1));	// xorshift is stuck at 0
+    let mut checks = vec![Check {
+	name : "field known answers".to_string(),
+	outcome : outcome(field_answers(&field)),
+    }];
+
+    for &kernel in [Kernel::Simd, Kernel::Reference].iter() {
+	let use_ref = kernel == Kernel::Reference;
+	let label = if use_ref { "reference" } else { "SIMD" };
+	if let (Kernel::Simd, Err(missing)) = (kernel, simd_support()) {
+	    checks.push(Check {
+		name : format!("{} kernel", label),
+		outcome : Outcome::Skipped(format!("CPU doesn't have {}",
+						   missing)),
+	    });
+	    continue
+	}
+	checks.push(Check {
+	    name : format!("{} share known answers", label),
+	    outcome : outcome(share_answers(&field, use_ref)),
+	});
+	for &(k, n, cols) in SHAPES.iter() {
+	    checks.push(Check {
+		name : format!("{} multiply, k = {}, n = {}, {} columns",
+			       label, k, n, cols),
+		outcome : outcome(multiply_matches(&field, use_ref, k, n, cols,
+						   &mut rng)),
+	    });
+	    checks.push(Check {
+		name : format!("{} round trip, k = {}, n = {}, {} columns",
+			       label, k, n, cols),
+		outcome : outcome(round_trip(use_ref, k, n, cols, &mut rng)),
+	    });
+	}
+    }
+    checks
+}
+
+// Fails if any check did
+pub fn selftest_result(checks : &[Check]) -> IdaResult<()> {
+    let failed = checks.iter()
+	.filter(|c| matches!(c.outcome, Outcome::Failed(_))).count();
+    if failed > 0 {
+	return Err(IdaError::new(ErrorClass::SelfTest, format!(
+	    "{} of {} self-test checks failed", failed, checks.len())))
+    }
+    Ok(())
+}
@@ -0,0 +1,675 @@
+// Where share files live
+//
+// split, combine, verify and repair don't open share files
+// themselves. They ask a ShareStore to list, open or create shares
+// by name, so that the same code can work on:
+//
+//   LocalStore   files in the local file system (names are paths,
+//                optionally relative to a root directory)
+//   MemoryStore  an in-memory map of name to contents (for tests)
+//   TarStore     entries in a tar archive
+//   UnionStore   several stores read as one
+//
+// New shares are written through a ShareWriter and only become
+// visible in the store when committed. commit_all() does that for a
+// group of shares, checking that all of them can be committed before
+// committing any.
+//
+// A LocalStore can also map files into memory (see mapped.rs) when
+// asked to. Other stores just read and write them.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::{Cursor, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use memmap2::Mmap;
+
+use crate::error::*;
+use crate::mapped::*;
+use crate::output::*;
+
+pub type ShareReader = Box<dyn Read + Send>;
+
+pub trait ShareWriter : Write + Send {
+    // Check (without committing) that commit() would be allowed
+    fn ready(&self) -> IdaResult<()> { Ok(()) }
+
+    // Make the share visible in the store
+    fn commit(self : Box<Self>) -> IdaResult<()>;
+}
+
+pub trait ShareStore {
+    // Names of all the files in the store
+    fn list(&self) -> IdaResult<Vec<String>>;
+
+    // Returns the file's contents and its length in bytes
+    fn open_read(&self, name : &str) -> IdaResult<(ShareReader, usize)>;
+
+    fn create_write(&self, name : &str) -> IdaResult<Box<dyn ShareWriter>>;
+
+    // The whole file mapped into memory, if the store does that and
+    // the file can be mapped. Otherwise, use open_read().
+    fn open_mapped(&self, _name : &str) -> IdaResult<Option<Mmap>> {
+	Ok(None)
+    }
+
+    // As create_write(), for a file that will be len bytes long
+    fn create_sized(&self, name : &str, _len : usize)
+		    -> IdaResult<Box<dyn ShareWriter>> {
+	self.create_write(name)
+    }
+
+    // The file's own name, without anything the store adds to it (eg,
+    // a UnionStore's labels)
+    fn inner_name<'a>(&'a self, name : &'a str) -> &'a str { name }
+}
+
+// Commit a group of new shares (eg, all the shares from a split) once
+// they've all been written
+pub fn commit_all(writers : Vec<Box<dyn ShareWriter>>) -> IdaResult<()> {
+    for writer in writers.iter() {
+	writer.ready()?;
+    }
+    for writer in writers {
+	writer.commit()?;
+    }
+    Ok(())
+}
+
+// Local files
+//
+// Without a root, names are just paths (relative to the current
+// directory). New files are written with OutputFile, so they follow
+// the --force and --sync options.
+pub struct LocalStore {
+    root   : Option<PathBuf>,
+    output : OutputOptions,
+    mmap   : bool,			// map files where possible
+}
+
+impl LocalStore {
+
+    pub fn new(output : OutputOptions) -> LocalStore {
+	LocalStore { root : None, output, mmap : false }
+    }
+
+    pub fn at(root : &str, output : OutputOptions) -> LocalStore {
+	LocalStore { root : Some(PathBuf::from(root)), output, mmap : false }
+    }
+
+    pub fn with_mmap(self, mmap : bool) -> LocalStore {
+	LocalStore { mmap, ..self }
+    }
+
+    fn path(&self, name : &str) -> String {
+	match &self.root {
+	    Some(root) => root.join(name).to_string_lossy().into_owned(),
+	    None => name.to_string(),
+	}
+    }
+}
+
+impl ShareWriter for OutputFile {
+    fn ready(&self) -> IdaResult<()> {
+	OutputFile::ready(self)
+    }
+    fn commit(self : Box<Self>) -> IdaResult<()> {
+	OutputFile::commit(*self)
+    }
+}
+
+// Every file below dir, named relative to it
+fn walk(dir : &Path, prefix : &str, names : &mut Vec<String>)
+	-> IdaResult<()> {
+    let listing = if dir == Path::new("") { Path::new(".") } else { dir };
+    let entries = fs::read_dir(listing)
+	.map_err(|e| IdaError::io(&listing.display().to_string(), e))?;
+    for entry in entries {
+	let entry = entry
+	    .map_err(|e| IdaError::io(&listing.display().to_string(), e))?;
+	let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+	match entry.file_type() {
+	    Ok(t) if t.is_dir() => {
+		walk(&entry.path(), &format!("{}/", name), names)?
+	    },
+	    Ok(t) if t.is_file() => names.push(name),
+	    _ => {},
+	}
+    }
+    Ok(())
+}
+
+impl ShareStore for LocalStore {
+
+    fn list(&self) -> IdaResult<Vec<String>> {
+	let mut names = Vec::new();
+	match &self.root {
+	    Some(root) => walk(root, "", &mut names)?,
+	    None => walk(Path::new(""), "", &mut names)?,
+	}
+	names.sort();
+	Ok(names)
+    }
+
+    fn open_read(&self, name : &str) -> IdaResult<(ShareReader, usize)> {
+	let path = self.path(name);
+	let fh = File::open(&path).map_err(|e| IdaError::io(&path, e))?;
+	let meta = fh.metadata().map_err(|e| IdaError::io(&path, e))?;
+	if !meta.is_file() {
+	    // a pipe (say) can't say how long it is without being read
+	    let data = read_all(fh).map_err(|e| IdaError::io(&path, e))?;
+	    let len = data.len();
+	    return Ok((Box::new(Cursor::new(data)), len))
+	}
+	Ok((Box::new(fh), meta.len() as usize))
+    }
+
+    fn create_write(&self, name : &str) -> IdaResult<Box<dyn ShareWriter>> {
+	Ok(Box::new(OutputFile::create(&self.path(name), &self.output)?))
+    }
+
+    fn open_mapped(&self, name : &str) -> IdaResult<Option<Mmap>> {
+	if !self.mmap { return Ok(None) }
+	let path = self.path(name);
+	let fh = File::open(&path).map_err(|e| IdaError::io(&path, e))?;
+	Ok(map_file(&fh))
+    }
+
+    fn create_sized(&self, name : &str, len : usize)
+		    -> IdaResult<Box<dyn ShareWriter>> {
+	match self.mmap {
+	    true => MappedOutput::create(&self.path(name), len, &self.output),
+	    false => self.create_write(name),
+	}
+    }
+}
+
+// Files held in memory. Clones share the same contents, and new files
+// replace any existing file of the same name.
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    files : Arc<Mutex<BTreeMap<String, Vec<u8>>>>,
+}
+
+impl MemoryStore {
+
+    pub fn new() -> MemoryStore {
+	MemoryStore::default()
+    }
+
+    pub fn get(&self, name : &str) -> Option<Vec<u8>> {
+	self.files.lock().unwrap().get(name).cloned()
+    }
+
+    pub fn insert(&self, name : &str, data : Vec<u8>) {
+	self.files.lock().unwrap().insert(name.to_string(), data);
+    }
+
+    pub fn remove(&self, name : &str) -> Option<Vec<u8>> {
+	self.files.lock().unwrap().remove(name)
+    }
+}
+
+struct MemoryWriter {
+    files : Arc<Mutex<BTreeMap<String, Vec<u8>>>>,
+    name  : String,
+    data  : Vec<u8>,
+}
+
+impl Write for MemoryWriter {
+    fn write(&mut self, buf : &[u8]) -> io::Result<usize> {
+	self.data.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+impl ShareWriter for MemoryWriter {
+    fn commit(self : Box<Self>) -> IdaResult<()> {
+	let writer = *self;
+	writer.files.lock().unwrap().insert(writer.name, writer.data);
+	Ok(())
+    }
+}
+
+impl ShareStore for MemoryStore {
+
+    fn list(&self) -> IdaResult<Vec<String>> {
+	Ok(self.files.lock().unwrap().keys().cloned().collect())
+    }
+
+    fn open_read(&self, name : &str) -> IdaResult<(ShareReader, usize)> {
+	let data = self.get(name).ok_or_else(|| IdaError::new(
+	    ErrorClass::Io, format!("{}: no such file in memory store", name)))?;
+	let len = data.len();
+	Ok((Box::new(Cursor::new(data)), len))
+    }
+
+    fn create_write(&self, name : &str) -> IdaResult<Box<dyn ShareWriter>> {
+	Ok(Box::new(MemoryWriter {
+	    files : Arc::clone(&self.files),
+	    name  : name.to_string(),
+	    data  : Vec::new(),
+	}))
+    }
+}
+
+// Tar archives
+//
+// An archive is either read (TarStore::open) or written
+// (TarStore::create), not both. Only plain files are used when
+// reading; directories, links and so on are skipped. Long names can
+// use either the ustar prefix field or GNU's "././@LongLink" entries,
+// and sizes can be octal or GNU's base-256.
+//
+// A tar header has to give the entry's size, so each new entry is
+// spooled to a hidden file next to the archive until it's committed,
+// and then copied in. The archive itself is an OutputFile, which only
+// appears under its real name when finish() is called. An archive
+// can also be written to a stream (eg, stdout), with entries spooled
+// in the temporary directory.
+
+const BLOCK : usize = 512;
+
+pub struct TarStore {
+    path    : String,
+    entries : BTreeMap<String, (u64, u64)>,	// offset and size of data
+    out     : Option<Arc<Mutex<TarOutput>>>,
+}
+
+struct TarOutput {
+    sink   : TarSink,
+    names  : Vec<String>,		// entries written so far
+    spools : usize,			// for naming spool files
+    spool_dir : PathBuf,
+}
+
+enum TarSink {
+    File(OutputFile),
+    Stream(Box<dyn Write + Send>),
+}
+
+impl TarSink {
+    fn writer(&mut self) -> &mut dyn Write {
+	match self {
+	    TarSink::File(file) => file.file(),
+	    TarSink::Stream(stream) => stream,
+	}
+    }
+}
+
+fn bad_tar(path : &str, msg : &str) -> IdaError {
+    IdaError::new(ErrorClass::BadHeader, format!("{}: {}", path, msg))
+}
+
+// A NUL-terminated (or full-width) string field
+fn tar_string(field : &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+fn tar_number(field : &[u8]) -> Option<u64> {
+    if field[0] & 0x80 != 0 {
+	// base-256, big endian
+	let mut value = (field[0] & 0x7f) as u64;
+	for &b in field[1..].iter() {
+	    value = value.checked_mul(256)? | b as u64;
+	}
+	return Some(value)
+    }
+    let text = tar_string(field);
+    let text = text.trim_matches(|c| c == ' ' || c == '\0');
+    if text.is_empty() { return Some(0) }
+    u64::from_str_radix(text, 8).ok()
+}
+
+fn tar_checksum(header : &[u8]) -> u64 {
+    header.iter().enumerate()
+	.map(|(i, &b)| if (148..156).contains(&i) { b' ' as u64 } else { b as u64 })
+	.sum()
+}
+
+fn padding(size : u64) -> u64 {
+    (BLOCK as u64 - size % BLOCK as u64) % BLOCK as u64
+}
+
+// Longest GNU long name we'll read. Real ones are at most PATH_MAX, and
+// the size comes straight from the archive, so don't trust it further.
+const MAX_LONG_NAME : u64 = 64 * 1024;
+
+fn put_octal(field : &mut [u8], value : u64) {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+}
+
+fn tar_header(name : &str, size : u64, mtime : u64) -> IdaResult<[u8; BLOCK]> {
+    let mut header = [0u8; BLOCK];
+    let bytes = name.as_bytes();
+    if bytes.len() <= 100 {
+	header[..bytes.len()].copy_from_slice(bytes);
+    } else {
+	// split at a '/' into prefix and name
+	let split = bytes.iter().enumerate()
+	    .filter(|&(i, &b)| b == b'/' && i <= 155 && bytes.len() - i - 1 <= 100
+		    && i + 1 < bytes.len())
+	    .map(|(i, _)| i)
+	    .next()
+	    .ok_or_else(|| IdaError::new(ErrorClass::Usage, format!(
+		"{}: name too long for a tar archive", name)))?;
+	header[..bytes.len() - split - 1].copy_from_slice(&bytes[split + 1..]);
+	header[345..345 + split].copy_from_slice(&bytes[..split]);
+    }
+    put_octal(&mut header[100..108], 0o644);
+    put_octal(&mut header[108..116], 0);
+    put_octal(&mut header[116..124], 0);
+    if size < 1 << 33 {
+	put_octal(&mut header[124..136], size);
+    } else {
+	header[124] = 0x80;
+	header[128..136].copy_from_slice(&size.to_be_bytes());
+    }
+    put_octal(&mut header[136..148], mtime);
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    let sum = format!("{:06o}\0 ", tar_checksum(&header));
+    header[148..156].copy_from_slice(sum.as_bytes());
+    Ok(header)
+}
+
+impl TarStore {
+
+    // Index the plain files in an existing archive. If a name appears
+    // more than once, the last one wins (as when tar extracts it).
+    pub fn open(path : &str) -> IdaResult<TarStore> {
+	let mut fh = File::open(path).map_err(|e| IdaError::io(path, e))?;
+	let mut entries = BTreeMap::new();
+	let mut long_name = None;
+	let mut offset = 0u64;
+	let mut header = [0u8; BLOCK];
+	loop {
+	    match fh.read_exact(&mut header) {
+		Ok(()) => {},
+		// some writers leave out the end-of-archive blocks
+		Err(e) if e.kind() == io::ErrorKind::UnexpectedEof
+		    && offset > 0 => break,
+		Err(e) => return Err(IdaError::io(path, e)),
+	    }
+	    offset += BLOCK as u64;
+	    if header.iter().all(|&b| b == 0) { break }
+	    if tar_number(&header[148..156]) != Some(tar_checksum(&header)) {
+		return Err(bad_tar(path, &format!(
+		    "bad tar header checksum at offset {}", offset - BLOCK as u64)))
+	    }
+	    let size = tar_number(&header[124..136])
+		.ok_or_else(|| bad_tar(path, "bad size in tar header"))?;
+	    match header[156] {
+		b'0' | 0 => {
+		    let mut name = tar_string(&header[..100]);
+		    let prefix = tar_string(&header[345..500]);
+		    if header[257..262] == *b"ustar" && !prefix.is_empty() {
+			name = format!("{}/{}", prefix, name);
+		    }
+		    if let Some(long) = long_name.take() { name = long }
+		    entries.insert(name, (offset, size));
+		},
+		b'L' => {
+		    if size > MAX_LONG_NAME {
+			return Err(bad_tar(path, &format!(
+			    "{}-byte long name in tar header", size)))
+		    }
+		    let mut name = vec![0u8; size as usize];
+		    fh.read_exact(&mut name).map_err(|e| IdaError::io(path, e))?;
+		    long_name = Some(tar_string(&name));
+		},
+		// the long name was for something we skip
+		_ => long_name = None,
+	    }
+	    offset = offset.checked_add(size)
+		.and_then(|end| end.checked_add(padding(size)))
+		.ok_or_else(|| bad_tar(path, "bad size in tar header"))?;
+	    fh.seek(SeekFrom::Start(offset)).map_err(|e| IdaError::io(path, e))?;
+	}
+	Ok(TarStore { path : path.to_string(), entries, out : None })
+    }
+
+    // Start a new archive
+    pub fn create(path : &str, output : OutputOptions) -> IdaResult<TarStore> {
+	let file = OutputFile::create(path, &output)?;
+	let spool_dir = Path::new(path).parent()
+	    .map_or(PathBuf::new(), |dir| dir.to_path_buf());
+	Ok(TarStore::writing(path, TarSink::File(file), spool_dir))
+    }
+
+    // Write an archive to stream; name is only used in messages
+    pub fn stream(name : &str, stream : Box<dyn Write + Send>) -> TarStore {
+	TarStore::writing(name, TarSink::Stream(stream), std::env::temp_dir())
+    }
+
+    fn writing(path : &str, sink : TarSink, spool_dir : PathBuf) -> TarStore {
+	TarStore {
+	    path : path.to_string(),
+	    entries : BTreeMap::new(),
+	    out : Some(Arc::new(Mutex::new(TarOutput {
+		sink, names : Vec::new(), spools : 0, spool_dir }))),
+	}
+    }
+
+    // Finish writing a new archive and put it in place. Any entries
+    // that haven't been committed by now are left out.
+    pub fn finish(self) -> IdaResult<()> {
+	let path = self.path;
+	let out = match self.out {
+	    Some(out) => out,
+	    None => return Ok(()),
+	};
+	let out = match Arc::try_unwrap(out) {
+	    Ok(out) => out.into_inner().unwrap(),
+	    Err(_) => return Err(IdaError::new(ErrorClass::Usage, format!(
+		"{}: entries still being written", path))),
+	};
+	let mut sink = out.sink;
+	sink.writer().write_all(&[0u8; 2 * BLOCK])
+	    .and_then(|_| sink.writer().flush())
+	    .map_err(|e| IdaError::io(&path, e))?;
+	match sink {
+	    TarSink::File(file) => file.commit(),
+	    TarSink::Stream(_) => Ok(()),
+	}
+    }
+}
+
+struct TarWriter {
+    out   : Arc<Mutex<TarOutput>>,
+    path  : String,			// the archive
+    name  : String,
+    spool : PathBuf,
+    file  : Option<File>,		// None once committed
+}
+
+impl Write for TarWriter {
+    fn write(&mut self, buf : &[u8]) -> io::Result<usize> {
+	self.file.as_mut().unwrap().write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+	self.file.as_mut().unwrap().flush()
+    }
+}
+
+impl Drop for TarWriter {
+    fn drop(&mut self) {
+	if self.file.take().is_some() {
+	    let _ = fs::remove_file(&self.spool);
+	}
+    }
+}
+
+fn already_in(out : &TarOutput, path : &str, name : &str) -> IdaResult<()> {
+    if out.names.iter().any(|n| n == name) {
+	return Err(IdaError::new(ErrorClass::Usage, format!(
+	    "{}: {} is already in the archive", path, name)))
+    }
+    Ok(())
+}
+
+impl ShareWriter for TarWriter {
+
+    fn ready(&self) -> IdaResult<()> {
+	already_in(&self.out.lock().unwrap(), &self.path, &self.name)
+    }
+
+    fn commit(mut self : Box<Self>) -> IdaResult<()> {
+	let mut spool = self.file.take().unwrap();
+	let result = self.copy_in(&mut spool);
+	drop(spool);
+	let _ = fs::remove_file(&self.spool);
+	result
+    }
+}
+
+impl TarWriter {
+    fn copy_in(&self, spool : &mut File) -> IdaResult<()> {
+	let mut out = self.out.lock().unwrap();
+	already_in(&out, &self.path, &self.name)?;
+	let size = spool.seek(SeekFrom::End(0))
+	    .and_then(|size| spool.seek(SeekFrom::Start(0)).map(|_| size))
+	    .map_err(|e| IdaError::io(&self.spool.to_string_lossy(), e))?;
+	let mtime = SystemTime::now().duration_since(UNIX_EPOCH)
+	    .map_or(0, |d| d.as_secs());
+	let header = tar_header(&self.name, size, mtime)?;
+	let archive = out.sink.writer();
+	archive.write_all(&header)
+	    .and_then(|_| io::copy(spool, archive))
+	    .and_then(|_| archive.write_all(&[0u8; BLOCK][..padding(size) as usize]))
+	    .map_err(|e| IdaError::io(&self.path, e))?;
+	out.names.push(self.name.clone());
+	Ok(())
+    }
+}
+
+impl ShareStore for TarStore {
+
+    fn list(&self) -> IdaResult<Vec<String>> {
+	let mut names : Vec<String> = self.entries.keys().cloned().collect();
+	if let Some(out) = &self.out {
+	    names.extend(out.lock().unwrap().names.iter().cloned());
+	    names.sort();
+	}
+	Ok(names)
+    }
+
+    fn open_read(&self, name : &str) -> IdaResult<(ShareReader, usize)> {
+	let &(offset, size) = self.entries.get(name).ok_or_else(|| {
+	    IdaError::new(ErrorClass::Io, format!(
+		"{}: no file {} in archive", self.path, name))
+	})?;
+	let mut fh = File::open(&self.path)
+	    .map_err(|e| IdaError::io(&self.path, e))?;
+	fh.seek(SeekFrom::Start(offset))
+	    .map_err(|e| IdaError::io(&self.path, e))?;
+	Ok((Box::new(fh.take(size)), size as usize))
+    }
+
+    fn create_write(&self, name : &str) -> IdaResult<Box<dyn ShareWriter>> {
+	let out = self.out.as_ref().ok_or_else(|| IdaError::new(
+	    ErrorClass::Usage, format!(
+		"{}: archive was opened for reading", self.path)))?;
+	let spool = {
+	    let mut out = out.lock().unwrap();
+	    already_in(&out, &self.path, name)?;
+	    out.spools += 1;
+	    let base = Path::new(&self.path).file_name()
+		.map(|s| s.to_string_lossy().into_owned())
+		.unwrap_or_default();
+	    out.spool_dir.join(format!(".{}.{}.spool-{}", base, out.spools,
+				       std::process::id()))
+	};
+	let file = File::options().read(true).write(true).create_new(true)
+	    .open(&spool)
+	    .map_err(|e| IdaError::io(&spool.to_string_lossy(), e))?;
+	Ok(Box::new(TarWriter {
+	    out : Arc::clone(out),
+	    path : self.path.clone(),
+	    name : name.to_string(),
+	    spool,
+	    file : Some(file),
+	}))
+    }
+}
+
+// Several stores read as one, eg partial archives from different
+// sites. Each name is prefixed with its store's label and a ':', so
+// the same file name can turn up in more than one.
+pub struct UnionStore {
+    stores : Vec<(String, Box<dyn ShareStore>)>,
+}
+
+impl UnionStore {
+
+    pub fn new() -> UnionStore {
+	UnionStore { stores : Vec::new() }
+    }
+
+    pub fn add(&mut self, label : &str, store : Box<dyn ShareStore>) {
+	self.stores.push((label.to_string(), store));
+    }
+
+    fn find<'a>(&'a self, name : &'a str)
+		-> IdaResult<(&'a dyn ShareStore, &'a str)> {
+	for (label, store) in self.stores.iter() {
+	    let inner = name.strip_prefix(&label[..])
+		.and_then(|rest| rest.strip_prefix(':'));
+	    if let Some(inner) = inner {
+		return Ok((store.as_ref(), inner))
+	    }
+	}
+	Err(IdaError::new(ErrorClass::Io, format!("{}: no such file", name)))
+    }
+}
+
+impl Default for UnionStore {
+    fn default() -> Self { UnionStore::new() }
+}
+
+impl ShareStore for UnionStore {
+
+    fn list(&self) -> IdaResult<Vec<String>> {
+	let mut names = Vec::new();
+	for (label, store) in self.stores.iter() {
+	    for name in store.list()? {
+		names.push(format!("{}:{}", label, name));
+	    }
+	}
+	Ok(names)
+    }
+
+    fn open_read(&self, name : &str) -> IdaResult<(ShareReader, usize)> {
+	let (store, inner) = self.find(name)?;
+	store.open_read(inner)
+    }
+
+    fn create_write(&self, name : &str) -> IdaResult<Box<dyn ShareWriter>> {
+	let (store, inner) = self.find(name)?;
+	store.create_write(inner)
+    }
+
+    fn open_mapped(&self, name : &str) -> IdaResult<Option<Mmap>> {
+	let (store, inner) = self.find(name)?;
+	store.open_mapped(inner)
+    }
+
+    fn create_sized(&self, name : &str, len : usize)
+		    -> IdaResult<Box<dyn ShareWriter>> {
+	let (store, inner) = self.find(name)?;
+	store.create_sized(inner, len)
+    }
+
+    fn inner_name<'a>(&'a self, name : &'a str) -> &'a str {
+	self.find(name).map_or(name, |(store, inner)| store.inner_name(inner))
+    }
+}
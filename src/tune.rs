@@ -0,0 +1,250 @@
+// Picking a buffer size (columns per block) by trying a few
+//
+// How fast a block multiplies depends a lot on its size: too small and
+// the per-block overhead dominates, too big and the input and output
+// buffers no longer fit in cache. Where the sweet spot is depends on
+// k, n, the kernel and the CPU, so "--auto-tune" times each of the
+// candidate sizes on a sample of the input (the file being split, or
+// the first share being combined) and uses the fastest.
+//
+// The choice can be saved to a config file, after which it's the
+// default for that operation, k and n. The file is JSON:
+//
+//   { "config": 1,
+//     "bufsize": [ { "op": "split", "k": 8, "n": 16, "bufsize": 16384 },
+//                  { "op": "combine", "k": 8, "bufsize": 32768 } ] }
+//
+// It's found at $IDA_CONFIG, or else ida/config.json under
+// $XDG_CONFIG_HOME (or ~/.config).
+
+use std::fs;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::*;
+use crate::codec::*;
+use crate::combine::*;
+use crate::error::*;
+use crate::json::*;
+use crate::output::*;
+use crate::split::*;
+
+// Sizes tried by default
+pub const CANDIDATES : [usize; 7] = [1024, 2048, 4096, 8192, 16384, 32768, 65536];
+
+// How much of the input to time each candidate on
+pub const SAMPLE_SIZE : usize = 1 << 20;
+
+// Each candidate gets passes over the sample until this much time has
+// gone by, so tiny samples still give a steady figure
+const MIN_TIME : Duration = Duration::from_millis(50);
+
+pub struct Timing {
+    pub bufsize : usize,
+    pub rate    : f64,		// input bytes per second
+}
+
+// Which of the timings was fastest
+pub fn fastest(timings : &[Timing]) -> usize {
+    timings.iter()
+	.max_by(|a, b| a.rate.partial_cmp(&b.rate).unwrap())
+	.map_or(0, |t| t.bufsize)
+}
+
+// Time a rows x k matrix on sample (taken as k-byte columns) with each
+// of the candidate sizes. Blocks are filled and emptied as split (rowwise output) or
+// combine (colwise) would, minus the I/O.
+pub fn time_bufsizes(matrix : &[u8], rows : usize, k : usize,
+		     rowwise : bool, sample : &[u8], poly : u16,
+		     use_ref : bool) -> Vec<Timing> {
+    let field = new_field(poly);
+    let use_ref = choose_kernel(use_ref, poly).0 == Kernel::Reference;
+    // whole columns only, and at least one
+    let mut sample = sample[..sample.len() - sample.len() % k].to_vec();
+    if sample.is_empty() { sample = vec![0; k] }
+    let total_cols = sample.len() / k;
+    let mut out = vec![0u8; total_cols.max(1) * rows];
+
+    CANDIDATES.iter().map(|&bufsize| {
+	let mut transform = Transform::new(matrix, rows, k, bufsize, rowwise);
+	let start = Instant::now();
+	let mut bytes = 0;
+	while bytes == 0 || start.elapsed() < MIN_TIME {
+	    let mut done = 0;
+	    for block in sample.chunks(bufsize * k) {
+		let cols = block.len() / k;
+		transform.input()[..block.len()].copy_from_slice(block);
+		transform.apply(&field, use_ref);
+		if rowwise {
+		    for r in 0..rows {
+			let [first, rest] = transform.row(r, cols);
+			let to = &mut out[r * total_cols + done..];
+			to[..first.len()].copy_from_slice(first);
+			to[first.len()..][..rest.len()].copy_from_slice(rest);
+		    }
+		} else {
+		    let plain = transform.columns(cols);
+		    out[done * rows..][..plain.len()].copy_from_slice(plain);
+		}
+		done += cols;
+	    }
+	    bytes += sample.len();
+	}
+	let secs = start.elapsed().as_secs_f64();
+	Timing { bufsize, rate : bytes as f64 / secs.max(1e-9) }
+    }).collect()
+}
+
+// Candidates for splitting with opts, timed on sample
+pub fn tune_split(sample : &[u8], opts : &SplitOptions)
+		  -> IdaResult<Vec<Timing>> {
+    let key = opts.check()?;
+    let field = new_field(opts.poly);
+    let matrix = transform_matrix(&field, &key, opts.k, opts.n);
+    Ok(time_bufsizes(&matrix, opts.n, opts.k, true, sample, opts.poly,
+		     opts.use_ref))
+}
+
+// Candidates for combining k shares with opts, timed on sample. Any
+// inverse matrix is as good as any other for timing.
+pub fn tune_combine(sample : &[u8], k : usize, opts : &CombineOptions)
+		    -> Vec<Timing> {
+    let field = new_field(opts.poly);
+    let matrix = transform_matrix(&field, &default_key(k, k), k, k);
+    let inverse = invert_matrix(&field, &matrix, k)
+	.expect("Cauchy matrices are invertible");
+    time_bufsizes(&inverse, k, k, false, sample, opts.poly, opts.use_ref)
+}
+
+// Up to SAMPLE_SIZE bytes from the start of a file
+pub fn read_sample(file : &str) -> IdaResult<Vec<u8>> {
+    let mut sample = Vec::new();
+    fs::File::open(file)
+	.and_then(|fh| fh.take(SAMPLE_SIZE as u64).read_to_end(&mut sample))
+	.map_err(|e| IdaError::io(file, e))?;
+    Ok(sample)
+}
+
+// As read_sample(), but of the data after a share's header
+pub fn read_share_sample(file : &str) -> IdaResult<(HeaderV1, Vec<u8>)> {
+    let mut fh = fs::File::open(file).map_err(|e| IdaError::io(file, e))?;
+    let header = read_sharefile_header(&mut fh)
+	.map_err(|e| IdaError::new(ErrorClass::BadHeader,
+				   format!("{}: {}", file, e)))?;
+    let mut sample = Vec::new();
+    fh.take(SAMPLE_SIZE as u64).read_to_end(&mut sample)
+	.map_err(|e| IdaError::io(file, e))?;
+    Ok((header, sample))
+}
+
+// What a saved buffer size is for: combine doesn't care about n
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TuneFor {
+    Split { k : usize, n : usize },
+    Combine { k : usize },
+}
+
+pub struct TuneConfig {
+    pub bufsizes : Vec<(TuneFor, usize)>,
+}
+
+// Where the config file lives, if there's anywhere to put it
+pub fn config_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("IDA_CONFIG") {
+	return Some(PathBuf::from(path))
+    }
+    let dir = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+	.or_else(|| std::env::var_os("HOME")
+		 .map(|home| Path::new(&home).join(".config")))?;
+    Some(dir.join("ida").join("config.json"))
+}
+
+fn bad(name : &str, msg : &str) -> IdaError {
+    IdaError::new(ErrorClass::Usage, format!("{}: {}", name, msg))
+}
+
+impl TuneConfig {
+
+    pub fn new() -> TuneConfig {
+	TuneConfig { bufsizes : Vec::new() }
+    }
+
+    pub fn bufsize(&self, what : TuneFor) -> Option<usize> {
+	self.bufsizes.iter().find(|(w, _)| *w == what).map(|(_, size)| *size)
+    }
+
+    pub fn set_bufsize(&mut self, what : TuneFor, bufsize : usize) {
+	self.bufsizes.retain(|(w, _)| *w != what);
+	self.bufsizes.push((what, bufsize));
+    }
+
+    pub fn to_json(&self) -> Json {
+	let sizes : Vec<Json> = self.bufsizes.iter().map(|(what, size)| {
+	    let entry = match *what {
+		TuneFor::Split { k, n } => Json::object()
+		    .with("op", "split").with("k", k).with("n", n),
+		TuneFor::Combine { k } => Json::object()
+		    .with("op", "combine").with("k", k),
+	    };
+	    entry.with("bufsize", *size)
+	}).collect();
+	Json::object()
+	    .with("config", 1usize)
+	    .with("bufsize", sizes)
+    }
+
+    pub fn parse(name : &str, text : &str) -> IdaResult<TuneConfig> {
+	let json = Json::parse(text).map_err(|e| bad(name, &e))?;
+	let number = |json : &Json, key : &str| json.get(key)
+	    .and_then(|v| v.as_number()).map(|v| v as usize)
+	    .ok_or_else(|| bad(name, &format!("missing or bad \"{}\"", key)));
+	if number(&json, "config")? != 1 {
+	    return Err(bad(name, "not a version 1 config file"))
+	}
+	let mut config = TuneConfig::new();
+	let sizes = json.get("bufsize").map_or(Some(&[][..]), |v| v.as_array())
+	    .ok_or_else(|| bad(name, "missing or bad \"bufsize\""))?;
+	for entry in sizes.iter() {
+	    let k = number(entry, "k")?;
+	    let what = match entry.get("op").and_then(|v| v.as_str()) {
+		Some("split") => TuneFor::Split { k, n : number(entry, "n")? },
+		Some("combine") => TuneFor::Combine { k },
+		_ => return Err(bad(name, "missing or bad \"op\"")),
+	    };
+	    match number(entry, "bufsize")? {
+		0 => return Err(bad(name, "buffer sizes can't be zero")),
+		size => config.set_bufsize(what, size),
+	    }
+	}
+	Ok(config)
+    }
+
+    // A missing file is the same as an empty one
+    pub fn load(path : &Path) -> IdaResult<TuneConfig> {
+	let name = path.to_string_lossy();
+	match fs::read_to_string(path) {
+	    Ok(text) => TuneConfig::parse(&name, &text),
+	    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+		Ok(TuneConfig::new())
+	    },
+	    Err(e) => Err(IdaError::io(&name, e)),
+	}
+    }
+
+    // Replaces any existing file
+    pub fn save(&self, path : &Path) -> IdaResult<()> {
+	let name = path.to_string_lossy();
+	if let Some(dir) = path.parent().filter(|d| *d != Path::new("")) {
+	    fs::create_dir_all(dir).map_err(|e| IdaError::io(&name, e))?;
+	}
+	let opts = OutputOptions { force : true, sync : false };
+	let mut out = OutputFile::create(&name, &opts)?;
+	writeln!(out, "{}", self.to_json()).map_err(|e| IdaError::io(&name, e))?;
+	out.commit()
+    }
+}
+
+impl Default for TuneConfig {
+    fn default() -> Self { TuneConfig::new() }
+}
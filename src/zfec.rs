@@ -0,0 +1,692 @@
+// zfec share file import and export
+//
+// zfec's command-line tools write each share to a file named
+// "<prefix>.<sharenum>_<m>.fec" (both numbers zero-padded to the
+// width of m), starting with a small header:
+//
+// bits          name      value
+// 8             m         total number of shares, minus 1
+// log2(m)       k         quorum, minus 1
+// log2(k)       pad       padding bytes added to the input
+// log2(m)       sharenum  share number, counting from zero
+//
+// where log2(x) is rounded up (zero bits if x is 1). The fields are
+// packed into a big-endian 2, 3 or 4-byte value, with the last field
+// in the high bits of the final byte. (This is zfec's own
+// _build_header/_parse_header format.)
+//
+// The input is encoded in segments of k * 4096 bytes (the final one
+// can be shorter). Each segment is cut into k equal-length stripes,
+// padding the last one with nulls, and share i receives one stripe's
+// worth of bytes from row i of a systematic Reed-Solomon
+// (Vandermonde-derived) matrix over GF(2^8) with polynomial 0x11d.
+// The first k shares are therefore plain copies of the stripes.
+//
+// This is a completely different layout from sharefiles, where each
+// column of the input is k consecutive bytes and the field
+// polynomial is 0x11b, so converting between the two means decoding
+// and re-encoding. We do that a block at a time in memory so that the
+// plaintext never has to be written to disk.
+//
+// guff-matrix's Matrix types can't be used here: they need at least
+// 16 elements (zfec's default k is 3) and only support the 0x11b
+// polynomial in SIMD. Instead, there's a simple table-driven
+// implementation of the few matrix operations we need.
+
+use std::fs::File;
+use std::fs::metadata;
+use std::io::prelude::*;
+
+use guff::*;
+use guff_ida::*;
+
+use crate::*;
+use crate::codec::invert_matrix;
+use crate::error::*;
+use crate::output::*;
+use crate::store::*;
+
+// zfec's segment size, per share
+const ZFEC_CHUNKSIZE : usize = 4096;
+
+// columns per block when reading or writing sharefiles
+const SHAREFILE_BLOCK : usize = 4096;
+
+pub struct ZfecHeader {
+    pub m        : usize,
+    pub k        : usize,
+    pub pad      : usize,
+    pub sharenum : usize,
+}
+
+// bits needed to store values 0 .. n - 1 (zfec's log_ceil(n, 2))
+fn bits_for(n : usize) -> usize {
+    let mut bits = 0;
+    while (1 << bits) < n { bits += 1 }
+    bits
+}
+
+impl ZfecHeader {
+
+    // total number of header bits in use
+    fn bits(&self) -> usize {
+        8 + bits_for(self.m) + bits_for(self.k) + bits_for(self.m)
+    }
+
+    pub fn header_len(&self) -> usize {
+        match self.bits() {
+            0..=16  => 2,
+            17..=24 => 3,
+            _       => 4,
+        }
+    }
+
+    // length of the original file, given the length of the data part
+    // of a share, or None if the share is too short for the padding
+    pub fn file_size(&self, share_len : usize) -> Option<usize> {
+        (share_len * self.k).checked_sub(self.pad)
+    }
+}
+
+pub fn read_zfec_header<R : Read + ?Sized>(file : &mut R)
+                        -> Result<ZfecHeader,String>
+{
+    let mut buf = [0u8; 4];
+
+    // the first two bytes are always enough to get m and k
+    if file.read_exact(&mut buf[0..2]).is_err() {
+        return Err("Problem reading zfec header".to_string())
+    }
+    let m = buf[0] as usize + 1;
+    let kbits = bits_for(m);
+    let k = ((buf[1] as usize) >> (8 - kbits)) + 1;
+    if k > m {
+        return Err(format!("zfec header has k ({}) > m ({})", k, m))
+    }
+
+    let partial = ZfecHeader { m, k, pad : 0, sharenum : 0 };
+    let len = partial.header_len();
+    if file.read_exact(&mut buf[2..len]).is_err() {
+        return Err("Problem reading zfec header".to_string())
+    }
+    let mut val = 0usize;
+    for byte in buf[..len].iter() {
+        val = (val << 8) | *byte as usize;
+    }
+    let shbits  = bits_for(m);
+    let padbits = bits_for(k);
+    val >>= len * 8 - partial.bits();
+    let sharenum = val & ((1 << shbits) - 1);
+    let pad = (val >> shbits) & ((1 << padbits) - 1);
+
+    if sharenum >= m {
+        return Err(format!("zfec share number {} out of range", sharenum))
+    }
+    if pad >= k {
+        return Err(format!("zfec padding {} out of range", pad))
+    }
+
+    Ok(ZfecHeader { m, k, pad, sharenum })
+}
+
+pub fn write_zfec_header<W : Write + ?Sized>(file : &mut W, header : &ZfecHeader)
+                         -> Result<usize, std::io::Error>
+{
+    let mut val = header.m - 1;
+    val = (val << bits_for(header.m)) | (header.k - 1);
+    val = (val << bits_for(header.k)) | header.pad;
+    val = (val << bits_for(header.m)) | header.sharenum;
+
+    let len = header.header_len();
+    val <<= len * 8 - header.bits();
+
+    let mut buffer = Vec::<u8>::with_capacity(4);
+    for byte in (0..len).rev() {
+        buffer.push((val >> (byte * 8)) as u8);
+    }
+    file.write_all(&buffer)?;
+    Ok(len)
+}
+
+// zfec's naming scheme: "%s.%0*d_%0*d%s"
+pub fn zfec_share_name(prefix : &str, sharenum : usize, m : usize)
+                       -> String {
+    let width = m.to_string().len();
+    format!("{}.{:0width$}_{:0width$}.fec", prefix, sharenum, m,
+            width = width)
+}
+
+// Field used by zfec (fec.c's "101110001" polynomial)
+pub fn zfec_field() -> F8 {
+    new_gf8(0x11d, 0x1d)
+}
+
+// Field used by sharefiles with w = 1 (and by Crypt::IDA)
+fn sharefile_field() -> F8 {
+    new_gf8(0x11b, 0x1b)
+}
+
+// Full 256 x 256 multiplication table. Row a holds a * b for all b.
+fn mul_table(field : &F8) -> Vec<u8> {
+    let mut table = vec![0u8; 256 * 256];
+    for a in 1..256 {
+        for b in 1..256 {
+            table[a * 256 + b] = field.mul(a as u8, b as u8);
+        }
+    }
+    table
+}
+
+// dest += coefficient * src
+fn fma_row(table : &[u8], dest : &mut [u8], coefficient : u8,
+           src : &[u8]) {
+    let row = &table[coefficient as usize * 256..][..256];
+    for (d, s) in dest.iter_mut().zip(src.iter()) {
+        *d ^= row[*s as usize];
+    }
+}
+
+// rows x k matrix times k x cols data (both rowwise), with each data
+// row in its own buffer
+fn multiply_rows(table : &[u8], matrix : &[u8], k : usize,
+                 input : &[Vec<u8>], output : &mut [Vec<u8>],
+                 cols : usize) {
+    for (r, out) in output.iter_mut().enumerate() {
+        let out = &mut out[..cols];
+        for byte in out.iter_mut() { *byte = 0 }
+        for (c, row) in input.iter().enumerate() {
+            fma_row(table, out, matrix[r * k + c], &row[..cols]);
+        }
+    }
+}
+
+// zfec's systematic encoding matrix (m rows of k)
+//
+// Rows of a Vandermonde matrix are taken at the points 0, 1, a, a^2,
+// ..., where a = 2 is the field's generator. Multiplying by the
+// inverse of the top k x k square makes the first k rows the identity
+// matrix without losing the property that any k rows are invertible.
+pub fn zfec_encode_matrix(k : usize, m : usize) -> Vec<u8> {
+    let field = zfec_field();
+
+    let mut vdm = vec![0u8; m * k];
+    vdm[0] = 1;
+    for row in 1..m {
+        for col in 0..k {
+            let exp = ((row - 1) * col) % 255;
+            vdm[row * k + col] = field.pow(2, exp as u16);
+        }
+    }
+
+    let top = invert_matrix(&field, &vdm[..k * k], k)
+        .expect("Vandermonde matrix should be invertible");
+    let mut enc = vec![0u8; m * k];
+    for row in 0..m {
+        for col in 0..k {
+            let mut sum = 0;
+            for i in 0..k {
+                sum ^= field.mul(vdm[row * k + i], top[i * k + col]);
+            }
+            enc[row * k + col] = sum;
+        }
+    }
+    enc
+}
+
+// Transform matrix for sharefiles (n rows of k, over 0x11b). This
+// uses the same Cauchy key as ida-split unless a Vandermonde matrix
+// is asked for.
+pub fn sharefile_transform(k : usize, n : usize, vandermonde : bool)
+                           -> Vec<u8> {
+    let field = sharefile_field();
+    if vandermonde {
+        vandermonde_matrix(&field, k, n)
+    } else {
+        let key = (1..).take(n + k).collect();
+        cauchy_matrix(&field, &key, k, n)
+    }
+}
+
+// Streams that yield the original file a block at a time
+
+fn read_rows(files : &mut [File], names : &[String], rows : &mut [Vec<u8>],
+             cols : usize) -> IdaResult<()> {
+    for (i, fh) in files.iter_mut().enumerate() {
+        fh.read_exact(&mut rows[i][..cols])
+            .map_err(|e| IdaError::io(&names[i], e))?;
+    }
+    Ok(())
+}
+
+fn bad_header(name : &str, msg : &str) -> IdaError {
+    IdaError::new(ErrorClass::BadHeader, format!("{}: {}", name, msg))
+}
+
+fn mismatch(name : &str, msg : &str) -> IdaError {
+    IdaError::new(ErrorClass::Mismatch, format!("{}: {}", name, msg))
+}
+
+struct ZfecReader {
+    files : Vec<File>,
+    names : Vec<String>,
+    k     : usize,
+    table : Vec<u8>,
+    inverse : Vec<u8>,
+    remaining_share : usize,	// bytes left to read from each share
+    remaining_file  : usize,	// bytes of output still to produce
+    input  : Vec<Vec<u8>>,
+    output : Vec<Vec<u8>>,
+}
+
+impl ZfecReader {
+
+    fn open(infiles : &[&str]) -> IdaResult<(ZfecReader, ZfecHeader)> {
+        if infiles.is_empty() {
+            return Err(IdaError::new(ErrorClass::Usage,
+                                     "No zfec share files supplied"))
+        }
+        let mut files = Vec::new();
+        let mut names = Vec::new();
+        let mut headers : Vec<ZfecHeader> = Vec::new();
+        let mut share_len = 0;
+        for name in infiles.iter() {
+            let mut fh = File::open(name)
+                .map_err(|e| IdaError::io(name, e))?;
+            let header = read_zfec_header(&mut fh)
+                .map_err(|e| bad_header(name, &e))?;
+            let len = metadata(name)
+                .map_err(|e| IdaError::io(name, e))?
+                .len() as usize - header.header_len();
+
+            if let Some(first) = headers.first() {
+                if header.m != first.m || header.k != first.k
+                    || header.pad != first.pad {
+                    return Err(mismatch(name, "mismatched zfec header"))
+                }
+                if len != share_len {
+                    return Err(mismatch(name, "mismatched share length"))
+                }
+            } else {
+                share_len = len;
+            }
+            if headers.iter().any(|h| h.sharenum == header.sharenum) {
+                eprintln!("File {} is a duplicate share; skipping", name);
+                continue
+            }
+
+            files.push(fh);
+            names.push(name.to_string());
+            headers.push(header);
+            if headers.len() == headers[0].k { break }
+        }
+
+        let k = headers[0].k;
+        if headers.len() < k {
+            return Err(IdaError::new(ErrorClass::Quorum, format!(
+                "Need {} zfec shares, only got {}", k, headers.len())))
+        }
+
+        let field = zfec_field();
+        let enc = zfec_encode_matrix(k, headers[0].m);
+        let mut rows = Vec::with_capacity(k * k);
+        for header in headers.iter() {
+            rows.extend_from_slice(&enc[header.sharenum * k..][..k]);
+        }
+        let inverse = invert_matrix(&field, &rows, k)
+            .ok_or_else(|| IdaError::new(ErrorClass::Mismatch,
+                                         "zfec decode matrix not invertible"))?;
+
+        let first = ZfecHeader { m : headers[0].m, k, pad : headers[0].pad,
+                                 sharenum : 0 };
+        let remaining_file = first.file_size(share_len)
+            .ok_or_else(|| IdaError::new(ErrorClass::Damaged, format!(
+                "{}: too short ({} bytes of data, {} bytes of padding)",
+                names[0], share_len, first.pad)))?;
+        Ok((ZfecReader {
+            files, names,
+            k, table : mul_table(&field), inverse,
+            remaining_share : share_len, remaining_file,
+            input  : vec![vec![0u8; ZFEC_CHUNKSIZE]; k],
+            output : vec![vec![0u8; ZFEC_CHUNKSIZE]; k],
+        }, first))
+    }
+
+    // Decode the next segment into buf; returns false at end of file
+    fn next_block(&mut self, buf : &mut Vec<u8>) -> IdaResult<bool> {
+        buf.clear();
+        if self.remaining_file == 0 { return Ok(false) }
+
+        let cols = self.remaining_share.min(ZFEC_CHUNKSIZE);
+        read_rows(&mut self.files, &self.names, &mut self.input, cols)?;
+        multiply_rows(&self.table, &self.inverse, self.k,
+                      &self.input, &mut self.output, cols);
+        for stripe in self.output.iter() {
+            buf.extend_from_slice(&stripe[..cols]);
+        }
+        buf.truncate(self.remaining_file);
+
+        self.remaining_share -= cols;
+        self.remaining_file  -= buf.len();
+        Ok(true)
+    }
+}
+
+struct SharefileReader {
+    files : Vec<File>,
+    names : Vec<String>,
+    k     : usize,
+    table : Vec<u8>,
+    inverse : Vec<u8>,
+    remaining_share : usize,
+    remaining_file  : usize,
+    input  : Vec<Vec<u8>>,
+    output : Vec<Vec<u8>>,
+}
+
+impl SharefileReader {
+
+    fn open(infiles : &[&str]) -> IdaResult<(SharefileReader, usize)> {
+        if infiles.is_empty() {
+            return Err(IdaError::new(ErrorClass::Usage,
+                                     "No share files supplied"))
+        }
+        let mut files = Vec::new();
+        let mut names = Vec::new();
+        let mut headers : Vec<HeaderV1> = Vec::new();
+        for name in infiles.iter() {
+            let mut fh = File::open(name)
+                .map_err(|e| IdaError::io(name, e))?;
+            let header = read_sharefile_header(&mut fh)
+                .map_err(|e| bad_header(name, &e))?;
+            if header.w != 1 {
+                return Err(bad_header(name, "only w = 1 is supported"))
+            }
+            if header.chunk_start != 0 {
+                return Err(bad_header(name, "combining chunks not supported"))
+            }
+            if !header.xform {
+                return Err(bad_header(name, "no transform row in header"))
+            }
+            if let Some(first) = headers.first() {
+                if header.k != first.k
+                    || header.chunk_next != first.chunk_next {
+                    return Err(mismatch(name, "mismatched header"))
+                }
+            }
+            if headers.iter().any(|h| h.xform_data == header.xform_data) {
+                eprintln!("File {} is a duplicate share; skipping", name);
+                continue
+            }
+            files.push(fh);
+            names.push(name.to_string());
+            headers.push(header);
+            if headers.len() == headers[0].k { break }
+        }
+
+        let k = headers[0].k;
+        if headers.len() < k {
+            return Err(IdaError::new(ErrorClass::Quorum, format!(
+                "Need {} share files, only got {}", k, headers.len())))
+        }
+
+        let field = sharefile_field();
+        let mut rows = Vec::with_capacity(k * k);
+        for header in headers.iter() {
+            rows.extend_from_slice(&header.xform_data);
+        }
+        let inverse = invert_matrix(&field, &rows, k)
+            .ok_or_else(|| IdaError::new(
+                ErrorClass::Mismatch,
+                "No matrix inverse (dependent shares supplied)"))?;
+
+        let chunk_next = headers[0].chunk_next;
+        Ok((SharefileReader {
+            files, names,
+            k, table : mul_table(&field), inverse,
+            remaining_share : chunk_next.div_ceil(k),
+            remaining_file  : chunk_next,
+            input  : vec![vec![0u8; SHAREFILE_BLOCK]; k],
+            output : vec![vec![0u8; SHAREFILE_BLOCK]; k],
+        }, k))
+    }
+
+    fn next_block(&mut self, buf : &mut Vec<u8>) -> IdaResult<bool> {
+        buf.clear();
+        if self.remaining_file == 0 { return Ok(false) }
+
+        let cols = self.remaining_share.min(SHAREFILE_BLOCK);
+        read_rows(&mut self.files, &self.names, &mut self.input, cols)?;
+        multiply_rows(&self.table, &self.inverse, self.k,
+                      &self.input, &mut self.output, cols);
+
+        // output rows are interleaved back into columns of k bytes
+        for col in 0..cols {
+            for row in self.output.iter() {
+                buf.push(row[col]);
+            }
+        }
+        buf.truncate(self.remaining_file);
+
+        self.remaining_share -= cols;
+        self.remaining_file  -= buf.len();
+        Ok(true)
+    }
+}
+
+// Encoders that take the original file a block at a time
+
+// Outputs only appear under their real names once all of them have
+// been written (see output.rs)
+fn create_all(outfiles : &[String], opts : &OutputOptions)
+              -> IdaResult<Vec<OutputFile>> {
+    outfiles.iter().map(|name| OutputFile::create(name, opts)).collect()
+}
+
+fn write_rows(files : &mut [OutputFile], names : &[String],
+              rows : &[Vec<u8>], cols : usize) -> IdaResult<()> {
+    for (i, fh) in files.iter_mut().enumerate() {
+        fh.write_all(&rows[i][..cols])
+            .map_err(|e| IdaError::io(&names[i], e))?;
+    }
+    Ok(())
+}
+
+fn finish_all(files : Vec<OutputFile>) -> IdaResult<()> {
+    commit_all(files.into_iter()
+               .map(|f| Box::new(f) as Box<dyn ShareWriter>).collect())
+}
+
+struct ZfecWriter {
+    files : Vec<OutputFile>,
+    names : Vec<String>,
+    k     : usize,
+    table : Vec<u8>,
+    enc   : Vec<u8>,
+    pending : Vec<u8>,
+    input   : Vec<Vec<u8>>,
+    output  : Vec<Vec<u8>>,
+}
+
+impl ZfecWriter {
+
+    fn create(outfiles : Vec<String>, k : usize, size : usize,
+              opts : &OutputOptions) -> IdaResult<ZfecWriter> {
+        let m = outfiles.len();
+        let mut files = create_all(&outfiles, opts)?;
+        let pad = (k - size % k) % k;
+        for (sharenum, fh) in files.iter_mut().enumerate() {
+            let header = ZfecHeader { m, k, pad, sharenum };
+            write_zfec_header(fh, &header)
+                .map_err(|e| IdaError::io(&outfiles[sharenum], e))?;
+        }
+        Ok(ZfecWriter {
+            files, names : outfiles, k,
+            table : mul_table(&zfec_field()),
+            enc : zfec_encode_matrix(k, m),
+            pending : Vec::with_capacity(k * ZFEC_CHUNKSIZE),
+            input  : vec![vec![0u8; ZFEC_CHUNKSIZE]; k],
+            output : vec![vec![0u8; ZFEC_CHUNKSIZE]; m],
+        })
+    }
+
+    fn encode_segment(&mut self, len : usize) -> IdaResult<()> {
+        let cols = len.div_ceil(self.k);
+        let pending = &self.pending;
+        for (i, stripe) in self.input.iter_mut().enumerate() {
+            for (c, byte) in stripe[..cols].iter_mut().enumerate() {
+                *byte = *pending.get(i * cols + c).unwrap_or(&0);
+            }
+        }
+        multiply_rows(&self.table, &self.enc, self.k,
+                      &self.input, &mut self.output, cols);
+        self.pending.drain(..len);
+        write_rows(&mut self.files, &self.names, &self.output, cols)
+    }
+
+    fn push(&mut self, data : &[u8]) -> IdaResult<()> {
+        let segment = self.k * ZFEC_CHUNKSIZE;
+        self.pending.extend_from_slice(data);
+        while self.pending.len() >= segment {
+            self.encode_segment(segment)?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> IdaResult<Vec<String>> {
+        if !self.pending.is_empty() {
+            let len = self.pending.len();
+            self.encode_segment(len)?;
+        }
+        finish_all(self.files)?;
+        Ok(self.names)
+    }
+}
+
+struct SharefileWriter {
+    files : Vec<OutputFile>,
+    names : Vec<String>,
+    k     : usize,
+    table : Vec<u8>,
+    xform : Vec<u8>,
+    pending : Vec<u8>,
+    input   : Vec<Vec<u8>>,
+    output  : Vec<Vec<u8>>,
+}
+
+impl SharefileWriter {
+
+    fn create(outfiles : Vec<String>, k : usize, xform : &[u8],
+              size : usize, opts : &OutputOptions)
+              -> IdaResult<SharefileWriter> {
+        let n = outfiles.len();
+        if xform.len() != n * k {
+            return Err(IdaError::new(ErrorClass::Usage, format!(
+                "Transform matrix should have {} rows of {}", n, k)))
+        }
+        let mut files = create_all(&outfiles, opts)?;
+        for (i, fh) in files.iter_mut().enumerate() {
+            let header = HeaderV1 {
+                k, w : 1, chunk_start : 0, chunk_next : size,
+                large_k : false, large_w : false,
+                is_final : true, xform : true,
+                xform_data : xform[i * k..][..k].to_vec(),
+            };
+            write_sharefile_header(fh, &header)
+                .map_err(|e| IdaError::io(&outfiles[i], e))?;
+        }
+        Ok(SharefileWriter {
+            files, names : outfiles, k,
+            table : mul_table(&sharefile_field()),
+            xform : xform.to_vec(),
+            pending : Vec::with_capacity(k * SHAREFILE_BLOCK),
+            input  : vec![vec![0u8; SHAREFILE_BLOCK]; k],
+            output : vec![vec![0u8; SHAREFILE_BLOCK]; n],
+        })
+    }
+
+    fn encode_block(&mut self, len : usize) -> IdaResult<()> {
+        // split columns of k bytes out into rows, zero-padding any
+        // partial final column
+        let k = self.k;
+        let cols = len.div_ceil(k);
+        let pending = &self.pending;
+        for (i, row) in self.input.iter_mut().enumerate() {
+            for (c, byte) in row[..cols].iter_mut().enumerate() {
+                *byte = *pending.get(c * k + i).unwrap_or(&0);
+            }
+        }
+        multiply_rows(&self.table, &self.xform, self.k,
+                      &self.input, &mut self.output, cols);
+        self.pending.drain(..len);
+        write_rows(&mut self.files, &self.names, &self.output, cols)
+    }
+
+    fn push(&mut self, data : &[u8]) -> IdaResult<()> {
+        let block = self.k * SHAREFILE_BLOCK;
+        self.pending.extend_from_slice(data);
+        while self.pending.len() >= block {
+            self.encode_block(block)?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> IdaResult<Vec<String>> {
+        if !self.pending.is_empty() {
+            let len = self.pending.len();
+            self.encode_block(len)?;
+        }
+        finish_all(self.files)?;
+        Ok(self.names)
+    }
+}
+
+// Convert k zfec shares into sharefiles
+//
+// Any k of the input files (with distinct share numbers) are enough.
+// The output files get one row each of xform, which must have k
+// columns for the same k as the zfec shares. Returns the original
+// file's size.
+pub fn zfec_to_sharefiles(infiles : &[&str], outfiles : Vec<String>,
+                          xform : &[u8], opts : &OutputOptions)
+                          -> IdaResult<usize> {
+    let (mut reader, header) = ZfecReader::open(infiles)?;
+    let size = reader.remaining_file;
+    let mut writer = SharefileWriter::create(outfiles, header.k,
+                                             xform, size, opts)?;
+    let mut buf = Vec::with_capacity(header.k * ZFEC_CHUNKSIZE);
+    while reader.next_block(&mut buf)? {
+        writer.push(&buf)?;
+    }
+    writer.finish()?;
+    Ok(size)
+}
+
+// Convert k sharefiles into m zfec shares named after prefix
+//
+// Returns the names of the files written.
+pub fn sharefiles_to_zfec(infiles : &[&str], prefix : &str,
+                          k : usize, m : usize, opts : &OutputOptions)
+                          -> IdaResult<Vec<String>> {
+    if k < 1 || k > m || m > 256 {
+        return Err(IdaError::new(ErrorClass::Usage, format!(
+            "Bad zfec parameters k = {}, m = {}", k, m)))
+    }
+    let (mut reader, _) = SharefileReader::open(infiles)?;
+    let size = reader.remaining_file;
+    let outfiles = (0..m).map(|i| zfec_share_name(prefix, i, m)).collect();
+    let mut writer = ZfecWriter::create(outfiles, k, size, opts)?;
+    let mut buf = Vec::with_capacity(reader.k * SHAREFILE_BLOCK);
+    while reader.next_block(&mut buf)? {
+        writer.push(&buf)?;
+    }
+    writer.finish()
+}
+
+// Read the k, m values of a zfec share (for choosing output defaults)
+pub fn zfec_scheme(infile : &str) -> IdaResult<(usize, usize)> {
+    let mut fh = File::open(infile)
+        .map_err(|e| IdaError::io(infile, e))?;
+    let header = read_zfec_header(&mut fh)
+        .map_err(|e| bad_header(infile, &e))?;
+    Ok((header.k, header.m))
+}
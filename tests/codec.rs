@@ -0,0 +1,83 @@
+// Transform gives the same answers as a plain matrix multiply, for
+// any block size
+
+use guff::*;
+use guff_sharefiles::codec::*;
+
+// Deterministic bytes, different for each use
+fn bytes(seed : usize, len : usize) -> Vec<u8> {
+    (0..len).map(|i| ((i * 89 + seed * 37 + i / 7) % 251) as u8 + 1).collect()
+}
+
+// rows x k matrix times k x cols (colwise) input, rowwise
+fn expected(field : &F8, matrix : &[u8], input : &[u8], k : usize,
+	    cols : usize) -> Vec<Vec<u8>> {
+    matrix.chunks(k).map(|row| {
+	(0..cols).map(|c| {
+	    row.iter().zip(input[c * k..][..k].iter())
+		.fold(0, |sum, (&x, &y)| sum ^ field.mul(x, y))
+	}).collect()
+    }).collect()
+}
+
+#[test]
+fn any_block_size() {
+    let field = new_field(DEFAULT_POLY);
+    for &(rows, k) in [(16, 8), (3, 2), (2, 2), (1, 1), (6, 4), (9, 3),
+		       (10, 5), (4, 4), (20, 16), (17, 16), (24, 20)].iter() {
+	let matrix = bytes(rows * k, rows * k);
+	for &cols in [1, 2, 5, 16, 18, 30, 64, 100, 4096].iter() {
+	    for &use_ref in [false, true].iter() {
+		let mut rowwise = Transform::new(&matrix, rows, k, cols, true);
+		let mut colwise = Transform::new(&matrix, rows, k, cols, false);
+
+		// twice, so stale data from the first block would show
+		for round in 0..2 {
+		    let input = bytes(round * 1000 + cols, k * cols);
+		    let want = expected(&field, &matrix, &input, k, cols);
+		    let what = format!("rows {}, k {}, cols {}, use_ref {}",
+				       rows, k, cols, use_ref);
+
+		    rowwise.input().copy_from_slice(&input);
+		    rowwise.apply(&field, use_ref);
+		    for (r, want) in want.iter().enumerate() {
+			let [first, rest] = rowwise.row(r, cols);
+			assert_eq!([first, rest].concat(), *want, "{}", what);
+			// a shorter row is a prefix of the full one
+			let [first, rest] = rowwise.row(r, cols / 2);
+			assert_eq!([first, rest].concat(), want[..cols / 2],
+				   "{}", what);
+		    }
+		    // the input is left alone
+		    assert!(rowwise.input() == &input[..], "{}", what);
+
+		    colwise.input().copy_from_slice(&input);
+		    colwise.apply(&field, use_ref);
+		    let got = colwise.columns(cols);
+		    for (r, want) in want.iter().enumerate() {
+			let column : Vec<u8> = got.chunks(rows)
+			    .map(|col| col[r]).collect();
+			assert_eq!(column, *want, "{}", what);
+		    }
+		}
+	    }
+	}
+    }
+}
+
+#[test]
+fn kernel_choice() {
+    assert_eq!(choose_kernel(true, DEFAULT_POLY).0, Kernel::Reference);
+    assert_eq!(choose_kernel(false, 0x11d).0, Kernel::Reference);
+    let (kernel, why) = choose_kernel(false, DEFAULT_POLY);
+    match simd_support() {
+	Ok(what) => {
+	    assert_eq!(kernel, Kernel::Simd);
+	    assert!(why.contains(what));
+	},
+	Err(missing) => {
+	    assert_eq!(kernel, Kernel::Reference);
+	    assert!(why.contains(missing));
+	},
+    }
+}
@@ -0,0 +1,34 @@
+// Helpers shared by the integration tests
+//
+// Each test file pulls this in with "mod common;" and only uses some
+// of it, hence the allow.
+
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Fresh scratch directory per test, since split writes its shares
+// next to the input file. Each test binary has its own pid, so names
+// only need to differ within a file.
+pub fn scratch(name : &str) -> PathBuf {
+    let dir = std::env::temp_dir()
+	.join(format!("guff-sharefiles-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// Deterministic bytes, different for each seed
+pub fn sample(seed : usize, len : usize) -> Vec<u8> {
+    (0..len).map(|i| ((i * 131 + seed * 71 + i / 253) % 256) as u8).collect()
+}
+
+// Write dir/sample and return its contents. It isn't a multiple of
+// any of the k values the tests use, and takes several blocks at
+// small buffer sizes.
+pub fn sample_file(dir : &Path) -> Vec<u8> {
+    let data = sample(0, 20011);
+    fs::write(dir.join("sample"), &data).unwrap();
+    data
+}
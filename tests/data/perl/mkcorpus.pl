@@ -0,0 +1,111 @@
+#!/usr/bin/perl
+#
+# Regenerate the Crypt::IDA interoperability corpus
+#
+# Usage: mkcorpus.pl [--builtin]
+#
+# Writes "sample" (33001 bytes of deterministic pseudo-random data)
+# and the seven share files "sample-0-0.sf" .. "sample-0-6.sf" created
+# from it with a (k=4, n=7, w=1) scheme into the current directory.
+#
+# If Crypt::IDA::ShareFile is installed, the shares are made with its
+# sf_split() routine. Otherwise (or if --builtin is given), a small
+# stand-alone encoder that writes the same sharefile layout is used
+# instead. Either way, GENERATOR says which one made the files, so
+# the tests can tell a real Crypt::IDA corpus from a stand-in.
+
+use strict;
+use warnings;
+
+my ($k, $n, $w) = (4, 7, 1);
+my $size = 33001;		# not a multiple of k, and > 1 block
+my @key  = (1 .. $k + $n);	# y1 .. yk, then x1 .. xn
+my $infile = "sample";
+
+my $builtin = grep { $_ eq "--builtin" } @ARGV;
+
+# deterministic "random" data (simple 32-bit LCG)
+my $seed = 0x5346;
+my $data = "";
+for (1 .. $size) {
+    $seed = (1103515245 * $seed + 12345) & 0xffffffff;
+    $data .= chr(($seed >> 16) & 0xff);
+}
+open my $out, ">:raw", $infile or die "$infile: $!\n";
+print $out $data;
+close $out;
+
+if (!$builtin && eval { require Crypt::IDA::ShareFile; 1 }) {
+    Crypt::IDA::ShareFile::sf_split(
+        filename => $infile,
+        quorum   => $k,
+        shares   => $n,
+        width    => $w,
+        key      => \@key,
+        filespec => "%f-%c-%s.sf",
+    );
+    generator("Crypt::IDA::ShareFile $Crypt::IDA::ShareFile::VERSION");
+    print "Shares written with Crypt::IDA::ShareFile::sf_split\n";
+    exit 0;
+}
+
+# record what made the shares
+sub generator {
+    open my $fh, ">", "GENERATOR" or die "GENERATOR: $!\n";
+    print $fh "$_[0]\n";
+    close $fh;
+}
+
+# GF(2^8) arithmetic with the 0x11b polynomial
+sub gf_mul {
+    my ($a, $b) = @_;
+    my $p = 0;
+    while ($b) {
+        $p ^= $a if $b & 1;
+        $a <<= 1;
+        $a ^= 0x11b if $a & 0x100;
+        $b >>= 1;
+    }
+    return $p;
+}
+sub gf_inv {
+    my $a = shift;
+    for my $b (1 .. 255) { return $b if gf_mul($a, $b) == 1 }
+    die "no inverse for $a\n";
+}
+
+# Cauchy matrix, one row per share: 1 / (x_i + y_j)
+my @y = @key[0 .. $k - 1];
+my @x = @key[$k .. $k + $n - 1];
+my @matrix = map { my $x = $_; [ map { gf_inv($x ^ $_) } @y ] } @x;
+
+# variable-length big-endian encoding of chunk offsets
+sub encode_length {
+    my $v = shift;
+    my @bytes;
+    while ($v) { unshift @bytes, $v & 0xff; $v >>= 8 }
+    return pack("C*", scalar(@bytes), @bytes);
+}
+
+# pad input with nulls to a whole number of columns
+my @input = unpack("C*", $data);
+push @input, 0 while @input % $k;
+my $cols = @input / $k;
+
+for my $share (0 .. $n - 1) {
+    my @row = @{ $matrix[$share] };
+    my $header = "SF" . pack("CCCC", 1, 0x0c, $k, $w) # final+transform
+        . encode_length(0) . encode_length($size) . pack("C*", @row);
+    my $body = "";
+    for my $col (0 .. $cols - 1) {
+        my $sum = 0;
+        $sum ^= gf_mul($row[$_], $input[$col * $k + $_]) for 0 .. $k - 1;
+        $body .= chr($sum);
+    }
+    my $file = "$infile-0-$share.sf";
+    open my $fh, ">:raw", $file or die "$file: $!\n";
+    print $fh $header, $body;
+    close $fh;
+}
+generator("built-in encoder");
+print "Shares written with built-in encoder\n";
@@ -0,0 +1,24 @@
+#!/bin/sh
+#
+# Regenerate the zfec fixture with zfec's own command-line tool
+#
+# Usage: mkfixture.sh   (run in this directory, with zfec installed:
+#                        "pip install zfec")
+#
+# Writes "sample" (30001 bytes of deterministic pseudo-random data:
+# more than one 3 * 4096-byte segment, and not a multiple of k) and the
+# five shares "sample.0_5.fec" .. "sample.4_5.fec" that zfec makes from
+# it with k = 3, m = 5.
+
+set -e
+
+perl -e '
+    my $seed = 0x7a66;
+    for (1 .. 30001) {
+        $seed = (1103515245 * $seed + 12345) & 0xffffffff;
+        print chr(($seed >> 16) & 0xff);
+    }' > sample
+
+rm -f sample.*_5.fec
+zfec -k 3 -m 5 -p sample sample
+zfec --version
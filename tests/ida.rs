@@ -0,0 +1,686 @@
+// Tests for the ida tool's subcommands
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+mod common;
+use common::*;
+
+fn ida(dir : &Path, args : &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_ida"))
+	.current_dir(dir)
+	.args(args)
+	.output().unwrap()
+}
+
+#[test]
+fn split_combine_small_k() {
+    let dir = scratch("small-k");
+    let original = sample_file(&dir);
+
+    // k < 4 needs padding to get 16-element matrices, and n = 6 isn't
+    // coprime with the requested buffer size
+    for (k, n) in [(2, 3), (3, 6), (4, 6)].iter() {
+	for mul in ["-r", "-s"].iter() {
+	    let (k, n) = (k.to_string(), n.to_string());
+	    let out = ida(&dir, &["split", "--force", mul, "-k", &k, "-n", &n,
+				  "-b", "1000", "sample"]);
+	    assert!(out.status.success());
+	    let out = ida(&dir, &["combine", "--force", mul, "-b", "999", "-o", "out",
+				  "sample-block.3", "sample-block.1",
+				  "sample-block.2", "sample-block.6"]);
+	    assert!(out.status.success());
+	    assert_eq!(fs::read(dir.join("out")).unwrap(), original,
+		       "k = {}, n = {}, {}", k, n, mul);
+	}
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn whole_file_split_large_k() {
+    let dir = scratch("whole");
+    for &(k, n, len) in [(16, 20, 30), (16, 20, 1), (20, 24, 33), (17, 17, 500)]
+	.iter() {
+	let original = sample(k + len, len);
+	fs::write(dir.join("small"), &original).unwrap();
+	for mul in ["-s", "-r"].iter() {
+	    let out = ida(&dir, &["split", "--force", mul, "-f",
+				  "-k", &k.to_string(), "-n", &n.to_string(),
+				  "small"]);
+	    assert!(out.status.success(), "k = {}, {} bytes", k, len);
+	    let mut args = vec!["combine", "--force", "-o", "out"];
+	    let shares : Vec<String> = (n - k + 1..=n)
+		.map(|i| format!("small-block.{}", i)).collect();
+	    args.extend(shares.iter().map(|s| &s[..]));
+	    assert!(ida(&dir, &args).status.success());
+	    assert_eq!(fs::read(dir.join("out")).unwrap(), original,
+		       "k = {}, n = {}, {} bytes, {}", k, n, len, mul);
+	}
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn verify_and_repair() {
+    let dir = scratch("repair");
+    sample_file(&dir);
+    let shares : Vec<String> = (1..=6).map(|i| format!("sample-block.{}", i))
+	.collect();
+    let all : Vec<&str> = shares.iter().map(|s| &s[..]).collect();
+
+    assert!(ida(&dir, &["split", "-k", "4", "-n", "6", "sample"])
+	    .status.success());
+    let mut args = vec!["verify"];
+    args.extend(all.iter());
+    assert!(ida(&dir, &args).status.success());
+
+    // corrupt a share that isn't used for decoding
+    let damaged = dir.join(&shares[5]);
+    let mut data = fs::read(&damaged).unwrap();
+    data[100] ^= 1;
+    fs::write(&damaged, &data).unwrap();
+    let out = ida(&dir, &args);
+    assert!(!out.status.success());
+    let report = String::from_utf8(out.stdout).unwrap();
+    assert!(report.contains("sample-block.6: data mismatch"));
+    assert!(report.contains("sample-block.5: OK"));
+
+    // regenerate it, plus one that's missing
+    let good = fs::read(dir.join(&shares[1])).unwrap();
+    fs::remove_file(&damaged).unwrap();
+    fs::remove_file(dir.join(&shares[1])).unwrap();
+    let out = ida(&dir, &["repair", "-n", "6", all[0], all[2], all[3],
+			  all[4]]);
+    assert!(out.status.success());
+    assert_eq!(fs::read(dir.join(&shares[1])).unwrap(), good);
+    assert!(ida(&dir, &args).status.success());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn combine_scan() {
+    let dir = scratch("scan");
+    let original = sample_file(&dir);
+    fs::create_dir(dir.join("shares")).unwrap();
+    assert!(ida(&dir, &["split", "-k", "4", "-n", "6", "-d", "shares",
+			"sample"]).status.success());
+
+    // a duplicate, a truncated share and something that isn't a share
+    let shares = dir.join("shares");
+    fs::copy(shares.join("sample-block.1"), shares.join("copy")).unwrap();
+    let data = fs::read(shares.join("sample-block.2")).unwrap();
+    fs::write(shares.join("sample-block.2"), &data[..1000]).unwrap();
+    fs::write(shares.join("notes.txt"), b"not a share\n").unwrap();
+
+    // headers claiming a 256-byte row and a 9-byte chunk_start
+    fs::write(shares.join("sample-block.7"), b"SF\x01\x08\x20\x10\x00\x00")
+	.unwrap();
+    fs::write(shares.join("sample-block.8"), b"SF\x01\x00\x02\x01\x09")
+	.unwrap();
+
+    for spec in ["shares", "shares/sample-block.*"].iter() {
+	let out = ida(&dir, &["combine", "--scan", spec, "-o", "out", "--force"]);
+	assert!(out.status.success(), "scan {}", spec);
+	assert_eq!(fs::read(dir.join("out")).unwrap(), original);
+	let errors = String::from_utf8_lossy(&out.stderr);
+	assert!(errors.contains("sample-block.7: Problem reading transform row"));
+	assert!(errors.contains("sample-block.8: chunk start length 9 is too big"));
+    }
+
+    // only three good shares left
+    fs::remove_file(shares.join("sample-block.3")).unwrap();
+    fs::remove_file(shares.join("sample-block.4")).unwrap();
+    let out = ida(&dir, &["combine", "--scan", "shares", "-o", "out"]);
+    assert!(!out.status.success());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn combine_scan_other_field() {
+    let dir = scratch("scan-field");
+    let original = sample_file(&dir);
+    fs::create_dir(dir.join("shares")).unwrap();
+    assert!(ida(&dir, &["split", "-k", "3", "-n", "12", "--field", "0x12b",
+			"-d", "shares", "sample"]).status.success());
+
+    // these rows are independent in this field, but not in the default one
+    for i in 3..=11 {
+	fs::remove_file(dir.join(format!("shares/sample-block.{}", i))).unwrap();
+    }
+    let out = ida(&dir, &["combine", "--scan", "shares", "--field", "0x12b",
+			  "-o", "out"]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(fs::read(dir.join("out")).unwrap(), original);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn combine_scan_same_size_files() {
+    let dir = scratch("scan-same-size");
+    let original = sample_file(&dir);
+    let other : Vec<u8> = original.iter().map(|b| b ^ 0x5a).collect();
+    fs::write(dir.join("other"), &other).unwrap();
+    fs::create_dir(dir.join("shares")).unwrap();
+    for file in ["sample", "other"].iter() {
+	assert!(ida(&dir, &["split", "-k", "3", "-n", "5", "-d", "shares",
+			    file]).status.success());
+    }
+
+    // two shares of one file don't get mixed in with the other's
+    let shares = dir.join("shares");
+    for i in 1..=3 {
+	fs::remove_file(shares.join(format!("sample-block.{}", i))).unwrap();
+    }
+    let out = ida(&dir, &["combine", "--scan", "shares", "-o", "out"]);
+    assert!(out.status.success());
+    assert_eq!(fs::read(dir.join("out")).unwrap(), other);
+
+    // a share that could be from either
+    fs::copy(shares.join("sample-block.4"), shares.join("copy")).unwrap();
+    let out = ida(&dir, &["combine", "--scan", "shares", "-o", "out2"]);
+    assert_eq!(out.status.code(), Some(5));
+    let errors = String::from_utf8_lossy(&out.stderr);
+    assert!(errors.contains("copy could be from other or sample"), "{}", errors);
+    assert!(!dir.join("out2").exists());
+    fs::remove_file(shares.join("copy")).unwrap();
+
+    // both complete
+    assert!(ida(&dir, &["split", "-k", "3", "-n", "5", "-d", "shares",
+			"--force", "sample"]).status.success());
+    let out = ida(&dir, &["combine", "--scan", "shares", "-o", "out2"]);
+    assert_eq!(out.status.code(), Some(5));
+    assert!(String::from_utf8_lossy(&out.stderr)
+	    .contains("Found more than one share set"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn combine_skips_duplicates() {
+    let dir = scratch("dups");
+    let original = sample_file(&dir);
+    assert!(ida(&dir, &["split", "-k", "4", "-n", "6", "sample"])
+	    .status.success());
+    fs::copy(dir.join("sample-block.2"), dir.join("copy-of-2")).unwrap();
+
+    // the duplicate is replaced by the next share on the list
+    let out = ida(&dir, &["combine", "-o", "out", "sample-block.1",
+			  "sample-block.2", "copy-of-2", "sample-block.3",
+			  "sample-block.5"]);
+    assert!(out.status.success());
+    assert_eq!(fs::read(dir.join("out")).unwrap(), original);
+    let errors = String::from_utf8(out.stderr).unwrap();
+    assert!(errors.contains("copy-of-2 is a duplicate of sample-block.2"));
+
+    // with no replacement, the error names both files
+    let out = ida(&dir, &["combine", "-o", "out", "sample-block.1",
+			  "sample-block.2", "copy-of-2", "sample-block.3"]);
+    assert!(!out.status.success());
+    let errors = String::from_utf8(out.stderr).unwrap();
+    assert!(errors.contains("copy-of-2 = sample-block.2"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn exit_codes() {
+    let dir = scratch("exit");
+    sample_file(&dir);
+    assert!(ida(&dir, &["split", "-k", "4", "-n", "6", "sample"])
+	    .status.success());
+    let data = fs::read(dir.join("sample-block.3")).unwrap();
+    fs::write(dir.join("short"), &data[..1000]).unwrap();
+
+    let code = |args : &[&str]| ida(&dir, args).status.code().unwrap();
+    assert_eq!(code(&["split", "--bogus", "sample"]), 2);
+    assert_eq!(code(&["split", "-k", "9", "-n", "6", "sample"]), 2);
+    assert_eq!(code(&["split", "missing"]), 3);
+    assert_eq!(code(&["combine", "-o", "out", "sample-block.1", "missing",
+		      "sample-block.2", "sample-block.4"]), 3);
+    assert_eq!(code(&["combine", "-o", "out", "sample-block.1", "sample",
+		      "sample-block.2", "sample-block.4"]), 4);
+    assert_eq!(code(&["combine", "-o", "out", "sample-block.1",
+		      "sample-block.2"]), 6);
+    assert_eq!(code(&["combine", "-o", "out", "sample-block.1", "short",
+		      "sample-block.2", "sample-block.4"]), 7);
+
+    // a header whose transform row (w * k = 512 bytes) is bigger than
+    // the buffer it used to be read into
+    fs::write(dir.join("big-row"), b"SF\x01\x08\x20\x10\x00\x00").unwrap();
+    assert_eq!(code(&["info", "big-row"]), 4);
+    assert_eq!(code(&["info", "--dump", "big-row"]), 4);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn json_output() {
+    let dir = scratch("json");
+    sample_file(&dir);
+    let stdout = |args : &[&str]| {
+	let out = ida(&dir, args);
+	assert!(out.status.success(), "{:?}", args);
+	String::from_utf8(out.stdout).unwrap()
+    };
+
+    // nothing but the JSON on stdout
+    let split = stdout(&["split", "--json", "-k", "4", "-n", "6", "sample"]);
+    assert!(split.starts_with("{\n") && split.ends_with("}\n"));
+    assert!(split.contains("\"size\": 20011"));
+    for i in 1..=6 {
+	assert!(split.contains(&format!("\"path\": \"sample-block.{}\"", i)));
+    }
+
+    let info = stdout(&["info", "--json", "sample-block.2"]);
+    assert!(info.contains("\"k\": 4"));
+    assert!(info.contains("\"chunk_next\": 20011"));
+    assert!(info.contains("\"data_length\": 5003"));
+    assert!(info.contains("\"transform\": ["));
+
+    let combine = stdout(&["combine", "--json", "-o", "out",
+			   "sample-block.6", "sample-block.2",
+			   "sample-block.4", "sample-block.1"]);
+    assert!(combine.contains("\"bytes\": 20011"));
+    assert!(combine.contains("\"path\": \"sample-block.6\""));
+
+    let verify = stdout(&["verify", "--json", "sample-block.1",
+			  "sample-block.2", "sample-block.3", "sample-block.4",
+			  "sample-block.5"]);
+    assert!(verify.contains("\"ok\": true"));
+    assert!(!verify.contains("\"ok\": false"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn info_share_set() {
+    let dir = scratch("info-set");
+    sample_file(&dir);
+    assert!(ida(&dir, &["split", "-k", "3", "-n", "5", "sample"]).status.success());
+    fs::copy(dir.join("sample-block.2"), dir.join("copy")).unwrap();
+    let data = fs::read(dir.join("sample-block.4")).unwrap();
+    fs::write(dir.join("short"), &data[..100]).unwrap();
+
+    let out = ida(&dir, &["info", "sample-block.1", "sample-block.2",
+			  "sample-block.5", "copy", "short"]);
+    assert!(out.status.success());
+    let text = String::from_utf8_lossy(&out.stdout);
+    assert!(text.contains("copy: duplicate of sample-block.2"));
+    assert!(text.contains("short: has 87 bytes of data, expected 6671"));
+    assert!(text.contains("3 usable shares, 3 independent, need 3"));
+    assert!(text.contains("Set is decodable; it can lose 0 more shares"));
+
+    // shares are numbered from their transform rows, whatever their name
+    let line = text.lines().find(|l| l.contains(" copy ")).unwrap();
+    assert_eq!(line.split_whitespace().nth(2), Some("2"));
+
+    let out = ida(&dir, &["info", "sample-block.1", "copy", "sample-block.2"]);
+    assert_eq!(out.status.code(), Some(6));
+    assert!(String::from_utf8_lossy(&out.stdout).contains("Set is not decodable"));
+
+    // files with bad headers are listed, not fatal
+    fs::write(dir.join("big-row"), b"SF\x01\x08\x20\x10\x00\x00").unwrap();
+    fs::write(dir.join("stray"), b"hello\n").unwrap();
+    let out = ida(&dir, &["info", "sample-block.1", "big-row", "sample-block.2",
+			  "stray", "sample-block.3"]);
+    assert!(out.status.success());
+    let text = String::from_utf8_lossy(&out.stdout);
+    for name in ["big-row", "stray"].iter() {
+	let line = text.lines().find(|l| l.contains(&format!(" {} ", name)))
+	    .unwrap();
+	assert!(line.starts_with('!') && line.ends_with("(unreadable)"), "{}", line);
+    }
+    assert!(text.contains("big-row: Problem reading transform row"));
+    assert!(text.contains("Set is decodable"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn info_dump() {
+    let dir = scratch("info-dump");
+    sample_file(&dir);
+    assert!(ida(&dir, &["split", "-k", "3", "-n", "5", "sample"]).status.success());
+
+    let out = ida(&dir, &["info", "--dump", "sample-block.1"]);
+    assert!(out.status.success());
+    let text = String::from_utf8_lossy(&out.stdout);
+    assert!(text.contains("chunk_next: 2 length bytes -> 20011"));
+    assert!(text.contains("Payload at offset 13: 6671 bytes, as expected"));
+
+    // damage the magic and cut the file off inside chunk_next
+    let mut data = fs::read(dir.join("sample-block.1")).unwrap();
+    data[1] = b'X';
+    data.truncate(9);
+    fs::write(dir.join("bad"), &data).unwrap();
+    let out = ida(&dir, &["info", "--dump", "bad"]);
+    assert_eq!(out.status.code(), Some(4));
+    let text = String::from_utf8_lossy(&out.stdout);
+    assert!(text.lines().next_back() == Some("File ends inside the header"));
+    let bad : Vec<&str> = text.lines().filter(|l| l.starts_with('!'))
+	.collect();
+    assert_eq!(bad.len(), 2);
+    assert!(bad[0].contains("expected 53 46"));
+    assert!(bad[1].contains("chunk_next: needs 3 bytes"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn rewrite_rows() {
+    let dir = scratch("rewrite");
+    let original = sample_file(&dir);
+    assert!(ida(&dir, &["split", "-k", "3", "-n", "5", "--matrix", "matrix",
+			"sample"]).status.success());
+    let shares = ["sample-block.1", "sample-block.3", "sample-block.5"];
+    let before = fs::metadata(dir.join(shares[0])).unwrap().len();
+
+    let mut args = vec!["info", "--rewrite", "--strip-row"];
+    args.extend(shares.iter());
+    assert!(ida(&dir, &args).status.success());
+    assert_eq!(fs::metadata(dir.join(shares[0])).unwrap().len(), before - 3);
+
+    // rows have to come from somewhere
+    let mut args = vec!["combine", "-o", "out"];
+    args.extend(shares.iter());
+    assert_eq!(ida(&dir, &args).status.code(), Some(4));
+    args.extend(["--matrix", "matrix"].iter());
+    assert!(ida(&dir, &args).status.success());
+    assert_eq!(fs::read(dir.join("out")).unwrap(), original);
+
+    // and when looking for them with --scan
+    let mut args = vec!["combine", "--scan", "sample-block.*", "-o", "out3"];
+    assert_eq!(ida(&dir, &args).status.code(), Some(6));
+    args.extend(["--matrix", "matrix"].iter());
+    assert!(ida(&dir, &args).status.success());
+    assert_eq!(fs::read(dir.join("out3")).unwrap(), original);
+
+    // put a row back under a new name, then combine as normal
+    assert!(ida(&dir, &["info", "--rewrite", "--matrix", "matrix", "--row", "5",
+			"--output", "copy", "sample-block.5"]).status.success());
+    assert!(ida(&dir, &["info", "--rewrite", "--matrix", "matrix",
+			"sample-block.1", "sample-block.3"]).status.success());
+    assert_eq!(fs::metadata(dir.join("copy")).unwrap().len(), before);
+    assert!(ida(&dir, &["combine", "-o", "out2", "sample-block.1",
+			"sample-block.3", "copy"]).status.success());
+    assert_eq!(fs::read(dir.join("out2")).unwrap(), original);
+
+    // a chunk range that doesn't fit the data is refused
+    let out = ida(&dir, &["info", "--rewrite", "--chunk-next", "30000", "copy"]);
+    assert_eq!(out.status.code(), Some(5));
+    assert_eq!(fs::metadata(dir.join("copy")).unwrap().len(), before);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn share_length_checked_first() {
+    let dir = scratch("length");
+    sample_file(&dir);
+    assert!(ida(&dir, &["split", "-k", "3", "-n", "5", "sample"]).status.success());
+    let data = fs::read(dir.join("sample-block.2")).unwrap();
+    fs::write(dir.join("sample-block.2"), &data[..data.len() - 5]).unwrap();
+    let mut data = fs::read(dir.join("sample-block.3")).unwrap();
+    data.push(0);
+    fs::write(dir.join("sample-block.3"), &data).unwrap();
+
+    // no output file is created for a bad share
+    for (bad, problem) in [("sample-block.2", "truncated"),
+			   ("sample-block.3", "too long")].iter() {
+	let out = ida(&dir, &["combine", "-o", "out", "sample-block.1", bad,
+			      "sample-block.4"]);
+	assert_eq!(out.status.code(), Some(7));
+	assert!(String::from_utf8_lossy(&out.stderr).contains(problem));
+	assert!(!dir.join("out").exists());
+
+	let out = ida(&dir, &["info", bad]);
+	assert_eq!(out.status.code(), Some(7));
+    }
+
+    // verify reports them, and checks the rest without them
+    let out = ida(&dir, &["verify", "sample-block.1", "sample-block.2",
+			  "sample-block.3", "sample-block.4", "sample-block.5"]);
+    assert_eq!(out.status.code(), Some(7));
+    let text = String::from_utf8_lossy(&out.stdout);
+    let lines : Vec<&str> = text.lines().collect();
+    assert_eq!(lines[0], "sample-block.1: OK");
+    assert!(lines[1].starts_with("sample-block.2: has 6666 bytes"));
+    assert!(lines[2].ends_with("(too long)"));
+    assert_eq!(lines[4], "sample-block.5: OK");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn no_clobber() {
+    let dir = scratch("clobber");
+    let original = sample_file(&dir);
+    fs::write(dir.join("sample-block.3"), b"precious\n").unwrap();
+
+    // nothing is written if any share already exists
+    let out = ida(&dir, &["split", "-k", "3", "-n", "5", "sample"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("--force"));
+    let mut names : Vec<String> = fs::read_dir(&dir).unwrap()
+	.map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+	.collect();
+    names.sort();
+    assert_eq!(names, ["sample", "sample-block.3"]);
+    assert_eq!(fs::read(dir.join("sample-block.3")).unwrap(), b"precious\n");
+
+    assert!(ida(&dir, &["split", "-k", "3", "-n", "5", "--force", "--sync",
+			"sample"]).status.success());
+    fs::write(dir.join("out"), b"precious\n").unwrap();
+    let out = ida(&dir, &["combine", "-o", "out", "sample-block.1",
+			  "sample-block.2", "sample-block.3"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(fs::read(dir.join("out")).unwrap(), b"precious\n");
+    assert!(ida(&dir, &["combine", "-o", "out", "--force", "sample-block.1",
+			"sample-block.2", "sample-block.3"]).status.success());
+    assert_eq!(fs::read(dir.join("out")).unwrap(), original);
+
+    // the same goes for a matrix file
+    for i in 1..=5 {
+	fs::remove_file(dir.join(format!("sample-block.{}", i))).unwrap();
+    }
+    fs::write(dir.join("matrix"), b"precious\n").unwrap();
+    let out = ida(&dir, &["split", "-k", "3", "-n", "5", "--matrix", "matrix",
+			  "sample"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(fs::read(dir.join("matrix")).unwrap(), b"precious\n");
+    assert!(!dir.join("sample-block.1").exists());
+    assert!(ida(&dir, &["split", "-k", "3", "-n", "5", "--matrix", "matrix",
+			"--force", "sample"]).status.success());
+    assert_eq!(fs::metadata(dir.join("matrix")).unwrap().len(), 15);
+
+    // no temporary files left behind
+    assert!(fs::read_dir(&dir).unwrap()
+	    .all(|e| !e.unwrap().file_name().to_string_lossy().starts_with('.')));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn convert_zfec() {
+    let dir = scratch("convert");
+    let original = sample_file(&dir);
+    assert!(ida(&dir, &["split", "-k", "3", "-n", "5", "sample"])
+	    .status.success());
+    let code = |args : &[&str]| ida(&dir, args).status.code().unwrap();
+
+    // bad numbers are usage errors, not panics
+    assert_eq!(code(&["convert", "-z", "-n", "five", "-o", "z",
+		      "sample-block.1"]), 2);
+    assert_eq!(code(&["convert", "-z", "-k", "x", "-n", "5", "-o", "z",
+		      "sample-block.1"]), 2);
+    assert_eq!(code(&["convert", "-z", "-o", "z", "sample-block.1"]), 2);
+    fs::write(dir.join("empty"), b"").unwrap();
+    assert_eq!(code(&["convert", "-o", "z", "empty"]), 4);
+
+    // existing outputs are left alone without --force
+    fs::write(dir.join("z.2_5.fec"), b"precious\n").unwrap();
+    assert_eq!(code(&["convert", "-z", "-n", "5", "-o", "z", "sample-block.1",
+		      "sample-block.3", "sample-block.5"]), 2);
+    assert_eq!(fs::read(dir.join("z.2_5.fec")).unwrap(), b"precious\n");
+    assert!(!dir.join("z.0_5.fec").exists());
+    assert_eq!(code(&["convert", "-z", "-n", "5", "-o", "z", "--force",
+		      "sample-block.1", "sample-block.3", "sample-block.5"]), 0);
+
+    // and back to sharefiles
+    assert_eq!(code(&["convert", "-o", "back", "z.4_5.fec", "z.0_5.fec",
+		      "z.2_5.fec"]), 0);
+    assert_eq!(code(&["combine", "-o", "out", "back-block.2", "back-block.4",
+		      "back-block.5"]), 0);
+    assert_eq!(fs::read(dir.join("out")).unwrap(), original);
+
+    // with a Vandermonde matrix instead
+    assert_eq!(code(&["convert", "-V", "-o", "vdm", "z.1_5.fec", "z.3_5.fec",
+		      "z.0_5.fec"]), 0);
+    assert_ne!(fs::read(dir.join("vdm-block.1")).unwrap(),
+	       fs::read(dir.join("back-block.1")).unwrap());
+    assert_eq!(code(&["combine", "-o", "out-vdm", "vdm-block.1", "vdm-block.3",
+		      "vdm-block.4"]), 0);
+    assert_eq!(fs::read(dir.join("out-vdm")).unwrap(), original);
+
+    // a duplicate share is skipped, as for sharefiles
+    fs::copy(dir.join("z.4_5.fec"), dir.join("copy.fec")).unwrap();
+    let out = ida(&dir, &["convert", "-o", "dup", "z.4_5.fec", "copy.fec",
+			  "z.0_5.fec", "z.2_5.fec"]);
+    assert!(out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("copy.fec"));
+    assert_eq!(code(&["combine", "-o", "out-dup", "dup-block.1", "dup-block.2",
+		      "dup-block.3"]), 0);
+    assert_eq!(fs::read(dir.join("out-dup")).unwrap(), original);
+
+    // shares cut off after the header are damaged (the file needs
+    // padding, so their length doesn't add up)
+    for i in [0, 1, 2].iter() {
+	let data = fs::read(dir.join(format!("z.{}_5.fec", i))).unwrap();
+	fs::write(dir.join(format!("h.{}_5.fec", i)), &data[..2]).unwrap();
+    }
+    assert_eq!(code(&["convert", "-o", "h", "h.0_5.fec", "h.1_5.fec",
+		      "h.2_5.fec"]), 7);
+    assert!(!dir.join("h-block.1").exists());
+
+    assert!(fs::read_dir(&dir).unwrap()
+	    .all(|e| !e.unwrap().file_name().to_string_lossy().starts_with('.')));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn naming_template() {
+    let dir = scratch("template");
+    let original = sample_file(&dir);
+    for disk in ["d1", "d2", "d3"].iter() {
+	fs::create_dir(dir.join(disk)).unwrap();
+    }
+    let template = "{name}.{index:02}.sf";
+
+    // shares go to each directory in turn
+    assert!(ida(&dir, &["split", "-k", "3", "-n", "5", "-d", "d1", "-d", "d2",
+			"-d", "d3", "--template", template, "sample"])
+	    .status.success());
+    for (disk, share) in [("d1", 1), ("d2", 2), ("d3", 3), ("d1", 4), ("d2", 5)]
+	.iter() {
+	let name = dir.join(disk).join(format!("sample.{:02}.sf", share));
+	assert!(name.exists(), "{}", name.display());
+    }
+
+    // something in the right place that doesn't fit the template
+    fs::write(dir.join("d3").join("sample-block.1"), b"junk\n").unwrap();
+    let out = ida(&dir, &["combine", "--scan", "d?", "--template", template,
+			  "-o", "out"]);
+    assert!(out.status.success());
+    assert!(!String::from_utf8_lossy(&out.stderr).contains("sample-block.1"));
+    assert_eq!(fs::read(dir.join("out")).unwrap(), original);
+
+    // repair works out names from the template too
+    fs::remove_file(dir.join("d3").join("sample.03.sf")).unwrap();
+    assert!(ida(&dir, &["repair", "-n", "5", "--template", template, "-d", "d3",
+			"d1/sample.01.sf", "d2/sample.02.sf", "d1/sample.04.sf",
+			"d2/sample.05.sf"]).status.success());
+    assert!(dir.join("d3").join("sample.03.sf").exists());
+
+    let out = ida(&dir, &["split", "--template", "{name}.sf", "sample"]);
+    assert_eq!(out.status.code(), Some(2));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn tar_archives() {
+    let dir = scratch("tar");
+    let original = sample_file(&dir);
+
+    assert!(ida(&dir, &["split", "-k", "3", "-n", "5", "--tar", "all.tar",
+			"sample"]).status.success());
+    // only the archive (no share files or spools) is left behind
+    let mut files : Vec<String> = fs::read_dir(&dir).unwrap()
+	.map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+	.collect();
+    files.sort();
+    assert_eq!(files, ["all.tar", "sample"]);
+
+    // the same archive on stdout, apart from timestamps
+    let out = ida(&dir, &["split", "-k", "3", "-n", "5", "--tar", "-", "sample"]);
+    assert!(out.status.success());
+    assert_eq!(out.stdout.len(), fs::metadata(dir.join("all.tar")).unwrap().len()
+	       as usize);
+    fs::write(dir.join("stdout.tar"), &out.stdout).unwrap();
+
+    let out = ida(&dir, &["combine", "--tar", "stdout.tar", "-o", "out"]);
+    assert!(out.status.success());
+    assert_eq!(fs::read(dir.join("out")).unwrap(), original);
+
+    let out = ida(&dir, &["combine", "--tar", "none.tar", "-o", "out2"]);
+    assert_eq!(out.status.code(), Some(3));
+    assert_eq!(ida(&dir, &["split", "--json", "--tar", "-", "sample"])
+	       .status.code(), Some(2));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn manifests() {
+    let dir = scratch("manifest");
+    let original = sample_file(&dir);
+    fs::create_dir(dir.join("sh")).unwrap();
+
+    assert!(ida(&dir, &["split", "-k", "3", "-n", "5", "-d", "sh", "--manifest",
+			"sh/sample.manifest.json", "sample"]).status.success());
+    assert!(ida(&dir, &["verify", "--manifest", "sh/sample.manifest.json"])
+	    .status.success());
+
+    // damage one share and lose another
+    let mut damaged = fs::read(dir.join("sh/sample-block.2")).unwrap();
+    damaged[100] ^= 1;
+    fs::write(dir.join("sh/sample-block.2"), &damaged).unwrap();
+    fs::remove_file(dir.join("sh/sample-block.4")).unwrap();
+    let out = ida(&dir, &["verify", "--manifest", "sh/sample.manifest.json"]);
+    assert_eq!(out.status.code(), Some(7));
+    let report = String::from_utf8_lossy(&out.stdout);
+    assert!(report.contains("sh/sample-block.2: data doesn't match"));
+    assert!(report.contains("sh/sample-block.4: missing"));
+
+    // listing the damaged share gets caught by the output's hash ...
+    let out = ida(&dir, &["combine", "--manifest", "sh/sample.manifest.json",
+			  "-o", "out", "sh/sample-block.1", "sh/sample-block.2",
+			  "sh/sample-block.3"]);
+    assert_eq!(out.status.code(), Some(7));
+    assert!(fs::metadata(dir.join("out")).is_err());
+    // ... and left to itself, combine skips it
+    assert!(ida(&dir, &["combine", "--manifest", "sh/sample.manifest.json",
+			"-o", "out"]).status.success());
+    assert_eq!(fs::read(dir.join("out")).unwrap(), original);
+
+    assert!(ida(&dir, &["repair", "--manifest", "sh/sample.manifest.json",
+			"--force"]).status.success());
+    assert!(ida(&dir, &["verify", "--manifest", "sh/sample.manifest.json"])
+	    .status.success());
+    assert_eq!(ida(&dir, &["split", "--manifest", "m.json", "--tar", "x.tar",
+			   "sample"]).status.code(), Some(2));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn selftest_passes() {
+    let dir = scratch("selftest");
+    let out = ida(&dir, &["selftest", "--json", "--seed", "7"]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stdout));
+    let report = String::from_utf8(out.stdout).unwrap();
+    assert!(report.contains("\"ok\": true"));
+    assert!(report.contains("\"seed\": 7"));
+    assert!(!report.contains("\"failed\""));
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,147 @@
+// Manifests: hashing, reading them back, and using them to check and
+// repair a share set
+
+use guff_sharefiles::combine::*;
+use guff_sharefiles::json::*;
+use guff_sharefiles::manifest::*;
+use guff_sharefiles::output::*;
+use guff_sharefiles::sha256::*;
+use guff_sharefiles::split::*;
+use guff_sharefiles::store::*;
+
+use std::fs;
+use std::path::Path;
+
+mod common;
+use common::*;
+
+fn sha256(data : &[u8]) -> String {
+    let mut hash = Sha256::new();
+    hash.update(data);
+    hash.hex()
+}
+
+#[test]
+fn sha256_vectors() {
+    assert_eq!(sha256(b""),
+	       "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    assert_eq!(sha256(b"abc"),
+	       "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    assert_eq!(sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+	       "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1");
+
+    // the same however the data is fed in
+    let data = sample(1, 30001);
+    let mut hash = Sha256::new();
+    for piece in data.chunks(61) {
+	hash.update(piece);
+    }
+    assert_eq!(hash.hex(), sha256(&data));
+}
+
+#[test]
+fn json_round_trip() {
+    let json = Json::object()
+	.with("name", "tab\there \"quoted\" \u{e9}")
+	.with("n", 18446744073709551615u64 as usize)
+	.with("row", vec![1u8, 2, 255])
+	.with("empty", Vec::<Json>::new())
+	.with("flag", false)
+	.with("none", Option::<usize>::None);
+    let parsed = Json::parse(&json.to_string()).unwrap();
+    assert_eq!(parsed.to_string(), json.to_string());
+    assert_eq!(parsed.get("name").and_then(|v| v.as_str()),
+	       Some("tab\there \"quoted\" \u{e9}"));
+    assert_eq!(parsed.get("flag").and_then(|v| v.as_bool()), Some(false));
+
+    let escaped = Json::parse(r#"["é😀\/"]"#).unwrap();
+    assert_eq!(escaped.as_array().unwrap()[0].as_str(), Some("\u{e9}\u{1f600}/"));
+    for bad in ["", "{", "[1,]", "{\"a\" 1}", "-1", "1.5", "\"x", "[1] 2"].iter() {
+	assert!(Json::parse(bad).is_err(), "{} parsed", bad);
+    }
+}
+
+#[test]
+fn check_and_repair() {
+    let dir = scratch("repair");
+    let data = sample(1, 30001);
+    let infile = dir.join("sample").to_string_lossy().into_owned();
+    fs::write(&infile, &data).unwrap();
+    let shares_dir = dir.join("shares");
+    fs::create_dir(&shares_dir).unwrap();
+    let path = shares_dir.join("sample.manifest.json").to_string_lossy()
+	.into_owned();
+    let opts = SplitOptions {
+	k : 3, n : 6, bufsize : 1000,
+	outdirs : vec![shares_dir.to_string_lossy().into_owned()],
+	..SplitOptions::default()
+    };
+    let (shares, _) = split_with_manifest(&infile, &path, &opts).unwrap();
+
+    // names are relative to the manifest, and come back as paths
+    let text = fs::read_to_string(&path).unwrap();
+    assert!(text.contains("\"path\": \"sample-block.1\""));
+    let manifest = Manifest::load(&path).unwrap();
+    assert_eq!((manifest.k, manifest.n, manifest.size), (3, 6, data.len()));
+    assert_eq!(manifest.sha256, sha256(&data));
+    for (share, listed) in shares.iter().zip(manifest.shares.iter()) {
+	assert_eq!(Path::new(&share.name), Path::new(&listed.name));
+	assert_eq!(share.header.xform_data, listed.transform);
+    }
+
+    // damage one share and lose another
+    let store = LocalStore::new(OutputOptions::default());
+    let names : Vec<String> = manifest.shares.iter().map(|s| s.name.clone())
+	.collect();
+    let names : Vec<&str> = names.iter().map(|s| &s[..]).collect();
+    let mut damaged = fs::read(names[1]).unwrap();
+    let last = damaged.len() - 1;
+    damaged[last] ^= 0x40;
+    fs::write(names[1], &damaged).unwrap();
+    fs::remove_file(names[4]).unwrap();
+    let opts = CombineOptions { bufsize : 1000, manifest : Some(manifest),
+				..CombineOptions::default() };
+    let checks = verify_in(&store, &names, &opts).unwrap();
+    let bad : Vec<usize> = checks.iter().enumerate()
+	.filter(|(_, c)| c.problem.is_some()).map(|(i, _)| i).collect();
+    assert_eq!(bad, [1, 4]);
+
+    // the damaged share makes the output fail its check, and nothing
+    // is written
+    let outfile = dir.join("out").to_string_lossy().into_owned();
+    let err = combine_files(&names[..3], &outfile, &opts).err()
+	.expect("damaged output not caught");
+    assert_eq!(err.class, guff_sharefiles::error::ErrorClass::Damaged);
+    assert!(fs::metadata(&outfile).is_err());
+
+    // repair rewrites the missing share, but only replaces the
+    // damaged one with force
+    let err = repair_manifest(&store, None, &opts).err()
+	.expect("damaged share overwritten");
+    assert!(err.to_string().contains("already exists"));
+    let output = OutputOptions { force : true, sync : false };
+    let store = LocalStore::new(output);
+    let force = CombineOptions { output, ..opts };
+    let written = repair_manifest(&store, None, &force).unwrap();
+    assert_eq!(written.len(), 2);
+    assert!(verify_in(&store, &names, &force).unwrap()
+	    .iter().all(|c| c.problem.is_none()));
+    assert!(repair_manifest(&store, None, &force).unwrap().is_empty());
+
+    // and shares without transform rows get them from the manifest
+    for name in names.iter() {
+	let whole = fs::read(name).unwrap();
+	let mut header = guff_sharefiles::read_sharefile_header(&mut &whole[..])
+	    .unwrap();
+	let data = &whole[whole.len() - header.share_len()..];
+	header.xform = false;
+	header.xform_data.clear();
+	let mut stripped = Vec::new();
+	guff_sharefiles::write_sharefile_header(&mut stripped, &header).unwrap();
+	stripped.extend_from_slice(data);
+	fs::write(name, &stripped).unwrap();
+    }
+    combine_files(&names[3..], &outfile, &force).unwrap();
+    assert!(fs::read(&outfile).unwrap() == data);
+    fs::remove_dir_all(&dir).unwrap();
+}
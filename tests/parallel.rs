@@ -0,0 +1,188 @@
+// Blocks pipelined and multiplied on several threads come out as if
+// from one
+
+use guff_sharefiles::combine::*;
+use guff_sharefiles::error::*;
+use guff_sharefiles::parallel::*;
+use guff_sharefiles::split::*;
+use guff_sharefiles::store::*;
+
+use std::io;
+use std::io::{Cursor, Read};
+
+mod common;
+use common::*;
+
+// Numbers 0..count, each squared by a worker, should be drained in
+// order however many threads there are
+fn squares(threads : usize, count : usize) -> IdaResult<Vec<usize>> {
+    let mut next = 0;
+    let mut out = Vec::new();
+    run_blocks(threads, vec![0usize; block_count(threads)],
+	       |block| {
+		   if next == count { return Ok(false) }
+		   *block = next;
+		   next += 1;
+		   Ok(true)
+	       },
+	       |block| {
+		   // uneven work, so blocks finish out of order
+		   if *block % 3 == 0 {
+		       std::thread::sleep(std::time::Duration::from_millis(1));
+		   }
+		   *block *= *block
+	       },
+	       |block| { out.push(*block); Ok(()) })?;
+    Ok(out)
+}
+
+#[test]
+fn blocks_stay_in_order() {
+    let expect : Vec<usize> = (0..200).map(|i| i * i).collect();
+    for &threads in [1, 2, 3, 8].iter() {
+	assert_eq!(squares(threads, 200).unwrap(), expect);
+	assert!(squares(threads, 0).unwrap().is_empty());
+    }
+    assert!(thread_count(0) >= 1);
+    assert_eq!(thread_count(5), 5);
+}
+
+#[test]
+fn errors_stop_the_run() {
+    let mut next = 0;
+    let mut drained = 0;
+    let result = run_blocks(4, vec![0usize; block_count(4)],
+			    |block| { *block = next; next += 1; Ok(true) },
+			    |_| (),
+			    |block| {
+				if *block == 10 {
+				    return Err(IdaError::new(ErrorClass::Io,
+							     "disk full"))
+				}
+				drained += 1;
+				Ok(())
+			    });
+    assert_eq!(result.unwrap_err().to_string(), "disk full");
+    assert_eq!(drained, 10);
+}
+
+#[test]
+fn read_errors_stop_the_run() {
+    let mut next = 0;
+    let mut drained = Vec::new();
+    let result = run_blocks(2, vec![0usize; block_count(2)],
+			    |block| {
+				if next == 7 {
+				    return Err(IdaError::new(ErrorClass::Io,
+							     "read failed"))
+				}
+				*block = next;
+				next += 1;
+				Ok(true)
+			    },
+			    |_| (),
+			    |block| { drained.push(*block); Ok(()) });
+    assert_eq!(result.unwrap_err().to_string(), "read failed");
+    // what was read before the error still gets passed on
+    assert_eq!(drained, [0, 1, 2, 3, 4, 5, 6]);
+}
+
+#[test]
+#[should_panic(expected = "bad block")]
+fn worker_panics_are_passed_on() {
+    let mut next = 0;
+    let _ = run_blocks(2, vec![0usize; block_count(2)],
+		       |block| { *block = next; next += 1; Ok(next < 10) },
+		       |block| if *block == 5 { panic!("bad block") },
+		       |_| Ok(()));
+}
+
+#[test]
+fn split_with_threads() {
+    let data = sample(2, 100003);
+    let split = |threads, whole| {
+	let store = MemoryStore::new();
+	let opts = SplitOptions { k : 5, n : 9, bufsize : 1000, threads, whole,
+				  ..SplitOptions::default() };
+	split_into(&mut Cursor::new(&data), data.len(), "sample", &store,
+		   &opts).unwrap();
+	let names = store.list().unwrap();
+	names.iter().map(|name| store.get(name).unwrap()).collect::<Vec<_>>()
+    };
+    let serial = split(1, false);
+    assert_eq!(serial.len(), 9);
+    for &threads in [2, 7, 0].iter() {
+	assert!(split(threads, false) == serial, "{} threads", threads);
+    }
+    // and the same again as a single block
+    assert!(split(0, true) == serial, "whole file");
+}
+
+// A store where one share can't be read past some point
+struct Failing {
+    inner : MemoryStore,
+    name  : String,
+    after : usize,
+}
+
+struct FailingReader {
+    inner : ShareReader,
+    left  : usize,
+}
+
+impl Read for FailingReader {
+    fn read(&mut self, buf : &mut [u8]) -> io::Result<usize> {
+	if self.left == 0 {
+	    return Err(io::Error::other("bad sector"))
+	}
+	let want = buf.len().min(self.left);
+	let got = self.inner.read(&mut buf[..want])?;
+	self.left -= got;
+	Ok(got)
+    }
+}
+
+impl ShareStore for Failing {
+    fn list(&self) -> IdaResult<Vec<String>> { self.inner.list() }
+
+    fn open_read(&self, name : &str) -> IdaResult<(ShareReader, usize)> {
+	let (inner, len) = self.inner.open_read(name)?;
+	if name != self.name { return Ok((inner, len)) }
+	Ok((Box::new(FailingReader { inner, left : self.after }), len))
+    }
+
+    fn create_write(&self, name : &str) -> IdaResult<Box<dyn ShareWriter>> {
+	self.inner.create_write(name)
+    }
+}
+
+#[test]
+fn combine_with_threads() {
+    let data = sample(2, 100003);
+    let store = MemoryStore::new();
+    let opts = SplitOptions { k : 4, n : 7, bufsize : 999, threads : 3,
+			      ..SplitOptions::default() };
+    split_into(&mut Cursor::new(&data), data.len(), "sample", &store, &opts)
+	.unwrap();
+    let names = store.list().unwrap();
+    let names : Vec<&str> = names.iter().map(|s| &s[..]).collect();
+
+    for &threads in [1, 2, 5, 0].iter() {
+	let opts = CombineOptions { bufsize : 777, threads,
+				    ..CombineOptions::default() };
+	let out = format!("out{}", threads);
+	combine_in(&store, &names[2..6], &out, &opts).unwrap();
+	assert!(store.get(&out).unwrap() == data, "{} threads", threads);
+    }
+
+    // a share that fails part way through is the one blamed
+    let failing = Failing { inner : store.clone(), name : names[4].to_string(),
+			    after : 10000 };
+    let mut set = ShareSet::open_in(&failing, &names[2..6], true, None)
+	.unwrap();
+    let opts = CombineOptions { bufsize : 777, threads : 2,
+				..CombineOptions::default() };
+    let err = set.decode(&opts, |_| Ok(())).unwrap_err();
+    assert!(err.to_string().contains("bad sector"));
+    assert_eq!(set.failed, Some(2));
+}
@@ -0,0 +1,94 @@
+// Round-trip tests against the corpus in tests/data/perl. It has
+// Crypt::IDA's naming and layout, but the committed copy was made by
+// mkcorpus.pl's built-in encoder rather than the Perl module (see the
+// README there). Until it's regenerated with the Perl module, these
+// only show that split and combine agree with that encoder.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+mod common;
+use common::*;
+
+const K : usize = 4;
+const N : usize = 7;
+
+fn corpus(file : &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "tests", "data", "perl", file]
+	.iter().collect()
+}
+
+fn combine(mul : &str, shares : &[usize], outfile : &PathBuf) {
+    let status = Command::new(env!("CARGO_BIN_EXE_ida-combine"))
+	.arg(mul).arg("--force")
+	.arg("-o").arg(outfile)
+	.args(shares.iter()
+	      .map(|s| corpus(&format!("sample-0-{}.sf", s))))
+	.status().unwrap();
+    assert!(status.success());
+}
+
+// The tests below only mean something once this passes
+#[test]
+#[ignore = "corpus not yet made by Crypt::IDA (see tests/data/perl/README)"]
+fn made_by_crypt_ida() {
+    let generator = fs::read_to_string(corpus("GENERATOR")).unwrap();
+    assert!(generator.starts_with("Crypt::IDA::ShareFile"),
+	    "corpus made by {}", generator.trim());
+}
+
+#[test]
+fn combine_perl_shares() {
+    let dir = scratch("combine");
+    let original = fs::read(corpus("sample")).unwrap();
+
+    // first k, last k, and out-of-order selections
+    let selections = [ [0, 1, 2, 3], [3, 4, 5, 6], [6, 1, 4, 2] ];
+    for mul in &["-r", "-s"] {
+	for shares in selections.iter() {
+	    let outfile = dir.join("out");
+	    combine(mul, shares, &outfile);
+	    assert_eq!(fs::read(&outfile).unwrap(), original,
+		       "combine {} of shares {:?}", mul, shares);
+	}
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn split_matches_perl_shares() {
+    for mul in &["-r", "-s"] {
+	let dir = scratch(&format!("split{}", mul));
+	let infile = dir.join("sample");
+	fs::copy(corpus("sample"), &infile).unwrap();
+
+	let status = Command::new(env!("CARGO_BIN_EXE_ida-split"))
+	    .arg(mul)
+	    .args(["-k", &K.to_string(), "-n", &N.to_string()])
+	    .arg("--perl-compat")
+	    .arg(&infile)
+	    .status().unwrap();
+	assert!(status.success());
+
+	for share in 0..N {
+	    let name = format!("sample-0-{}.sf", share);
+	    assert_eq!(fs::read(dir.join(&name)).unwrap(),
+		       fs::read(corpus(&name)).unwrap(),
+		       "split {} share {}", mul, name);
+	}
+	fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[test]
+fn share_names_parse_both_ways() {
+    use guff_sharefiles::{split_share_name, share_number, perl_share_name};
+    assert_eq!(split_share_name(&perl_share_name("dir/a-b", 0, 6)),
+	       Some(("dir/a-b", 6)));
+    assert_eq!(split_share_name("dir/a-b-block.3"), Some(("dir/a-b", 2)));
+    assert_eq!(split_share_name("a-block.0"), None);
+    assert_eq!(split_share_name("a-x-1.sf"), None);
+    assert_eq!(share_number("a-0-4.sf"), Some(4));
+    assert_eq!(share_number("plain"), None);
+}
@@ -0,0 +1,112 @@
+// ida-split and ida-combine, run on their own output
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use guff::*;
+
+mod common;
+use common::*;
+
+fn share(infile : &Path, share : usize) -> PathBuf {
+    PathBuf::from(format!("{}-block.{}", infile.display(), share))
+}
+
+fn split(args : &[&str], infile : &Path) {
+    let status = Command::new(env!("CARGO_BIN_EXE_ida-split"))
+	.args(args).arg(infile)
+	.status().unwrap();
+    assert!(status.success());
+}
+
+// The transform row comes last in the header, after "SF", the
+// version, options, k, w, and the chunk start and next (each a
+// length byte followed by that many bytes)
+fn header_row(share : &[u8]) -> Vec<u8> {
+    let k = share[4] as usize;
+    let mut at = 6;
+    at += 1 + share[at] as usize;
+    at += 1 + share[at] as usize;
+    share[at..at + k].to_vec()
+}
+
+// Share i's row is 1 / (x_i + y_j), where the default key has
+// y = 1 .. k and x = k + 1 .. k + n
+#[test]
+fn rows_are_cauchy() {
+    let dir = scratch("cauchy");
+    let infile = dir.join("sample");
+    fs::write(&infile, sample(1, 4000)).unwrap();
+    let (k, n) = (4, 7);
+    split(&["-k", "4", "-n", "7"], &infile);
+
+    let field = new_gf8(0x11b, 0x1b);
+    for i in 0..n {
+	let want : Vec<u8> = (0..k)
+	    .map(|j| field.inv(((k + 1 + i) ^ (1 + j)) as u8))
+	    .collect();
+	let data = fs::read(share(&infile, i + 1)).unwrap();
+	assert_eq!(header_row(&data), want, "share {}", i + 1);
+    }
+}
+
+// A final partial column is padded with zeros, so what the shares end
+// with depends only on the end of the input, and not on the block
+// before it (the default block is 8192 columns)
+#[test]
+fn final_column_is_zero_padded() {
+    let dir = scratch("padding");
+    let tail = sample(9, 6);
+    let mut ends = Vec::new();
+    for seed in 2..4 {
+	let infile = dir.join(format!("sample{}", seed));
+	let mut data = sample(seed, 8192 * 4);
+	data.extend_from_slice(&tail);
+	fs::write(&infile, &data).unwrap();
+	split(&["-k", "4", "-n", "7"], &infile);
+	let end : Vec<Vec<u8>> = (1..=7).map(|i| {
+	    let share = fs::read(share(&infile, i)).unwrap();
+	    share[share.len() - 2..].to_vec()
+	}).collect();
+	ends.push(end);
+    }
+    assert_eq!(ends[0], ends[1]);
+}
+
+fn combine(args : &[&str], outfile : &Path, shares : &[PathBuf]) {
+    let status = Command::new(env!("CARGO_BIN_EXE_ida-combine"))
+	.args(args).arg("-o").arg(outfile).args(shares)
+	.status().unwrap();
+    assert!(status.success());
+}
+
+// Combining stops at chunk_next, rather than writing out the padding
+// in the last column
+#[test]
+fn combine_writes_chunk_next_bytes() {
+    let dir = scratch("length");
+    let infile = dir.join("sample");
+    let data = sample(4, 4001);
+    fs::write(&infile, &data).unwrap();
+    split(&["-k", "4", "-n", "7"], &infile);
+    let outfile = dir.join("out");
+    combine(&[], &outfile, &[share(&infile, 2), share(&infile, 3),
+			     share(&infile, 5), share(&infile, 7)]);
+    assert!(fs::read(&outfile).unwrap() == data);
+}
+
+// The reference multiply reads its input colwise too, so combine -r
+// has to interleave the shares just as the SIMD path does
+#[test]
+fn combine_with_reference_multiply() {
+    let dir = scratch("reference");
+    let infile = dir.join("sample");
+    let data = sample(5, 4001);
+    fs::write(&infile, &data).unwrap();
+    split(&["-k", "4", "-n", "7"], &infile);
+    let outfile = dir.join("out");
+    combine(&["-r"], &outfile, &[share(&infile, 1), share(&infile, 4),
+				 share(&infile, 6), share(&infile, 7)]);
+    assert!(fs::read(&outfile).unwrap() == data);
+}
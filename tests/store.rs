@@ -0,0 +1,270 @@
+// split, combine, verify and repair against the different share stores
+
+use guff_sharefiles::combine::*;
+use guff_sharefiles::discover::*;
+use guff_sharefiles::error::*;
+use guff_sharefiles::output::*;
+use guff_sharefiles::split::*;
+use guff_sharefiles::store::*;
+
+use std::fs;
+use std::io::Cursor;
+use std::io::prelude::*;
+use std::path::PathBuf;
+use std::process::Command;
+
+mod common;
+use common::*;
+
+fn split_opts() -> SplitOptions {
+    SplitOptions { k : 3, n : 5, bufsize : 1000, ..SplitOptions::default() }
+}
+
+fn combine_opts() -> CombineOptions {
+    CombineOptions { bufsize : 1000, ..CombineOptions::default() }
+}
+
+#[test]
+fn memory_store() {
+    let data = sample(3, 20011);
+    let store = MemoryStore::new();
+    let shares = split_into(&mut Cursor::new(&data), data.len(), "sample",
+			    &store, &split_opts()).unwrap();
+    let names : Vec<&str> = shares.iter().map(|s| &s.name[..]).collect();
+    assert_eq!(names, ["sample-block.1", "sample-block.2", "sample-block.3",
+		       "sample-block.4", "sample-block.5"]);
+    assert_eq!(store.list().unwrap(), names);
+
+    let opts = combine_opts();
+    combine_in(&store, &names[2..], "out", &opts).unwrap();
+    assert!(store.get("out").unwrap() == data);
+
+    // lose two shares and rebuild them from the rest
+    let lost : Vec<Vec<u8>> = names[..2].iter()
+	.map(|name| store.remove(name).unwrap()).collect();
+    let rows : Vec<Vec<u8>> = shares[..2].iter()
+	.map(|s| s.header.xform_data.clone()).collect();
+    let outfiles : Vec<String> = names[..2].iter().map(|s| s.to_string())
+	.collect();
+    repair_in(&store, &names[2..], &rows, &outfiles, &opts).unwrap();
+    for (name, lost) in names.iter().zip(lost.iter()) {
+	assert!(store.get(name).as_ref() == Some(lost));
+    }
+
+    let checks = verify_in(&store, &names, &opts).unwrap();
+    assert!(checks.iter().all(|c| c.problem.is_none()));
+
+    // a changed byte in the data shows up
+    let mut damaged = store.get(names[4]).unwrap();
+    let last = damaged.len() - 1;
+    damaged[last] ^= 1;
+    store.insert(names[4], damaged);
+    let checks = verify_in(&store, &names, &opts).unwrap();
+    assert!(checks[4].problem.as_ref().unwrap().contains("mismatch"));
+}
+
+#[test]
+fn tar_store() {
+    let dir = scratch("tar");
+    let data = sample(3, 20011);
+    let archive = dir.join("shares.tar").to_string_lossy().into_owned();
+
+    // long names need the ustar prefix field
+    let long = "d".repeat(120);
+    let opts = SplitOptions { outdirs : vec![long.clone()], ..split_opts() };
+    let store = TarStore::create(&archive, OutputOptions::default()).unwrap();
+    let shares = split_into(&mut Cursor::new(&data), data.len(), "sample",
+			    &store, &opts).unwrap();
+    assert!(fs::metadata(&archive).is_err());
+    store.finish().unwrap();
+    assert!(fs::read_dir(&dir).unwrap().count() == 1);
+
+    let store = TarStore::open(&archive).unwrap();
+    let names : Vec<&str> = shares.iter().map(|s| &s.name[..]).collect();
+    assert_eq!(store.list().unwrap(), names);
+    assert!(names[0].starts_with(&long));
+    assert!(verify_in(&store, &names, &combine_opts()).unwrap()
+	    .iter().all(|c| c.problem.is_none()));
+
+    // decode straight from the archive
+    let mut set = ShareSet::open_in(&store, &names[1..4], true, None).unwrap();
+    let mut out = Vec::new();
+    set.decode(&combine_opts(), |plain| {
+	out.extend_from_slice(plain);
+	Ok(())
+    }).unwrap();
+    assert!(out == data);
+
+    // if tar is installed, check that it agrees
+    let listing = Command::new("tar").arg("-tf").arg(&archive).output();
+    if let Ok(listing) = listing {
+	assert!(listing.status.success());
+	let listed : Vec<String> = String::from_utf8_lossy(&listing.stdout)
+	    .lines().map(|s| s.to_string()).collect();
+	assert_eq!(listed, names);
+	let status = Command::new("tar").current_dir(&dir)
+	    .arg("-xf").arg(&archive).status().unwrap();
+	assert!(status.success());
+	let local = LocalStore::at(&dir.to_string_lossy(),
+				   OutputOptions::default());
+	combine_in(&local, &names[2..], "out", &combine_opts()).unwrap();
+	assert!(fs::read(dir.join("out")).unwrap() == data);
+    }
+
+    // an archive that's never finished doesn't appear
+    let store = TarStore::create(&archive, OutputOptions { force : true,
+							  sync : false })
+	.unwrap();
+    drop(store);
+    assert!(fs::read_dir(&dir).unwrap()
+	    .all(|e| !e.unwrap().file_name().to_string_lossy().starts_with('.')));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// A tar header block with the given name, type and raw size field
+fn tar_block(name : &str, kind : u8, size : &[u8]) -> Vec<u8> {
+    let mut header = vec![0u8; 512];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[124..124 + size.len()].copy_from_slice(size);
+    header[156] = kind;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[148..156].copy_from_slice(b"        ");
+    let sum : u32 = header.iter().map(|&b| b as u32).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+    header
+}
+
+#[test]
+fn hostile_tar_headers() {
+    let dir = scratch("hostile-tar");
+    let archive = dir.join("bad.tar").to_string_lossy().into_owned();
+    let open = |blocks : &[Vec<u8>]| {
+	fs::write(&archive, blocks.concat()).unwrap();
+	TarStore::open(&archive).map(|store| store.list().unwrap())
+    };
+    let end = vec![0u8; 1024];
+
+    // a GNU long name claiming (base-256) terabytes
+    let mut huge = vec![0x80u8; 12];
+    huge[1..].copy_from_slice(&[0, 0, 0, 0, 0, 0x10, 0, 0, 0, 0, 0]);
+    let err = open(&[tar_block("././@LongLink", b'L', &huge), end.clone()])
+	.err().unwrap();
+    assert_eq!(err.class, ErrorClass::BadHeader);
+
+    // a size that runs the offset past the end of a u64
+    let mut max = [0xffu8; 12];
+    max[..4].copy_from_slice(&[0x80, 0, 0, 0]);
+    let err = open(&[tar_block("big", b'0', &max), end.clone()]).err().unwrap();
+    assert_eq!(err.class, ErrorClass::BadHeader);
+
+    // a long name before a directory isn't used for the next file
+    let mut long = b"long-name".to_vec();
+    long.resize(512, 0);
+    let names = open(&[tar_block("././@LongLink", b'L', b"00000000011"), long,
+		       tar_block("dir/", b'5', b"00000000000"),
+		       tar_block("file", b'0', b"00000000000"), end]).unwrap();
+    assert_eq!(names, ["file"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn partial_archives() {
+    let dir = scratch("partial");
+    let data = sample(3, 20011);
+    let infile = dir.join("sample");
+    fs::write(&infile, &data).unwrap();
+    let all = dir.join("all.tar").to_string_lossy().into_owned();
+    let shares = split_to_tar(&infile.to_string_lossy(), &all, &split_opts())
+	.unwrap();
+    let all = TarStore::open(&all).unwrap();
+    let mut listed = all.list().unwrap();
+    assert_eq!(listed.pop().unwrap(), "sample.manifest.json");
+    assert_eq!(listed.len(), 5);
+
+    // each site has two shares, with share 2 at both
+    let mut sites = UnionStore::new();
+    for (site, picked) in [("a", [0, 1]), ("b", [1, 3])].iter() {
+	let path = dir.join(format!("{}.tar", site)).to_string_lossy()
+	    .into_owned();
+	let archive = TarStore::create(&path, OutputOptions::default()).unwrap();
+	for &i in picked.iter() {
+	    let (mut from, _) = all.open_read(&shares[i].name).unwrap();
+	    let mut to = archive.create_write(&shares[i].name).unwrap();
+	    std::io::copy(&mut from, &mut to).unwrap();
+	    to.commit().unwrap();
+	}
+	archive.finish().unwrap();
+	sites.add(site, Box::new(TarStore::open(&path).unwrap()));
+    }
+    assert_eq!(sites.list().unwrap(), ["a:sample-block.1", "a:sample-block.2",
+				       "b:sample-block.2", "b:sample-block.4"]);
+
+    let outfile = dir.join("out").to_string_lossy().into_owned();
+    let used = combine_store(&sites, "a b", None, &outfile, &combine_opts())
+	.unwrap();
+    let used : Vec<&str> = used.iter().map(|s| &s.name[..]).collect();
+    assert_eq!(used, ["a:sample-block.1", "a:sample-block.2", "b:sample-block.4"]);
+    assert!(fs::read(&outfile).unwrap() == data);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn mapped_files() {
+    let dir = scratch("mapped");
+    let data = sample(3, 20011);
+    let infile = dir.join("sample").to_string_lossy().into_owned();
+    fs::write(&infile, &data).unwrap();
+    let (plain, mapped) = (dir.join("plain"), dir.join("mapped"));
+    fs::create_dir(&plain).unwrap();
+    fs::create_dir(&mapped).unwrap();
+    let outdir = |dir : &PathBuf| vec![dir.to_string_lossy().into_owned()];
+
+    // the same shares either way
+    split_file(&infile, &SplitOptions { outdirs : outdir(&plain),
+					..split_opts() }).unwrap();
+    let shares = split_file(&infile, &SplitOptions {
+	outdirs : outdir(&mapped), mmap : true, ..split_opts() }).unwrap();
+    for share in shares.iter() {
+	let name = PathBuf::from(&share.name);
+	assert_eq!(fs::read(&name).unwrap(),
+		   fs::read(plain.join(name.file_name().unwrap())).unwrap());
+    }
+
+    let names : Vec<&str> = shares.iter().map(|s| &s.name[..]).collect();
+    let outfile = dir.join("out").to_string_lossy().into_owned();
+    let opts = CombineOptions { mmap : true, ..combine_opts() };
+    combine_files(&names[1..4], &outfile, &opts).unwrap();
+    assert!(fs::read(&outfile).unwrap() == data);
+
+    // and decoding straight from the mappings
+    let store = LocalStore::new(OutputOptions::default()).with_mmap(true);
+    let mut set = ShareSet::open_in(&store, &names[..3], true, None).unwrap();
+    let mut output = Vec::new();
+    set.decode(&opts, |block| { output.extend_from_slice(block); Ok(()) })
+	.unwrap();
+    assert!(output == data);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn mapped_output() {
+    let dir = scratch("mapped-output");
+    let store = LocalStore::at(&dir.to_string_lossy(), OutputOptions::default())
+	.with_mmap(true);
+
+    // less than promised is cut short, more is refused
+    let mut out = store.create_sized("short", 10).unwrap();
+    out.write_all(b"abc").unwrap();
+    out.commit().unwrap();
+    assert_eq!(fs::read(dir.join("short")).unwrap(), b"abc");
+    let mut out = store.create_sized("long", 2).unwrap();
+    assert!(out.write_all(b"abc").is_err());
+    drop(out);
+    assert!(fs::metadata(dir.join("long")).is_err());
+
+    let mut out = store.create_sized("empty", 0).unwrap();
+    out.write_all(b"").unwrap();
+    out.commit().unwrap();
+    assert_eq!(fs::read(dir.join("empty")).unwrap(), b"");
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,44 @@
+// Buffer size tuning and the config file it saves to
+
+use guff_sharefiles::split::*;
+use guff_sharefiles::tune::*;
+
+#[test]
+fn timings_cover_the_candidates() {
+    let sample : Vec<u8> = (0..5001u32).map(|i| (i * 7) as u8).collect();
+    let opts = SplitOptions { k : 3, n : 5, ..SplitOptions::default() };
+    let timings = tune_split(&sample, &opts).unwrap();
+    let sizes : Vec<usize> = timings.iter().map(|t| t.bufsize).collect();
+    assert_eq!(sizes, CANDIDATES);
+    assert!(timings.iter().all(|t| t.rate > 0.0));
+    assert!(CANDIDATES.contains(&fastest(&timings)));
+
+    // too little to make a single column is still timed
+    assert_eq!(tune_split(&[1], &opts).unwrap().len(), CANDIDATES.len());
+}
+
+#[test]
+fn config_round_trip() {
+    let mut config = TuneConfig::new();
+    config.set_bufsize(TuneFor::Split { k : 8, n : 16 }, 4096);
+    config.set_bufsize(TuneFor::Combine { k : 8 }, 32768);
+    config.set_bufsize(TuneFor::Split { k : 8, n : 16 }, 16384);
+
+    let path = std::env::temp_dir()
+	.join(format!("guff-sharefiles-tune-{}", std::process::id()))
+	.join("ida").join("config.json");
+    assert!(TuneConfig::load(&path).unwrap().bufsizes.is_empty());
+    config.save(&path).unwrap();
+    let loaded = TuneConfig::load(&path).unwrap();
+    assert_eq!(loaded.bufsize(TuneFor::Split { k : 8, n : 16 }), Some(16384));
+    assert_eq!(loaded.bufsize(TuneFor::Combine { k : 8 }), Some(32768));
+    assert_eq!(loaded.bufsize(TuneFor::Split { k : 8, n : 12 }), None);
+    assert_eq!(loaded.bufsizes.len(), 2);
+    std::fs::remove_dir_all(path.parent().unwrap().parent().unwrap()).unwrap();
+
+    for bad in [r#"{"config": 2}"#, r#"{"config": 1, "bufsize": [{"op": "x", "k": 1, "bufsize": 1}]}"#,
+		r#"{"config": 1, "bufsize": [{"op": "split", "k": 1, "bufsize": 1}]}"#,
+		r#"{"config": 1, "bufsize": [{"op": "combine", "k": 1, "bufsize": 0}]}"#].iter() {
+	assert!(TuneConfig::parse("test", bad).is_err(), "{} parsed", bad);
+    }
+}
@@ -0,0 +1,140 @@
+// zfec import/export: round trips through our own encoder and
+// decoder (starting from the sharefile corpus in tests/data/perl), and
+// byte-for-byte checks against shares made by zfec itself
+// (tests/data/zfec)
+
+use guff_sharefiles::combine::*;
+use guff_sharefiles::output::*;
+use guff_sharefiles::store::*;
+use guff_sharefiles::zfec::*;
+
+use std::fs::{self, File};
+use std::path::PathBuf;
+
+mod common;
+use common::*;
+
+fn data(dir : &str, file : &str) -> String {
+    [env!("CARGO_MANIFEST_DIR"), "tests", "data", dir, file]
+	.iter().collect::<PathBuf>().to_str().unwrap().to_string()
+}
+
+fn corpus(file : &str) -> String {
+    data("perl", file)
+}
+
+#[test]
+fn header_round_trip() {
+    let dir = scratch("zfec-header");
+    let file = dir.join("header");
+    for &(m, k) in [(1, 1), (3, 2), (8, 3), (10, 7), (256, 255)].iter() {
+	for &pad in [0, k - 1].iter() {
+	    for &sharenum in [0, m - 1].iter() {
+		let header = ZfecHeader { m, k, pad, sharenum };
+		let wrote = write_zfec_header(&mut File::create(&file).unwrap(),
+					      &header).unwrap();
+		assert_eq!(wrote, header.header_len());
+		let read = read_zfec_header(&mut File::open(&file).unwrap())
+		    .unwrap();
+		assert_eq!((read.m, read.k, read.pad, read.sharenum),
+			   (m, k, pad, sharenum));
+	    }
+	}
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn share_names() {
+    assert_eq!(zfec_share_name("foo", 2, 8), "foo.2_8.fec");
+    assert_eq!(zfec_share_name("foo", 2, 10), "foo.02_10.fec");
+}
+
+#[test]
+fn convert_both_ways() {
+    let dir = scratch("zfec-convert");
+    let original = fs::read(corpus("sample")).unwrap();
+    let (k, m) = (3, 5);
+
+    // sharefiles -> zfec
+    let sharefiles : Vec<_> = [5, 0, 3, 6].iter()
+	.map(|i| corpus(&format!("sample-0-{}.sf", i))).collect();
+    let sharefiles : Vec<&str> = sharefiles.iter().map(|s| &s[..]).collect();
+    let prefix = dir.join("z").to_str().unwrap().to_string();
+    let opts = OutputOptions::default();
+    let zfec = sharefiles_to_zfec(&sharefiles, &prefix, k, m, &opts).unwrap();
+    assert_eq!(zfec.len(), m);
+
+    // zfec shares are systematic: the first k hold the first k
+    // 4096-byte stripes of the first segment as they are
+    let header_len = ZfecHeader { m, k, pad : 0, sharenum : 0 }.header_len();
+    for (i, name) in zfec.iter().take(k).enumerate() {
+	let share = fs::read(name).unwrap();
+	assert_eq!(&share[header_len..][..4096], &original[i * 4096..][..4096]);
+    }
+
+    // zfec -> sharefiles, from the last k zfec shares
+    let zfec_in : Vec<&str> = zfec[m - k..].iter().map(|s| &s[..]).collect();
+    let outfiles : Vec<String> = (1..=4)
+	.map(|i| dir.join(format!("s-block.{}", i)).to_str().unwrap()
+	     .to_string())
+	.collect();
+    let size = zfec_to_sharefiles(&zfec_in, outfiles.clone(),
+				  &sharefile_transform(k, 4, false), &opts).unwrap();
+    assert_eq!(size, original.len());
+
+    // and back again gives identical zfec shares
+    let again : Vec<&str> = outfiles[1..].iter().map(|s| &s[..]).collect();
+    let prefix2 = dir.join("z2").to_str().unwrap().to_string();
+    let zfec2 = sharefiles_to_zfec(&again, &prefix2, k, m, &opts).unwrap();
+    for (a, b) in zfec.iter().zip(zfec2.iter()) {
+	assert_eq!(fs::read(a).unwrap(), fs::read(b).unwrap());
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// Shares from zfec itself convert to sharefiles that decode to the
+// original, and converting those back gives zfec's shares exactly.
+// Ignored until the fixture is committed; after that, drop the ignore.
+#[test]
+#[ignore = "zfec fixture not yet generated (see tests/data/zfec/README)"]
+fn genuine_zfec_fixture() {
+    let (k, m) = (3, 5);
+    let fixture : Vec<String> = (0..m)
+	.map(|i| data("zfec", &zfec_share_name("sample", i, m))).collect();
+    for file in fixture.iter().chain([data("zfec", "sample")].iter()) {
+	assert!(fs::metadata(file).is_ok(),
+		"{} missing; run mkfixture.sh (see tests/data/zfec/README)", file);
+    }
+    let original = fs::read(data("zfec", "sample")).unwrap();
+    let dir = scratch("zfec-fixture");
+    let opts = OutputOptions::default();
+
+    // zfec -> sharefiles, from a mix of data and parity shares
+    let zfec_in : Vec<&str> = [4, 1, 3].iter().map(|&i| &fixture[i][..])
+	.collect();
+    let outfiles : Vec<String> = (1..=m)
+	.map(|i| dir.join(format!("s-block.{}", i)).to_str().unwrap()
+	     .to_string())
+	.collect();
+    let size = zfec_to_sharefiles(&zfec_in, outfiles.clone(),
+				  &sharefile_transform(k, m, false), &opts)
+	.unwrap();
+    assert_eq!(size, original.len());
+
+    // sharefiles -> zfec gives back zfec's own files, byte for byte
+    let shares : Vec<&str> = outfiles[2..].iter().map(|s| &s[..]).collect();
+    let prefix = dir.join("z").to_str().unwrap().to_string();
+    let ours = sharefiles_to_zfec(&shares, &prefix, k, m, &opts).unwrap();
+    for (theirs, ours) in fixture.iter().zip(ours.iter()) {
+	assert!(fs::read(theirs).unwrap() == fs::read(ours).unwrap(),
+		"{} differs", theirs);
+    }
+
+    // and the sharefiles hold the original data
+    let out = dir.join("out").to_str().unwrap().to_string();
+    combine_in(&LocalStore::new(opts), &shares, &out,
+	       &CombineOptions::default()).unwrap();
+    assert!(fs::read(&out).unwrap() == original);
+    fs::remove_dir_all(&dir).unwrap();
+}